@@ -0,0 +1,131 @@
+use crate::connected_udp::{canonicalize, Options};
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+/// An unconnected [`UdpSocket`] that only accepts datagrams from an
+/// allowlist of peers.
+///
+/// This is the multi-peer counterpart to [`ConnectedUdpSocket`]'s
+/// single-peer filtering, for servers that share one socket between several
+/// peers. Unlike a connected socket, the filtering happens in userspace:
+/// datagrams from other sources are still received by the OS, then
+/// discarded. IPv4-mapped addresses are compared in their plain IPv4 form.
+///
+/// [`ConnectedUdpSocket`]: crate::ConnectedUdpSocket
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::AllowlistUdp;
+/// use std::net::UdpSocket;
+///
+/// fn main() -> std::io::Result<()> {
+///     let server = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///
+///     let server = AllowlistUdp::new(server, [client.local_addr()?]);
+///     client.send_to(b"hello", server.as_ref().local_addr()?)?;
+///
+///     let mut buf = [0; 32];
+///     let (n, from) = server.recv_from_allowed(&mut buf)?;
+///     assert_eq!(&buf[..n], b"hello");
+///     assert_eq!(from, client.local_addr()?);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AllowlistUdp {
+    socket: UdpSocket,
+    allowed: HashSet<SocketAddr>,
+}
+
+impl AllowlistUdp {
+    /// Wraps `socket`, accepting datagrams only from the `allowed` peers.
+    pub fn new(socket: UdpSocket, allowed: impl IntoIterator<Item = SocketAddr>) -> Self {
+        Self {
+            socket,
+            allowed: allowed.into_iter().map(normalize).collect(),
+        }
+    }
+
+    /// Adds `peer` to the allowlist.
+    pub fn allow(&mut self, peer: SocketAddr) {
+        self.allowed.insert(normalize(peer));
+    }
+
+    /// Removes `peer` from the allowlist, returning whether it was present.
+    pub fn disallow(&mut self, peer: SocketAddr) -> bool {
+        self.allowed.remove(&normalize(peer))
+    }
+
+    /// Returns whether datagrams from `peer` are accepted.
+    pub fn is_allowed(&self, peer: SocketAddr) -> bool {
+        self.allowed.contains(&normalize(peer))
+    }
+
+    /// Receives the next datagram from an allowed peer, returning the number
+    /// of bytes read and the peer's address.
+    ///
+    /// Datagrams from peers that aren't on the allowlist are discarded.
+    pub fn recv_from_allowed(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            let (n, from) = self.socket.recv_from(buf)?;
+            if self.is_allowed(from) {
+                return Ok((n, normalize(from)));
+            }
+        }
+    }
+}
+
+impl AsRef<UdpSocket> for AllowlistUdp {
+    fn as_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+/// Puts `addr` into the form allowlist entries are compared in.
+fn normalize(addr: SocketAddr) -> SocketAddr {
+    canonicalize(addr, &Options::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recv_from_allowed_drops_others() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let allowed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let denied = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let server = AllowlistUdp::new(server, [allowed.local_addr().unwrap()]);
+        assert!(!server.is_allowed(denied.local_addr().unwrap()));
+
+        denied.send_to(b"denied", server_addr).unwrap();
+        allowed.send_to(b"allowed", server_addr).unwrap();
+
+        let mut buf = [0u8; 32];
+        let (n, from) = server.recv_from_allowed(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"allowed");
+        assert_eq!(from, allowed.local_addr().unwrap());
+    }
+
+    #[test]
+    fn allow_and_disallow() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:4000".parse().unwrap();
+
+        let mut server = AllowlistUdp::new(server, []);
+        assert!(!server.is_allowed(peer));
+
+        server.allow(mapped);
+        assert!(server.is_allowed(peer));
+        assert!(server.disallow(peer));
+        assert!(!server.is_allowed(mapped));
+    }
+}
@@ -0,0 +1,51 @@
+/// Types that can be viewed as a byte slice, e.g. fixed-layout protocol
+/// headers.
+///
+/// For a `#[repr(C)]` struct without padding, the view is usually the
+/// struct's own memory:
+///
+/// ```
+/// use connected_udp::AsBytes;
+///
+/// #[repr(C)]
+/// struct Header {
+///     kind: u8,
+///     flags: u8,
+///     len: [u8; 2],
+/// }
+///
+/// impl AsBytes for Header {
+///     fn as_bytes(&self) -> &[u8] {
+///         // SAFETY: `Header` is `repr(C)` with no padding, so all of its
+///         // bytes are initialized.
+///         unsafe {
+///             std::slice::from_raw_parts(
+///                 (self as *const Self).cast::<u8>(),
+///                 std::mem::size_of::<Self>(),
+///             )
+///         }
+///     }
+/// }
+/// ```
+pub trait AsBytes {
+    /// Returns the value's bytes as they should appear on the wire.
+    fn as_bytes(&self) -> &[u8];
+}
+
+impl AsBytes for [u8] {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<const N: usize> AsBytes for [u8; N] {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsBytes for Vec<u8> {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+}
@@ -0,0 +1,459 @@
+use crate::connected_udp::Options;
+use crate::stats::OnDrop;
+use crate::{ByteOrder, ConnectedUdpSocket, ConnectedUdpStats, EmptyDatagramPolicy};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+/// Configures how a [`ConnectedUdpSocket`] is set up before it's handed back.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::ConnectedUdpSocket;
+/// use std::net::UdpSocket;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let host_addr = host.local_addr()?;
+///
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::builder()
+///         .handshake(b"hello".to_vec())
+///         .connect(client, host_addr)?;
+///
+///     let mut buf = [0; 32];
+///     let n = host.recv(&mut buf)?;
+///     assert_eq!(&buf[..n], b"hello");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Builder {
+    options: Options,
+    handshake: Option<Vec<u8>>,
+    check_route: bool,
+    min_recv_buffer: Option<usize>,
+    on_drop: Option<OnDrop>,
+}
+
+impl Builder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends `payload` to the peer as soon as the socket is connected, before
+    /// the socket is returned.
+    ///
+    /// This is useful for protocols that require a hello message, since no
+    /// caller can forget to send it. If sending the handshake fails, building
+    /// the socket fails too.
+    pub fn handshake(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.handshake = Some(payload.into());
+        self
+    }
+
+    /// Sets whether connecting sends an empty datagram to the peer, so that
+    /// routing errors surface right away instead of on the first real send.
+    ///
+    /// UDP's `connect` only checks that a route exists, not that it leads
+    /// anywhere, so errors like [`io::ErrorKind::NetworkUnreachable`] or
+    /// [`io::ErrorKind::HostUnreachable`] (e.g. from a blackhole or
+    /// prohibit route) may only be reported when something is sent. With
+    /// this enabled, [`connect`](Self::connect) returns them directly. The
+    /// peer receives the empty datagram, so it must be prepared to ignore
+    /// one. It's sent before any [`handshake`](Self::handshake).
+    ///
+    /// Defaults to `false`, keeping connecting free of network traffic.
+    pub fn check_route(mut self, check: bool) -> Self {
+        self.check_route = check;
+        self
+    }
+
+    /// Requires the socket's receive buffer (`SO_RCVBUF`) to hold at least
+    /// `bytes`, making the buffer size a hard precondition rather than a
+    /// hint.
+    ///
+    /// [`connect`](Self::connect) requests `bytes` and reads back what the OS
+    /// granted. The OS silently clamps the size to its configured maximum
+    /// (e.g. `net.core.rmem_max` on Linux), which otherwise only shows up as
+    /// dropped datagrams under load; with this set, connecting fails with an
+    /// error of kind [`io::ErrorKind::QuotaExceeded`] instead.
+    pub fn min_recv_buffer(mut self, bytes: usize) -> Self {
+        self.min_recv_buffer = Some(bytes);
+        self
+    }
+
+    /// Calls `hook` with the socket's final [`ConnectedUdpStats`] when it's
+    /// dropped, e.g. to track down sockets that send far less than
+    /// expected.
+    ///
+    /// The hook runs exactly once, when the socket and all of its
+    /// [`try_clone`](ConnectedUdpSocket::try_clone)s are gone (including
+    /// clones held by background threads such as a watchdog). Decomposing the
+    /// socket into its [`UdpSocket`] counts as dropping it. If connecting
+    /// fails, the hook never runs.
+    pub fn on_drop(mut self, hook: impl FnOnce(&ConnectedUdpStats) + Send + 'static) -> Self {
+        self.on_drop = Some(OnDrop(Box::new(hook)));
+        self
+    }
+
+    /// Sets whether IPv4-mapped peer addresses (e.g. `::ffff:127.0.0.1`) are
+    /// cached in their plain IPv4 form.
+    ///
+    /// Defaults to `true`, so [`ConnectedUdpSocket::peer_addr`] reports IPv4
+    /// peers consistently regardless of how a dual-stack socket was
+    /// connected. With `false`, a dual-stack socket reports its IPv4 peers in
+    /// the mapped form the OS uses.
+    pub fn unmap_ipv4(mut self, unmap: bool) -> Self {
+        self.options.unmap_ipv4 = unmap;
+        self
+    }
+
+    /// Sets how receiving a zero-length datagram is reported.
+    ///
+    /// Defaults to [`EmptyDatagramPolicy::ReturnZero`], which returns `Ok(0)`
+    /// like [`UdpSocket::recv`].
+    pub fn treat_empty_datagram_as(mut self, policy: EmptyDatagramPolicy) -> Self {
+        self.options.empty_datagrams = policy;
+        self
+    }
+
+    /// Sets whether datagrams are matched to the peer by IP address alone,
+    /// ignoring the port.
+    ///
+    /// This is for peers behind a NAT that keeps their IP address but may
+    /// rebind them to a new port. It affects
+    /// [`ConnectedUdpSocket::recv_verified`], which then accepts datagrams
+    /// from any port on the peer's IP, and
+    /// [`ConnectedUdpSocket::recv_tracking_peer`], which then doesn't report
+    /// a port change as a new peer. The OS-level connection still filters by
+    /// the full address, so plain receives only see the current port.
+    ///
+    /// Defaults to `false`, matching the full address.
+    pub fn match_peer_by_ip_only(mut self, ip_only: bool) -> Self {
+        self.options.match_peer_by_ip_only = ip_only;
+        self
+    }
+
+    /// Sets the byte order used by the numeric helpers without an endianness
+    /// suffix, such as [`ConnectedUdpSocket::send_u32`] and
+    /// [`ConnectedUdpSocket::recv_u32`].
+    ///
+    /// Defaults to [`ByteOrder::Big`] (network byte order). The `_be`
+    /// helpers always use big-endian regardless of this setting.
+    pub fn byte_order(mut self, order: ByteOrder) -> Self {
+        self.options.byte_order = order;
+        self
+    }
+
+    /// Scrambles payloads by XORing them with `key`, repeated from the start
+    /// of each datagram.
+    ///
+    /// **This is not encryption.** The key is trivially recovered from any
+    /// datagram with a guessable payload, and nothing detects tampering. It
+    /// only keeps payloads from being readable at a glance, e.g. to get past
+    /// naive deep packet inspection on a lab network. Use
+    /// [`SecureSocket`](crate::SecureSocket) to protect traffic.
+    ///
+    /// [`ConnectedUdpSocket::send`], [`send_vectored`] and
+    /// [`ConnectedUdpSocket::recv`] apply the key, as do the helpers built on
+    /// them and the Linux-specific sends and receives such as `send_gso`,
+    /// `recv_gro` and `recv_with_ttl`. With GSO and GRO, each segment is
+    /// obfuscated as a datagram of its own. Both ends must use the same key.
+    /// An empty key turns obfuscation off, which is the default.
+    ///
+    /// [`send_vectored`]: ConnectedUdpSocket::send_vectored
+    pub fn obfuscate(mut self, key: Vec<u8>) -> Self {
+        self.options.obfuscation_key = (!key.is_empty()).then(|| key.into());
+        self
+    }
+
+    /// Connects `socket` to `peer` and applies this configuration.
+    ///
+    /// See [`ConnectedUdpSocket::connect`] for details on connecting.
+    pub fn connect(self, socket: UdpSocket, peer: SocketAddr) -> io::Result<ConnectedUdpSocket> {
+        let conn = ConnectedUdpSocket::connect_with(socket, peer, self.options)?;
+        if let Some(min) = self.min_recv_buffer {
+            // `setsockopt` takes an `int`, so anything larger can't be requested.
+            conn.set_recv_buffer_size(min.min(i32::MAX as usize))?;
+            let granted = conn.recv_buffer_size()?;
+            if granted < min {
+                return Err(io::Error::new(
+                    io::ErrorKind::QuotaExceeded,
+                    format!("receive buffer of {granted} bytes is smaller than the required {min}"),
+                ));
+            }
+        }
+        if self.check_route {
+            conn.send(&[])?;
+        }
+        if let Some(handshake) = &self.handshake {
+            conn.send(handshake)?;
+        }
+        if let Some(hook) = self.on_drop {
+            conn.stats_counters().set_on_drop(hook);
+        }
+        Ok(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::IoSlice;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn handshake_is_sent_on_connect() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = Builder::new()
+            .handshake(b"hello".to_vec())
+            .connect(sender, recv_addr)
+            .unwrap();
+
+        let mut buf = [0u8; 32];
+        let (n, from) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert_eq!(from, sender_conn.local_addr().unwrap());
+    }
+
+    #[test]
+    fn no_handshake_by_default() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        Builder::new().connect(sender, recv_addr).unwrap();
+
+        let mut buf = [0u8; 32];
+        let err = receiver.recv_from(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn check_route_sends_empty_datagram() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        Builder::new()
+            .check_route(true)
+            .handshake(b"hello".to_vec())
+            .connect(sender, recv_addr)
+            .unwrap();
+
+        let mut buf = [0u8; 32];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 0);
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    #[test]
+    fn check_route_surfaces_send_errors() {
+        // A send to a closed port queues the ICMP error on the socket, so
+        // connecting to it again succeeds but the next send fails.
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let failing_socket = || {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.connect(closed_addr).unwrap();
+            socket.send(b"knock").unwrap();
+            thread::sleep(Duration::from_millis(20));
+            socket
+        };
+
+        // Not every platform reports the error on the next send.
+        let plain = failing_socket();
+        plain.connect(closed_addr).unwrap();
+        let Err(expected) = plain.send(&[]) else {
+            return;
+        };
+
+        let unchecked = Builder::new()
+            .check_route(false)
+            .connect(failing_socket(), closed_addr);
+        assert!(unchecked.is_ok());
+        let checked = Builder::new()
+            .check_route(true)
+            .connect(failing_socket(), closed_addr);
+        assert_eq!(checked.unwrap_err().kind(), expected.kind());
+    }
+
+    #[test]
+    fn min_recv_buffer_granted() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = Builder::new()
+            .min_recv_buffer(16 * 1024)
+            .connect(sender, recv_addr)
+            .unwrap();
+        assert!(sender_conn.recv_buffer_size().unwrap() >= 16 * 1024);
+    }
+
+    #[test]
+    fn min_recv_buffer_refused() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = Builder::new()
+            .min_recv_buffer(usize::MAX)
+            .connect(sender, recv_addr)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::QuotaExceeded);
+    }
+
+    #[test]
+    fn on_drop_reports_final_stats() {
+        use std::sync::mpsc;
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = Builder::new()
+            .on_drop(move |stats| tx.send(*stats).unwrap())
+            .connect(sender, recv_addr)
+            .unwrap();
+        let clone = sender_conn.try_clone().unwrap();
+
+        sender_conn.send(b"hello").unwrap();
+        drop(sender_conn);
+        assert!(rx.try_recv().is_err());
+
+        clone.send(b"bye").unwrap();
+        drop(clone);
+        let stats = rx.try_recv().unwrap();
+        assert_eq!(stats.datagrams_sent, 2);
+        assert_eq!(stats.bytes_sent, 8);
+        // The hook was consumed, so it can't run again.
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn numeric_helpers_use_configured_byte_order() {
+        for (order, wire) in [
+            (ByteOrder::Big, [0xde, 0xad, 0xbe, 0xef]),
+            (ByteOrder::Little, [0xef, 0xbe, 0xad, 0xde]),
+        ] {
+            let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let a_addr = a.local_addr().unwrap();
+            let b_addr = b.local_addr().unwrap();
+            let a_conn = Builder::new().byte_order(order).connect(a, b_addr).unwrap();
+            let b_conn = Builder::new().byte_order(order).connect(b, a_addr).unwrap();
+
+            a_conn.send_u32(0xdead_beef).unwrap();
+            let mut buf = [0u8; 4];
+            b_conn.as_ref().peek(&mut buf).unwrap();
+            assert_eq!(buf, wire);
+            assert_eq!(b_conn.recv_u32().unwrap(), 0xdead_beef);
+
+            b_conn.send_u16(0xbeef).unwrap();
+            assert_eq!(a_conn.recv_u16().unwrap(), 0xbeef);
+        }
+    }
+
+    #[test]
+    fn obfuscated_sockets_round_trip() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b_addr = b.local_addr().unwrap();
+        let key = b"lab key".to_vec();
+
+        let a_conn = Builder::new()
+            .obfuscate(key.clone())
+            .connect(a, b_addr)
+            .unwrap();
+        let a_addr = a_conn.local_addr().unwrap();
+        let b_conn = Builder::new().obfuscate(key).connect(b, a_addr).unwrap();
+
+        let payload = b"the quick brown fox";
+        a_conn.send(payload).unwrap();
+        let mut buf = [0u8; 32];
+        let n = b_conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], payload);
+
+        b_conn
+            .send_vectored(&[IoSlice::new(b"the quick "), IoSlice::new(b"brown fox")])
+            .unwrap();
+        let n = a_conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], payload);
+    }
+
+    #[test]
+    fn obfuscated_receive_helpers_unscramble() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b_addr = b.local_addr().unwrap();
+        let key = b"lab key".to_vec();
+
+        let a_conn = Builder::new()
+            .obfuscate(key.clone())
+            .connect(a, b_addr)
+            .unwrap();
+        let a_addr = a_conn.local_addr().unwrap();
+        let mut b_conn = Builder::new().obfuscate(key).connect(b, a_addr).unwrap();
+
+        a_conn.send(b"ping").unwrap();
+        let mut buf = [0u8; 8];
+        let cancel = AtomicBool::new(false);
+        let n = b_conn.recv_cancellable(&mut buf, &cancel).unwrap();
+        assert_eq!(n, Some(4));
+        assert_eq!(&buf[..4], b"ping");
+
+        a_conn.send(b"pong").unwrap();
+        let n = b_conn.recv_verified(&mut buf, 0).unwrap();
+        assert_eq!(&buf[..n], b"pong");
+
+        a_conn.send(b"any").unwrap();
+        let (n, from) = b_conn.recv_from_any(&mut buf).unwrap();
+        assert_eq!((&buf[..n], from), (&b"any"[..], a_addr));
+
+        a_conn.send(b"track").unwrap();
+        let (n, changed) = b_conn.recv_tracking_peer(&mut buf).unwrap();
+        assert_eq!((&buf[..n], changed), (&b"track"[..], false));
+
+        a_conn.send(b"uninit").unwrap();
+        let mut uninit = [std::mem::MaybeUninit::<u8>::uninit(); 8];
+        let n = b_conn.recv_uninit(&mut uninit).unwrap();
+        // SAFETY: `recv_uninit` initialized the first `n` bytes.
+        let data = unsafe { std::slice::from_raw_parts(uninit.as_ptr().cast::<u8>(), n) };
+        assert_eq!(data, b"uninit");
+    }
+
+    #[test]
+    fn obfuscated_payload_is_scrambled_on_the_wire() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = Builder::new()
+            .obfuscate(b"lab key".to_vec())
+            .connect(sender, recv_addr)
+            .unwrap();
+
+        let payload = b"the quick brown fox";
+        sender_conn.send(payload).unwrap();
+        let mut buf = [0u8; 32];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(n, payload.len());
+        assert_ne!(&buf[..n], payload);
+
+        let key = b"lab key".iter().cycle();
+        let unscrambled: Vec<u8> = buf[..n].iter().zip(key).map(|(b, k)| b ^ k).collect();
+        assert_eq!(unscrambled, payload);
+    }
+}
@@ -0,0 +1,422 @@
+use crate::connected_udp::{remaining, timed_out};
+use crate::ConnectedUdpSocket;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// The size of the header in front of every chunk sent by
+/// [`ConnectedUdpSocket::send_chunked`].
+pub(crate) const HEADER_LEN: usize = 8;
+
+/// The largest chunk [`ConnectedUdpSocket::recv_reassemble`] can receive.
+const MAX_CHUNK_LEN: usize = 64 * 1024;
+
+/// What each chunk slot of a partially received message costs, counted
+/// against the buffering limit before any payload arrives.
+const SLOT_LEN: usize = mem::size_of::<Option<Vec<u8>>>();
+
+/// The id given to the next message sent in chunks.
+pub(crate) static NEXT_MESSAGE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// The header in front of every chunk: a message id (4 bytes), the chunk's
+/// index (2 bytes), and the message's total number of chunks (2 bytes), all
+/// big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChunkHeader {
+    pub(crate) message_id: u32,
+    pub(crate) index: u16,
+    pub(crate) total: u16,
+}
+
+impl ChunkHeader {
+    pub(crate) fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(&self.message_id.to_be_bytes());
+        header[4..6].copy_from_slice(&self.index.to_be_bytes());
+        header[6..].copy_from_slice(&self.total.to_be_bytes());
+        header
+    }
+
+    /// Splits a chunk into its header and payload, or returns `None` if it's
+    /// too short or its header is inconsistent.
+    pub(crate) fn decode(chunk: &[u8]) -> Option<(Self, &[u8])> {
+        let (header, payload) = chunk.split_first_chunk::<HEADER_LEN>()?;
+        let header = Self {
+            message_id: u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+            index: u16::from_be_bytes([header[4], header[5]]),
+            total: u16::from_be_bytes([header[6], header[7]]),
+        };
+        if header.index >= header.total {
+            return None;
+        }
+        Some((header, payload))
+    }
+}
+
+/// The chunks of one message received so far.
+#[derive(Debug)]
+struct Partial {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+impl Partial {
+    fn new(total: u16) -> Self {
+        Self {
+            chunks: vec![None; usize::from(total)],
+            received: 0,
+        }
+    }
+}
+
+impl ConnectedUdpSocket {
+    /// Sends `msg` as a series of datagrams carrying at most `chunk_payload`
+    /// bytes of it each, returning the number of chunks sent.
+    ///
+    /// This is application-level fragmentation for messages that don't fit
+    /// in one datagram. Each chunk starts with an 8-byte header holding a
+    /// message id, the chunk's index, and the total number of chunks, so the
+    /// peer can put the message back together even if the chunks arrive out
+    /// of order. An empty message is sent as a single empty chunk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if
+    /// `chunk_payload` is zero or `msg` would need more than 65535 chunks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let chunks = conn_client.send_chunked(&[7; 3000], 1200).expect("couldn't send message");
+    ///  assert_eq!(chunks, 3);
+    /// # }
+    /// ```
+    pub fn send_chunked(&self, msg: &[u8], chunk_payload: usize) -> io::Result<usize> {
+        if chunk_payload == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "chunk payload size must be non-zero",
+            ));
+        }
+        let total = msg.len().div_ceil(chunk_payload).max(1);
+        let total = u16::try_from(total).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message needs more than 65535 chunks",
+            )
+        })?;
+
+        let message_id = NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+        for index in 0..total {
+            let start = usize::from(index) * chunk_payload;
+            let end = msg.len().min(start + chunk_payload);
+            let header = ChunkHeader {
+                message_id,
+                index,
+                total,
+            };
+            self.send_with_header(&header.encode(), &msg[start..end])?;
+        }
+        Ok(usize::from(total))
+    }
+
+    /// Receives chunks sent by [`send_chunked`](Self::send_chunked) until a
+    /// whole message has arrived, and returns it.
+    ///
+    /// Chunks of different messages may be interleaved; the first message to
+    /// complete is returned and chunks of any others received along the way
+    /// are dropped. At most `max_message_size` bytes are buffered in total,
+    /// counting both payload and a slot of a few dozen bytes for every chunk
+    /// of each message seen, so a peer can't make this allocate without
+    /// bound. Datagrams that aren't valid chunks, and chunks that disagree
+    /// with earlier ones about their message's length, are discarded. The
+    /// socket's read timeout is restored before returning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::TimedOut`] if no message is
+    /// complete within `timeout`, e.g. because a chunk was lost. Returns an
+    /// error of kind [`io::ErrorKind::InvalidData`] if a chunk is received
+    /// twice, or more than `max_message_size` bytes would need to be
+    /// buffered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() {
+    ///  let a = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to address");
+    ///  let b = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to address");
+    ///  let b_addr = b.local_addr().expect("couldn't retrieve address");
+    ///  let a_conn = ConnectedUdpSocket::connect(a, b_addr).expect("couldn't connect");
+    ///  let b_conn = ConnectedUdpSocket::connect(b, a_conn.local_addr().unwrap())
+    ///      .expect("couldn't connect");
+    ///
+    ///  a_conn.send_chunked(&[7; 3000], 1200).expect("couldn't send message");
+    ///  let msg = b_conn
+    ///      .recv_reassemble(Duration::from_secs(1), 4096)
+    ///      .expect("couldn't receive message");
+    ///  assert_eq!(msg, [7; 3000]);
+    /// # }
+    /// ```
+    pub fn recv_reassemble(
+        &self,
+        timeout: Duration,
+        max_message_size: usize,
+    ) -> io::Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        self.restoring_timeouts(|conn| conn.reassemble(deadline, max_message_size))
+    }
+
+    /// Receives chunks until a message is complete, giving up at `deadline`.
+    fn reassemble(&self, deadline: Instant, max_message_size: usize) -> io::Result<Vec<u8>> {
+        let mut partials: HashMap<u32, Partial> = HashMap::new();
+        let mut buffered = 0;
+        let mut buf = vec![0; MAX_CHUNK_LEN];
+        loop {
+            self.as_ref().set_read_timeout(Some(remaining(deadline)?))?;
+            let n = match self.recv(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(timed_out(e)),
+            };
+
+            // A stray datagram shouldn't cost the chunks received so far.
+            let Some((header, payload)) = ChunkHeader::decode(&buf[..n]) else {
+                continue;
+            };
+            let partial = match partials.entry(header.message_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    buffered += usize::from(header.total) * SLOT_LEN;
+                    if buffered > max_message_size {
+                        return Err(invalid("message exceeds the maximum size"));
+                    }
+                    entry.insert(Partial::new(header.total))
+                }
+            };
+            if partial.chunks.len() != usize::from(header.total) {
+                continue;
+            }
+            let slot = &mut partial.chunks[usize::from(header.index)];
+            if slot.is_some() {
+                return Err(invalid("received a chunk twice"));
+            }
+            buffered += payload.len();
+            if buffered > max_message_size {
+                return Err(invalid("message exceeds the maximum size"));
+            }
+            *slot = Some(payload.to_vec());
+            partial.received += 1;
+
+            if partial.received == partial.chunks.len() {
+                let partial = partials.remove(&header.message_id).unwrap();
+                return Ok(partial.chunks.into_iter().flatten().flatten().collect());
+            }
+        }
+    }
+}
+
+/// Returns an [`io::ErrorKind::InvalidData`] error describing a bad chunk.
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn send_chunked_splits_message() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let msg: Vec<u8> = (0..3 * 1024).map(|i| i as u8).collect();
+        assert_eq!(sender_conn.send_chunked(&msg, 512).unwrap(), 6);
+
+        let mut reassembled = vec![Vec::new(); 6];
+        let mut message_id = None;
+        let mut buf = [0u8; 1024];
+        for _ in 0..6 {
+            let n = receiver.recv(&mut buf).unwrap();
+            let (header, payload) = buf[..n].split_at(HEADER_LEN);
+            let id = &header[..4];
+            let index = u16::from_be_bytes([header[4], header[5]]);
+            let total = u16::from_be_bytes([header[6], header[7]]);
+            assert_eq!(total, 6);
+            assert_eq!(*message_id.get_or_insert(id.to_vec()), id);
+            assert!(payload.len() <= 512);
+            reassembled[usize::from(index)] = payload.to_vec();
+        }
+        assert_eq!(reassembled.concat(), msg);
+    }
+
+    /// Returns two sockets connected to each other.
+    fn connected_pair() -> (ConnectedUdpSocket, ConnectedUdpSocket) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+        (
+            ConnectedUdpSocket::connect(a, b_addr).unwrap(),
+            ConnectedUdpSocket::connect(b, a_addr).unwrap(),
+        )
+    }
+
+    #[test]
+    fn recv_reassemble_complete_message() {
+        let (a, b) = connected_pair();
+
+        let msg: Vec<u8> = (0..3 * 1024).map(|i| i as u8).collect();
+        a.send_chunked(&msg, 512).unwrap();
+        let reassembled = b.recv_reassemble(Duration::from_secs(5), 4096).unwrap();
+        assert_eq!(reassembled, msg);
+        assert_eq!(b.as_ref().read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn recv_reassemble_out_of_order() {
+        let (a, b) = connected_pair();
+
+        let chunk = |index: u16, payload: &[u8]| {
+            let header = ChunkHeader {
+                message_id: 7,
+                index,
+                total: 2,
+            };
+            a.send_with_header(&header.encode(), payload).unwrap();
+        };
+        chunk(1, b"world");
+        chunk(0, b"hello ");
+        let reassembled = b.recv_reassemble(Duration::from_secs(5), 64).unwrap();
+        assert_eq!(reassembled, b"hello world");
+    }
+
+    #[test]
+    fn recv_reassemble_missing_chunk_times_out() {
+        let (a, b) = connected_pair();
+
+        let header = ChunkHeader {
+            message_id: 1,
+            index: 0,
+            total: 2,
+        };
+        a.send_with_header(&header.encode(), b"half").unwrap();
+        let err = b
+            .recv_reassemble(Duration::from_millis(50), 64)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn recv_reassemble_duplicate_chunk_error() {
+        let (a, b) = connected_pair();
+
+        let header = ChunkHeader {
+            message_id: 1,
+            index: 0,
+            total: 2,
+        };
+        a.send_with_header(&header.encode(), b"half").unwrap();
+        a.send_with_header(&header.encode(), b"half").unwrap();
+        let err = b.recv_reassemble(Duration::from_secs(5), 64).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn recv_reassemble_skips_stray_datagrams() {
+        let (a, b) = connected_pair();
+
+        let chunk = |total: u16, index: u16, payload: &[u8]| {
+            let header = ChunkHeader {
+                message_id: 7,
+                index,
+                total,
+            };
+            a.send_with_header(&header.encode(), payload).unwrap();
+        };
+        chunk(2, 0, b"hello ");
+        a.send(b"junk").unwrap();
+        chunk(3, 1, b"stray");
+        chunk(2, 1, b"world");
+        let reassembled = b.recv_reassemble(Duration::from_secs(5), 64).unwrap();
+        assert_eq!(reassembled, b"hello world");
+    }
+
+    #[test]
+    fn recv_reassemble_bounds_empty_chunks() {
+        let (a, b) = connected_pair();
+
+        // Empty chunks of ever new messages carry no payload, but their
+        // slots still count.
+        for message_id in 0..100 {
+            let header = ChunkHeader {
+                message_id,
+                index: 0,
+                total: u16::MAX,
+            };
+            a.send(&header.encode()).unwrap();
+        }
+        let err = b
+            .recv_reassemble(Duration::from_secs(5), 1024 * 1024)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn recv_reassemble_too_large_error() {
+        let (a, b) = connected_pair();
+
+        a.send_chunked(&[0; 1024], 256).unwrap();
+        let err = b.recv_reassemble(Duration::from_secs(5), 512).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn send_chunked_zero_chunk_size_error() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let err = sender_conn.send_chunked(b"ping", 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn chunk_header_round_trip() {
+        let header = ChunkHeader {
+            message_id: 0xdead_beef,
+            index: 2,
+            total: 3,
+        };
+        let mut chunk = header.encode().to_vec();
+        chunk.extend_from_slice(b"data");
+        assert_eq!(ChunkHeader::decode(&chunk), Some((header, &b"data"[..])));
+
+        // Index out of range.
+        chunk[5] = 3;
+        assert_eq!(ChunkHeader::decode(&chunk), None);
+        // Too short for a header.
+        assert_eq!(ChunkHeader::decode(&chunk[..7]), None);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::sans_io::{FramedCodec, LENGTH_PREFIX_LEN};
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the flush thread sleeps between checks, at most.
+const POLL_SLICE: Duration = Duration::from_millis(10);
+
+/// Buffers small messages and sends them to the peer as fewer, larger
+/// datagrams, much like Nagle's algorithm does for TCP.
+///
+/// Each message is framed with [`FramedCodec`]'s 4-byte big-endian length
+/// prefix, and the framed messages are packed back to back into a datagram
+/// of at most `max_datagram_len` bytes. The datagram is sent once the next
+/// message wouldn't fit, once the oldest buffered message has waited
+/// `max_delay`, on [`flush`](Self::flush), or when the sender is dropped. A
+/// background thread handles the delay; errors from the sends it makes are
+/// dropped, so call `flush` where send errors matter.
+///
+/// This changes datagram boundaries, so it's only for framed protocols whose
+/// receiver splits datagrams back into messages, e.g. with
+/// [`FramedCodec::decode_all`].
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::sans_io::FramedCodec;
+/// use connected_udp::{CoalescingSender, ConnectedUdpSocket};
+/// use std::net::UdpSocket;
+/// use std::time::Duration;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///
+///     let sender =
+///         CoalescingSender::new(conn_client.into_shared(), 1200, Duration::from_millis(5));
+///     sender.write(b"one")?;
+///     sender.write(b"two")?;
+///     sender.flush()?;
+///
+///     let mut buf = [0; 1200];
+///     let n = host.recv(&mut buf)?;
+///     let messages = FramedCodec::new().decode_all(&buf[..n]).unwrap();
+///     assert_eq!(messages, [b"one".to_vec(), b"two".to_vec()]);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CoalescingSender {
+    shared: Arc<Shared>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// The state shared with the flush thread.
+#[derive(Debug)]
+struct Shared {
+    socket: Arc<ConnectedUdpSocket>,
+    max_datagram_len: usize,
+    max_delay: Duration,
+    pending: Mutex<Pending>,
+}
+
+/// The datagram being built up.
+#[derive(Debug, Default)]
+struct Pending {
+    datagram: Vec<u8>,
+    /// When the oldest message in `datagram` was written.
+    since: Option<Instant>,
+}
+
+impl CoalescingSender {
+    /// Creates a sender that coalesces messages into datagrams of up to
+    /// `max_datagram_len` bytes, holding none back for longer than
+    /// `max_delay`, and starts its flush thread.
+    pub fn new(
+        socket: Arc<ConnectedUdpSocket>,
+        max_datagram_len: usize,
+        max_delay: Duration,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            socket,
+            max_datagram_len,
+            max_delay,
+            pending: Mutex::default(),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let shared = Arc::clone(&shared);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || flush_when_due(&shared, &stop))
+        };
+
+        Self {
+            shared,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Buffers `msg` to be sent with the messages around it.
+    ///
+    /// If the framed message doesn't fit in the datagram being built up,
+    /// that datagram is sent first. A message too big to share a datagram
+    /// is sent in one of its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from sending a full datagram. The message isn't
+    /// buffered in that case.
+    pub fn write(&self, msg: &[u8]) -> io::Result<()> {
+        let mut pending = self.shared.pending.lock().unwrap();
+        let framed_len = LENGTH_PREFIX_LEN + msg.len();
+        if !pending.datagram.is_empty()
+            && pending.datagram.len() + framed_len > self.shared.max_datagram_len
+        {
+            self.shared.send(&mut pending)?;
+        }
+
+        FramedCodec::new().encode(msg, &mut pending.datagram);
+        pending.since.get_or_insert_with(Instant::now);
+        if pending.datagram.len() >= self.shared.max_datagram_len {
+            self.shared.send(&mut pending)?;
+        }
+        Ok(())
+    }
+
+    /// Sends any buffered messages right away.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut pending = self.shared.pending.lock().unwrap();
+        if pending.datagram.is_empty() {
+            return Ok(());
+        }
+        self.shared.send(&mut pending)
+    }
+}
+
+impl Drop for CoalescingSender {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = self.flush();
+    }
+}
+
+impl Shared {
+    /// Sends the datagram built up in `pending` and starts a new one, even
+    /// if the send fails.
+    fn send(&self, pending: &mut Pending) -> io::Result<()> {
+        pending.since = None;
+        let res = self.socket.send(&pending.datagram);
+        pending.datagram.clear();
+        res.map(|_| ())
+    }
+}
+
+/// Flushes buffered messages once they've waited `max_delay`, until `stop`
+/// is set.
+fn flush_when_due(shared: &Shared, stop: &AtomicBool) {
+    while !stop.load(Ordering::Acquire) {
+        let wait = {
+            let mut pending = shared.pending.lock().unwrap();
+            match pending.since.map(|since| since + shared.max_delay) {
+                Some(due) if due <= Instant::now() => {
+                    let _ = shared.send(&mut pending);
+                    POLL_SLICE
+                }
+                Some(due) => due
+                    .saturating_duration_since(Instant::now())
+                    .min(POLL_SLICE),
+                None => POLL_SLICE,
+            }
+        };
+        thread::sleep(wait);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    fn sender_and_receiver(max_delay: Duration) -> (CoalescingSender, UdpSocket) {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn = ConnectedUdpSocket::connect(sender, receiver.local_addr().unwrap()).unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        (
+            CoalescingSender::new(conn.into_shared(), 64, max_delay),
+            receiver,
+        )
+    }
+
+    #[test]
+    fn writes_flush_as_one_datagram() {
+        let (sender, receiver) = sender_and_receiver(Duration::from_secs(60));
+
+        for msg in [&b"one"[..], b"two", b"three"] {
+            sender.write(msg).unwrap();
+        }
+        sender.flush().unwrap();
+
+        let mut buf = [0u8; 128];
+        let n = receiver.recv(&mut buf).unwrap();
+        let messages = FramedCodec::new().decode_all(&buf[..n]).unwrap();
+        assert_eq!(
+            messages,
+            [b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+    }
+
+    #[test]
+    fn full_datagram_is_sent_before_overflowing() {
+        let (sender, receiver) = sender_and_receiver(Duration::from_secs(60));
+
+        // Two 34-byte framed messages don't fit in 64 bytes.
+        sender.write(&[1; 30]).unwrap();
+        sender.write(&[2; 30]).unwrap();
+
+        let mut buf = [0u8; 128];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            FramedCodec::new().decode_all(&buf[..n]).unwrap(),
+            [vec![1; 30]]
+        );
+
+        drop(sender);
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            FramedCodec::new().decode_all(&buf[..n]).unwrap(),
+            [vec![2; 30]]
+        );
+    }
+
+    #[test]
+    fn delay_flushes_in_background() {
+        let (sender, receiver) = sender_and_receiver(Duration::from_millis(20));
+
+        sender.write(b"late").unwrap();
+
+        let mut buf = [0u8; 128];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            FramedCodec::new().decode_all(&buf[..n]).unwrap(),
+            [b"late".to_vec()]
+        );
+    }
+}
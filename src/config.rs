@@ -0,0 +1,98 @@
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::time::Duration;
+
+/// A point-in-time snapshot of a [`ConnectedUdpSocket`]'s settings, as
+/// returned by [`ConnectedUdpSocket::config`].
+///
+/// Each setting is queried separately, so one that can't be read on this
+/// platform (or fails for any other reason) doesn't hide the others.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SocketConfigSnapshot {
+    /// The IP time-to-live (`IP_TTL`).
+    pub ttl: io::Result<u32>,
+    /// The read timeout, where `None` means reads block indefinitely.
+    pub read_timeout: io::Result<Option<Duration>>,
+    /// The write timeout, where `None` means writes block indefinitely.
+    pub write_timeout: io::Result<Option<Duration>>,
+    /// Whether the socket is in nonblocking mode, as reported by
+    /// [`ConnectedUdpSocket::is_nonblocking`].
+    pub nonblocking: io::Result<bool>,
+    /// The send buffer size (`SO_SNDBUF`), as reported by the OS.
+    pub send_buffer_size: io::Result<usize>,
+    /// The receive buffer size (`SO_RCVBUF`), as reported by the OS.
+    pub recv_buffer_size: io::Result<usize>,
+}
+
+impl ConnectedUdpSocket {
+    /// Queries the socket's current settings in one call, e.g. to log them
+    /// when diagnosing a problem.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  client.set_ttl(16).expect("couldn't set TTL");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let config = conn_client.config();
+    ///  assert_eq!(config.ttl.unwrap(), 16);
+    /// # }
+    /// ```
+    pub fn config(&self) -> SocketConfigSnapshot {
+        let socket = self.as_ref();
+        SocketConfigSnapshot {
+            ttl: socket.ttl(),
+            read_timeout: socket.read_timeout(),
+            write_timeout: socket.write_timeout(),
+            nonblocking: self.is_nonblocking(),
+            send_buffer_size: self.send_buffer_size(),
+            recv_buffer_size: self.recv_buffer_size(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn config_matches_individual_settings() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let socket = sender_conn.as_ref();
+        socket.set_ttl(42).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket.set_write_timeout(None).unwrap();
+        sender_conn.set_send_buffer_size(64 * 1024).unwrap();
+        sender_conn.set_recv_buffer_size(128 * 1024).unwrap();
+
+        let config = sender_conn.config();
+        assert_eq!(config.ttl.unwrap(), 42);
+        assert_eq!(config.read_timeout.unwrap(), Some(Duration::from_secs(2)));
+        assert_eq!(config.write_timeout.unwrap(), None);
+        assert_eq!(
+            config.send_buffer_size.unwrap(),
+            sender_conn.send_buffer_size().unwrap()
+        );
+        assert_eq!(
+            config.recv_buffer_size.unwrap(),
+            sender_conn.recv_buffer_size().unwrap()
+        );
+        assert!(!config.nonblocking.unwrap());
+    }
+}
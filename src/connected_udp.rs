@@ -1,6 +1,29 @@
+#[cfg(feature = "socks5")]
+use crate::socks5::Socks5Association;
+use crate::stats::Stats;
+use crate::sys::{self, Interest};
+use crate::watchdog::RecvClock;
+use crate::{AsBytes, Builder, Error};
+use socket2::{SockRef, Type};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::VecDeque;
 use std::convert::TryFrom;
-use std::io;
-use std::net::{SocketAddr, UdpSocket};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{self, IoSlice};
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How long [`ConnectedUdpSocket::recv_cancellable`] waits for data before
+/// checking its cancellation flag again.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The largest datagram [`ConnectedUdpSocket::send_until_ack`] can receive as
+/// a reply.
+const MAX_REPLY_LEN: usize = 64 * 1024;
 
 /// A connected UDP socket.
 ///
@@ -73,10 +96,102 @@ use std::net::{SocketAddr, UdpSocket};
 ///     Ok(())
 /// }
 /// ```
+///
+/// # IPv4-mapped peers
+///
+/// A dual-stack IPv6 socket talks to IPv4 peers through IPv4-mapped
+/// addresses such as `::ffff:127.0.0.1`. By default the cached peer is
+/// canonicalized to the plain IPv4 form, so [`peer_addr`](Self::peer_addr)
+/// reports `127.0.0.1` whether the socket was connected with an IPv4 address
+/// or its mapped form. Use [`Builder::unmap_ipv4`] to keep the mapped form
+/// instead.
+///
+/// # Nonblocking sockets
+///
+/// When a socket in nonblocking mode has nothing to receive, or no room to
+/// send, every method reports it as [`io::ErrorKind::WouldBlock`] on every
+/// supported platform. That includes the platforms where `EAGAIN` and
+/// `EWOULDBLOCK` are distinct error codes, and the methods that make raw
+/// system calls, so callers only ever need to match on the kind.
 #[derive(Debug)]
 pub struct ConnectedUdpSocket {
     socket: UdpSocket,
     peer: SocketAddr,
+    /// Whether the peer was cleared with `unset_peer`.
+    peer_unset: bool,
+    /// The local address, cached for `fingerprint`.
+    local: OnceLock<SocketAddr>,
+    options: Options,
+    /// When a datagram was last received, for `spawn_watchdog`.
+    recv_clock: Arc<RecvClock>,
+    stats: Arc<Stats>,
+    /// The mode last set with `set_nonblocking`, since Winsock can't report
+    /// it.
+    #[cfg(windows)]
+    nonblocking: Arc<AtomicBool>,
+    /// The SOCKS5 association traffic is relayed through, if any.
+    #[cfg(feature = "socks5")]
+    socks5: Option<Arc<Socks5Association>>,
+}
+
+/// The settings chosen through a [`Builder`] that stay with the socket.
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    /// Whether IPv4-mapped peer addresses are cached in their IPv4 form.
+    pub(crate) unmap_ipv4: bool,
+    pub(crate) empty_datagrams: EmptyDatagramPolicy,
+    pub(crate) byte_order: ByteOrder,
+    /// Whether datagrams are matched to the peer by IP address alone.
+    pub(crate) match_peer_by_ip_only: bool,
+    /// The key payloads are XORed with, if obfuscation is on. Never empty.
+    pub(crate) obfuscation_key: Option<Arc<[u8]>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            unmap_ipv4: true,
+            empty_datagrams: EmptyDatagramPolicy::default(),
+            byte_order: ByteOrder::default(),
+            match_peer_by_ip_only: false,
+            obfuscation_key: None,
+        }
+    }
+}
+
+/// How receiving a zero-length datagram is reported.
+///
+/// UDP allows empty datagrams, but a receive returning `Ok(0)` is easily
+/// mistaken for end-of-stream by code written against streams. Set the
+/// policy with [`Builder::treat_empty_datagram_as`].
+///
+/// The policy only applies when receiving into a non-empty buffer, since an
+/// empty buffer can't tell an empty datagram from a truncated one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EmptyDatagramPolicy {
+    /// Return `Ok(0)`, just like [`UdpSocket::recv`]. This is the default.
+    #[default]
+    ReturnZero,
+    /// Discard empty datagrams and wait for the next non-empty one.
+    Skip,
+    /// Fail with [`Error::EmptyDatagram`] (reported as
+    /// [`io::ErrorKind::InvalidData`]).
+    Error,
+}
+
+/// The byte order used by the numeric helpers without an endianness suffix,
+/// such as [`ConnectedUdpSocket::send_u32`].
+///
+/// Set the order with [`Builder::byte_order`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Most significant byte first, also known as network byte order. This
+    /// is the default.
+    #[default]
+    Big,
+    /// Least significant byte first.
+    Little,
 }
 
 impl ConnectedUdpSocket {
@@ -84,6 +199,12 @@ impl ConnectedUdpSocket {
     /// destination for `send` and limiting packets that are read via `recv` to
     /// that address.
     ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `peer` has
+    /// an unspecified IP address or a zero port, since a socket "connected"
+    /// to such an address isn't meaningfully connected.
+    ///
     /// # Examples
     ///
     /// ```
@@ -98,8 +219,420 @@ impl ConnectedUdpSocket {
     /// # }
     /// ```
     pub fn connect(socket: UdpSocket, peer: SocketAddr) -> io::Result<Self> {
-        socket.connect(peer)?;
-        Ok(Self { socket, peer })
+        Self::connect_with(socket, peer, Options::default())
+    }
+
+    /// Connects `socket` to `peer`, keeping `options` with the socket.
+    pub(crate) fn connect_with(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        options: Options,
+    ) -> io::Result<Self> {
+        let peer = connect_socket(&socket, peer, &options)?;
+        Ok(Self::from_parts(socket, peer, options))
+    }
+
+    /// Wraps an already-connected `socket`.
+    fn from_parts(socket: UdpSocket, peer: SocketAddr, options: Options) -> Self {
+        Self {
+            socket,
+            peer,
+            peer_unset: false,
+            local: OnceLock::new(),
+            options,
+            recv_clock: Arc::new(RecvClock::new()),
+            stats: Arc::default(),
+            #[cfg(windows)]
+            nonblocking: Arc::default(),
+            #[cfg(feature = "socks5")]
+            socks5: None,
+        }
+    }
+
+    /// Wraps `socket`, connected to the relay of `association`, so its
+    /// traffic to `peer` goes through the SOCKS5 proxy.
+    #[cfg(feature = "socks5")]
+    pub(crate) fn with_socks5(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        options: Options,
+        association: Socks5Association,
+    ) -> io::Result<Self> {
+        validate_peer(peer)?;
+        let mut conn = Self::from_parts(socket, peer, options);
+        conn.socks5 = Some(Arc::new(association));
+        Ok(conn)
+    }
+
+    /// Connects `socket` to `peer`, then sends `probe` and waits up to
+    /// `timeout` for the peer to reply before returning the socket.
+    ///
+    /// This confirms the peer is actually there, which a plain
+    /// [`connect`](Self::connect) can't do for UDP. The whole operation,
+    /// including sending the probe (which can block on a full send buffer),
+    /// is bounded by `timeout`. The reply is consumed and discarded. The
+    /// socket's read and write timeouts are restored before returning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::TimedOut`] if no reply
+    /// arrives in time, along with any error from connecting or sending.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use connected_udp::{spawn_echo_server, ConnectedUdpSocket};
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let (echo_addr, _echo) = spawn_echo_server()?;
+    ///
+    ///     let client = UdpSocket::bind("127.0.0.1:0")?;
+    ///     let conn_client = ConnectedUdpSocket::connect_with_probe(
+    ///         client,
+    ///         echo_addr,
+    ///         b"hello?",
+    ///         Duration::from_secs(1),
+    ///     )?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn connect_with_probe(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        probe: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let conn = Self::connect(socket, peer)?;
+        let deadline = Instant::now() + timeout;
+        conn.restoring_timeouts(|conn| conn.probe(probe, deadline))?;
+        Ok(conn)
+    }
+
+    /// Connects `socket` to `peer` only if the peer proves it can receive
+    /// datagrams sent to its address, giving up after `timeout`.
+    ///
+    /// A random nonce is sent to the peer, which must echo it back unchanged
+    /// as its first reply. Since the nonce can't be guessed, an echo shows
+    /// the peer really is reachable at `peer`, rather than just spoofing
+    /// that address as a source. The socket's read and write timeouts are
+    /// restored before returning.
+    ///
+    /// The nonce comes from the standard library's randomly seeded hasher.
+    /// It's unpredictable enough to stop off-path spoofing, but isn't meant
+    /// as cryptographic material.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::PermissionDenied`] if the
+    /// first reply isn't the nonce or no reply arrives in time, along with
+    /// any error from connecting or sending.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use connected_udp::{spawn_echo_server, ConnectedUdpSocket};
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let (echo_addr, _echo) = spawn_echo_server()?;
+    ///
+    ///     let client = UdpSocket::bind("127.0.0.1:0")?;
+    ///     let conn_client =
+    ///         ConnectedUdpSocket::challenge_connect(client, echo_addr, Duration::from_secs(1))?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn challenge_connect(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let conn = Self::connect(socket, peer)?;
+        let deadline = Instant::now() + timeout;
+        conn.restoring_timeouts(|conn| conn.challenge(&nonce(), deadline))?;
+        Ok(conn)
+    }
+
+    /// Runs `f`, then restores the socket's read and write timeouts to what
+    /// they were beforehand.
+    pub(crate) fn restoring_timeouts<T>(
+        &self,
+        f: impl FnOnce(&Self) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let read_timeout = self.socket.read_timeout()?;
+        let write_timeout = self.socket.write_timeout()?;
+
+        let res = f(self);
+        let restored = self
+            .socket
+            .set_read_timeout(read_timeout)
+            .and_then(|()| self.socket.set_write_timeout(write_timeout));
+        let value = res?;
+        restored?;
+        Ok(value)
+    }
+
+    /// Sends `nonce` and checks that the first reply echoes it, giving up at
+    /// `deadline`.
+    fn challenge(&self, nonce: &[u8], deadline: Instant) -> io::Result<()> {
+        self.socket.set_write_timeout(Some(remaining(deadline)?))?;
+        self.send(nonce).map_err(timed_out)?;
+
+        // One spare byte so a reply that merely starts with the nonce fails.
+        let mut buf = vec![0; nonce.len() + 1];
+        loop {
+            let timeout = remaining(deadline).map_err(|_| challenge_failed())?;
+            self.socket.set_read_timeout(Some(timeout))?;
+            match self.socket.recv(&mut buf) {
+                Ok(n) if buf[..n] == *nonce => return Ok(()),
+                Ok(_) => return Err(challenge_failed()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(challenge_failed())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Binds a new socket to the first free port in `range` on `local_ip`,
+    /// then connects it to `peer`.
+    ///
+    /// This is for clients behind firewalls that only let traffic out from
+    /// certain source ports. Ports are tried in ascending order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::AddrInUse`] if every port in
+    /// `range` is taken (or `range` is empty). Any other binding error is
+    /// returned immediately, as are errors from connecting.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::{Ipv4Addr, UdpSocket};
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let conn_client = ConnectedUdpSocket::connect_from_port_range(
+    ///      Ipv4Addr::LOCALHOST.into(),
+    ///      40000..=40999,
+    ///      host_addr,
+    ///  )
+    ///  .expect("couldn't client to host");
+    ///  assert!((40000..=40999).contains(&conn_client.local_addr().unwrap().port()));
+    /// # }
+    /// ```
+    pub fn connect_from_port_range(
+        local_ip: IpAddr,
+        range: RangeInclusive<u16>,
+        peer: SocketAddr,
+    ) -> io::Result<Self> {
+        for port in range {
+            match UdpSocket::bind(SocketAddr::new(local_ip, port)) {
+                Ok(socket) => return Self::connect(socket, peer),
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "every port in the range is in use",
+        ))
+    }
+
+    /// Connects a socket produced by `socket_factory` to `peer`, retrying
+    /// with exponential backoff on failure.
+    ///
+    /// Creating or binding a socket can fail transiently, e.g. while an
+    /// interface comes up, so each of the up to `attempts` tries calls the
+    /// factory for a fresh socket. After a failed try the next one waits
+    /// `base_delay`, then twice that, and so on.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last error once every attempt has failed. Returns an error
+    /// of kind [`io::ErrorKind::InvalidInput`] if `attempts` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let conn_client = ConnectedUdpSocket::connect_with_backoff(
+    ///      || UdpSocket::bind("127.0.0.1:0"),
+    ///      host_addr,
+    ///      5,
+    ///      Duration::from_millis(10),
+    ///  )
+    ///  .expect("couldn't client to host");
+    ///  assert_eq!(conn_client.peer_addr(), host_addr);
+    /// # }
+    /// ```
+    pub fn connect_with_backoff<F>(
+        mut socket_factory: F,
+        peer: SocketAddr,
+        attempts: usize,
+        base_delay: Duration,
+    ) -> io::Result<Self>
+    where
+        F: FnMut() -> io::Result<UdpSocket>,
+    {
+        let mut delay = base_delay;
+        let mut last_err = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one connection attempt is required",
+        );
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+            }
+            match socket_factory().and_then(|socket| Self::connect(socket, peer)) {
+                Ok(conn) => return Ok(conn),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Sends `probe` and waits for any reply, giving up at `deadline`.
+    fn probe(&self, probe: &[u8], deadline: Instant) -> io::Result<()> {
+        self.socket.set_write_timeout(Some(remaining(deadline)?))?;
+        self.send(probe).map_err(timed_out)?;
+
+        let mut buf = [0; 1];
+        loop {
+            self.socket.set_read_timeout(Some(remaining(deadline)?))?;
+            match self.socket.recv(&mut buf) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(timed_out(e)),
+            }
+        }
+    }
+
+    /// Clears the peer, putting the socket in a logically disconnected state
+    /// until [`reconnect`](Self::reconnect) is called.
+    ///
+    /// The OS-level connection is left in place, but sending and receiving
+    /// fail with [`Error::NotConnected`] (reported as
+    /// [`io::ErrorKind::NotConnected`]) in the meantime. This models a
+    /// "disconnected" phase without giving up the socket.
+    /// [`peer_addr`](Self::peer_addr) keeps returning the last peer.
+    pub fn unset_peer(&mut self) {
+        self.peer_unset = true;
+    }
+
+    /// Connects the socket to a new `peer`, replacing the current one.
+    ///
+    /// The cached peer is only updated if connecting succeeds. This also ends
+    /// a logical disconnect started by [`unset_peer`](Self::unset_peer).
+    ///
+    /// # Errors
+    ///
+    /// Fails for the same reasons as [`connect`](Self::connect), and with an
+    /// error of kind [`io::ErrorKind::Unsupported`] if the socket relays
+    /// through SOCKS5, since the association only targets its original peer.
+    pub fn reconnect(&mut self, peer: SocketAddr) -> io::Result<()> {
+        self.ensure_direct()?;
+        self.peer = connect_socket(&self.socket, peer, &self.options)?;
+        self.peer_unset = false;
+        // Connecting a wildcard-bound socket can change its local address.
+        self.local = OnceLock::new();
+        Ok(())
+    }
+
+    /// Swaps in the socket and peer of `new`, returning the previous ones as a
+    /// separate [`ConnectedUdpSocket`].
+    ///
+    /// This is for failover: prepare a replacement socket (e.g. on the same
+    /// local port with `SO_REUSEPORT`), then swap it in without re-creating
+    /// whatever holds this one. Settings chosen through the [`Builder`] stay
+    /// with `self`; the returned socket keeps them as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let old_host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let new_host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let new_host_addr = new_host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let mut conn_client = ConnectedUdpSocket::connect(client, old_host.local_addr().unwrap())
+    ///      .expect("couldn't client to host");
+    ///
+    ///  let replacement = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let replacement = ConnectedUdpSocket::connect(replacement, new_host_addr)
+    ///      .expect("couldn't client to host");
+    ///
+    ///  let old = conn_client.replace_socket(replacement);
+    ///  assert_eq!(conn_client.peer_addr(), new_host_addr);
+    ///  drop(old);
+    /// # }
+    /// ```
+    pub fn replace_socket(&mut self, new: ConnectedUdpSocket) -> ConnectedUdpSocket {
+        let mut old = std::mem::replace(self, new);
+        self.options = old.options.clone();
+        std::mem::swap(&mut self.recv_clock, &mut old.recv_clock);
+        std::mem::swap(&mut self.stats, &mut old.stats);
+        old
+    }
+
+    /// Fails with [`Error::NotConnected`] if the peer was cleared with
+    /// `unset_peer`.
+    pub(crate) fn ensure_peer(&self) -> io::Result<()> {
+        if self.peer_unset {
+            return Err(Error::NotConnected.into());
+        }
+        Ok(())
+    }
+
+    /// Fails with an error of kind [`io::ErrorKind::Unsupported`] if traffic
+    /// is relayed through SOCKS5, for methods that would move or bypass the
+    /// OS-level connection to the relay.
+    pub(crate) fn ensure_direct(&self) -> io::Result<()> {
+        #[cfg(feature = "socks5")]
+        if self.socks5.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "not supported on a socket relayed through SOCKS5",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decomposes the socket into the underlying [`UdpSocket`], the cached
+    /// peer address and the [`Builder`] settings.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn into_parts(self) -> (UdpSocket, SocketAddr, Options) {
+        (self.socket, self.peer, self.options)
+    }
+
+    /// Returns a [`Builder`] for configuring the socket before it's
+    /// connected.
+    pub fn builder() -> Builder {
+        Builder::new()
     }
 
     /// Returns the local socket address for this socket.
@@ -115,15 +648,116 @@ impl ConnectedUdpSocket {
     ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
     ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
     ///
-    ///  let local_addr = conn_client.local_addr();
+    ///  let local_addr = conn_client.local_addr().expect("couldn't retrieve local address");
     ///  println!("local addr: {}", local_addr);
     /// # }
+    /// ```
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.socket.local_addr()
     }
 
+    /// Returns the local port this socket is bound to.
+    ///
+    /// A shortcut for `local_addr().map(|a| a.port())`, e.g. for advertising
+    /// the port to a peer.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let port = conn_client.local_port().expect("couldn't retrieve local port");
+    ///  println!("local port: {}", port);
+    /// # }
+    /// ```
+    pub fn local_port(&self) -> io::Result<u16> {
+        self.local_addr().map(|a| a.port())
+    }
+
+    /// Returns the local address the socket sends from when talking to its
+    /// peer.
+    ///
+    /// A socket bound to a wildcard address such as `0.0.0.0:0` doesn't have
+    /// a specific local IP until it's connected; connecting picks one based on
+    /// the route to the peer, which is what this returns. Since a
+    /// [`ConnectedUdpSocket`] is always connected, this is its
+    /// [`local_addr`](Self::local_addr) with the guarantee that the IP is
+    /// specific.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::AddrNotAvailable`] if the OS
+    /// hasn't chosen a specific local IP.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let egress = conn_client.egress_addr().expect("couldn't retrieve egress address");
+    ///  assert!(egress.ip().is_loopback());
+    /// # }
+    /// ```
+    pub fn egress_addr(&self) -> io::Result<SocketAddr> {
+        let local = self.local_addr()?;
+        if local.ip().is_unspecified() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "no egress address has been chosen for the peer",
+            ));
+        }
+        Ok(local)
+    }
+
+    /// Returns whether the local port lies in the OS's ephemeral port range,
+    /// i.e. it was most likely assigned automatically rather than chosen.
+    ///
+    /// A client that never needs a discoverable local address can bind to a
+    /// wildcard such as `0.0.0.0:0` and connect; the OS then picks both the
+    /// local IP and an ephemeral port. On Linux the range is read from
+    /// `/proc/sys/net/ipv4/ip_local_port_range`; elsewhere the IANA dynamic
+    /// range (49152–65535) is assumed, which is the default on Windows, macOS
+    /// and the BSDs. A port chosen explicitly from inside that range is also
+    /// reported as ephemeral.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  assert!(conn_client.is_ephemeral_local().expect("couldn't check local port"));
+    /// # }
+    /// ```
+    pub fn is_ephemeral_local(&self) -> io::Result<bool> {
+        let port = self.local_addr()?.port();
+        Ok(sys::ephemeral_port_range()?.contains(&port))
+    }
+
     /// Returns the socket address of the remote peer.
     ///
+    /// This is the address cached when the socket was connected, so it's
+    /// free to call and can't fail. Use
+    /// [`peer_addr_result`](Self::peer_addr_result) to check it against the
+    /// OS.
+    ///
     /// # Examples
     /// ```
     /// # use connected_udp::ConnectedUdpSocket;
@@ -138,120 +772,3354 @@ impl ConnectedUdpSocket {
     ///  let peer_addr = conn_client.peer_addr();
     ///  println!("remote peer addr: {}", peer_addr);
     /// # }
+    /// ```
     pub fn peer_addr(&self) -> SocketAddr {
         self.peer
     }
 
-    /// Sends data through the underlying socket.
-    /// # Examples
+    /// Returns the socket address of the remote peer as the OS sees it,
+    /// checking that it still matches the cached peer.
     ///
-    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        self.socket.send(buf)
+    /// Unlike [`peer_addr`](Self::peer_addr), which just returns the cached
+    /// address, this makes a system call, like [`UdpSocket::peer_addr`]. It
+    /// catches the underlying socket being reconnected behind the wrapper's
+    /// back, e.g. through [`as_ref`](AsRef::as_ref).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotConnected`] if the socket
+    /// is no longer connected at the OS level, and of kind
+    /// [`io::ErrorKind::Other`] wrapping [`Error::PeerMismatch`] if it's
+    /// connected to a different address than the cached peer.
+    pub fn peer_addr_result(&self) -> io::Result<SocketAddr> {
+        let actual = self.socket.peer_addr()?;
+        // The socket is connected to the relay, not the peer.
+        #[cfg(feature = "socks5")]
+        if self.socks5.is_some() {
+            return Ok(self.peer);
+        }
+        let actual = canonicalize(actual, &self.options);
+        if actual != self.peer {
+            return Err(Error::PeerMismatch {
+                expected: self.peer,
+                actual,
+            }
+            .into());
+        }
+        Ok(self.peer)
     }
 
-    /// Receives data from the socket and writes it into the provided buffer.
+    /// Returns the local and remote peer socket addresses together, as
+    /// `(local_addr, peer_addr)`.
+    ///
+    /// Handy for logging a connection's full 4-tuple in one go.
+    ///
     /// # Examples
-    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        self.socket.recv(buf)
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let (local, peer) = conn_client.endpoints().expect("couldn't retrieve endpoints");
+    ///  println!("{} -> {}", local, peer);
+    /// # }
+    /// ```
+    pub fn endpoints(&self) -> io::Result<(SocketAddr, SocketAddr)> {
+        Ok((self.local_addr()?, self.peer))
     }
-}
 
-impl TryFrom<UdpSocket> for ConnectedUdpSocket {
-    type Error = io::Error;
+    /// Returns a fingerprint of the connection's 4-tuple (local and peer
+    /// addresses), e.g. for deduplicating connections.
+    ///
+    /// The local address is looked up once and cached. Sockets sharing the
+    /// same local address and peer, such as a socket and its
+    /// [`try_clone`](Self::try_clone), have the same fingerprint. The value
+    /// is only stable within a single build of the program, so don't persist
+    /// it.
+    pub fn fingerprint(&self) -> u64 {
+        let local = match self.local.get() {
+            Some(local) => Some(*local),
+            None => self
+                .socket
+                .local_addr()
+                .ok()
+                .map(|local| *self.local.get_or_init(|| local)),
+        };
 
-    fn try_from(socket: UdpSocket) -> Result<Self, Self::Error> {
-        let peer = socket.peer_addr()?;
-        Ok(Self { socket, peer })
+        let mut hasher = DefaultHasher::new();
+        (local, self.peer).hash(&mut hasher);
+        hasher.finish()
     }
-}
 
-impl AsRef<UdpSocket> for ConnectedUdpSocket {
-    fn as_ref(&self) -> &UdpSocket {
-        &self.socket
+    /// Creates a new independently owned handle to the same socket.
+    ///
+    /// The clone refers to the same OS socket, so it has the same local
+    /// address and peer, and socket options set through one handle affect
+    /// both. [`Clone`] does the same but panics on failure.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            socket: self.socket.try_clone()?,
+            peer: self.peer,
+            peer_unset: self.peer_unset,
+            local: self.local.clone(),
+            options: self.options.clone(),
+            recv_clock: Arc::clone(&self.recv_clock),
+            stats: Arc::clone(&self.stats),
+            #[cfg(windows)]
+            nonblocking: Arc::clone(&self.nonblocking),
+            #[cfg(feature = "socks5")]
+            socks5: self.socks5.clone(),
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::thread;
+    /// Moves the socket into an [`Arc`] so it can be shared between threads.
+    ///
+    /// Every method takes `&self`, so the shared handle supports sending and
+    /// receiving concurrently without any `try_clone` syscalls. Note that
+    /// concurrent `recv` calls race each other: each datagram is delivered to
+    /// exactly one reader.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::thread;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr)
+    ///      .expect("couldn't client to host")
+    ///      .into_shared();
+    ///
+    ///  let shared = conn_client.clone();
+    ///  thread::spawn(move || shared.send(b"ping").expect("couldn't send"))
+    ///      .join()
+    ///      .unwrap();
+    /// # }
+    /// ```
+    pub fn into_shared(self) -> Arc<Self> {
+        Arc::new(self)
+    }
 
-    #[test]
-    fn try_from_not_connected_error() {
-        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
-        let err = ConnectedUdpSocket::try_from(socket).unwrap_err();
-        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    /// Returns the type of the underlying OS socket (`SO_TYPE`).
+    ///
+    /// This is useful as a sanity check that the wrapped socket really is a
+    /// datagram socket, e.g. when it was constructed from a raw descriptor.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::{ConnectedUdpSocket, SockType};
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  assert_eq!(conn_client.socket_type().unwrap(), SockType::DGRAM);
+    /// # }
+    /// ```
+    pub fn socket_type(&self) -> io::Result<Type> {
+        SockRef::from(&self.socket).r#type()
     }
 
-    #[test]
-    fn try_from_connected() {
-        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// Moves the socket into or out of nonblocking mode.
+    ///
+    /// Prefer this over calling [`UdpSocket::set_nonblocking`] on the
+    /// underlying socket, since on Windows it's the only way
+    /// [`is_nonblocking`](Self::is_nonblocking) learns of the change.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.socket.set_nonblocking(nonblocking)?;
+        #[cfg(windows)]
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns whether the socket is in nonblocking mode, e.g. to avoid a
+    /// redundant mode switch.
+    ///
+    /// On Unix this asks the OS (`fcntl(F_GETFL)`). Winsock has no way to
+    /// query the mode, so on Windows this reports what was last set through
+    /// [`set_nonblocking`](Self::set_nonblocking) on this socket or one of its
+    /// clones, and otherwise assumes the socket is blocking.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  if !conn_client.is_nonblocking().expect("couldn't query mode") {
+    ///      conn_client.set_nonblocking(true).expect("couldn't set mode");
+    ///  }
+    /// # }
+    /// ```
+    pub fn is_nonblocking(&self) -> io::Result<bool> {
+        #[cfg(unix)]
+        return sys::is_nonblocking(&self.socket);
+        #[cfg(windows)]
+        return Ok(self.nonblocking.load(Ordering::Relaxed));
+    }
+
+    /// Sets the size of the socket's send buffer (`SO_SNDBUF`).
+    ///
+    /// The OS may round or clamp the requested size; use
+    /// [`send_buffer_size`](Self::send_buffer_size) to read back what was
+    /// applied.
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        SockRef::from(&self.socket).set_send_buffer_size(size)
+    }
+
+    /// Returns the size of the socket's send buffer (`SO_SNDBUF`).
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        SockRef::from(&self.socket).send_buffer_size()
+    }
+
+    /// Sets the size of the socket's receive buffer (`SO_RCVBUF`).
+    ///
+    /// The OS may round or clamp the requested size; use
+    /// [`recv_buffer_size`](Self::recv_buffer_size) to read back what was
+    /// applied.
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        SockRef::from(&self.socket).set_recv_buffer_size(size)
+    }
+
+    /// Returns the size of the socket's receive buffer (`SO_RCVBUF`).
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        SockRef::from(&self.socket).recv_buffer_size()
+    }
+
+    /// Sets the traffic class of outgoing datagrams: the DSCP and ECN bits
+    /// of `IP_TOS` for IPv4 peers, or of `IPV6_TCLASS` for IPv6 peers.
+    ///
+    /// The option is picked from the peer's address family, so callers don't
+    /// need to branch; a dual-stack socket talking to an IPv4-mapped peer
+    /// uses `IP_TOS`. Setting `IPV6_TCLASS` isn't supported on Windows.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  // Expedited Forwarding.
+    ///  conn_client.set_traffic_class(0xb8).expect("couldn't set traffic class");
+    /// # }
+    /// ```
+    pub fn set_traffic_class(&self, tc: u8) -> io::Result<()> {
+        if self.peer_is_ipv4() {
+            SockRef::from(&self.socket).set_tos_v4(u32::from(tc))
+        } else {
+            sys::set_tclass_v6(&self.socket, tc)
+        }
+    }
+
+    /// Returns the traffic class of outgoing datagrams (`IP_TOS` or
+    /// `IPV6_TCLASS`).
+    ///
+    /// See [`set_traffic_class`](Self::set_traffic_class) for details.
+    pub fn traffic_class(&self) -> io::Result<u8> {
+        if self.peer_is_ipv4() {
+            Ok(SockRef::from(&self.socket).tos_v4()? as u8)
+        } else {
+            sys::tclass_v6(&self.socket)
+        }
+    }
+
+    /// Returns whether traffic to the peer is IPv4, including through an
+    /// IPv4-mapped address.
+    fn peer_is_ipv4(&self) -> bool {
+        match self.peer {
+            SocketAddr::V4(_) => true,
+            SocketAddr::V6(v6) => v6.ip().to_ipv4_mapped().is_some(),
+        }
+    }
+
+    /// Sizes the send and receive buffers to the bandwidth-delay product of
+    /// a link with the given bandwidth (in bits per second) and round-trip
+    /// time, returning the `(send, recv)` buffer sizes actually applied.
+    ///
+    /// A buffer that holds a full bandwidth-delay product lets a sender keep
+    /// the link busy for a whole round trip. The OS clamps the sizes to its
+    /// configured maximum (e.g. `net.core.wmem_max` on Linux), so the applied
+    /// sizes can be smaller than requested; Linux also doubles the requested
+    /// size to account for bookkeeping overhead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  // 100 Mbit/s with a 20ms round trip.
+    ///  let (send, recv) = conn_client
+    ///      .tune_buffers(100_000_000, Duration::from_millis(20))
+    ///      .expect("couldn't tune buffers");
+    ///  println!("send buffer: {}, recv buffer: {}", send, recv);
+    /// # }
+    /// ```
+    pub fn tune_buffers(
+        &self,
+        target_bandwidth_bps: u64,
+        rtt: Duration,
+    ) -> io::Result<(usize, usize)> {
+        let bdp = u128::from(target_bandwidth_bps) * rtt.as_nanos() / 8 / 1_000_000_000;
+        let bdp = usize::try_from(bdp).unwrap_or(usize::MAX);
+        // `setsockopt` takes an `int`, so anything larger can't be requested.
+        let bdp = bdp.min(i32::MAX as usize);
+
+        self.set_send_buffer_size(bdp)?;
+        self.set_recv_buffer_size(bdp)?;
+        Ok((self.send_buffer_size()?, self.recv_buffer_size()?))
+    }
+
+    /// Sizes the receive buffer so that roughly `count` datagrams of
+    /// `typical_size` bytes can queue before the OS starts dropping them,
+    /// returning the buffer size actually applied.
+    ///
+    /// This bounds how much memory (and how much stale data) a slow receiver
+    /// can build up. It's a heuristic: the buffer is set to
+    /// `count * typical_size`, but the OS also charges each datagram for its
+    /// bookkeeping overhead, rounds the size, and clamps it to its configured
+    /// minimum and maximum, so the real limit varies with datagram size and
+    /// platform.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `count` or
+    /// `typical_size` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  // Keep at most about 64 full-size Ethernet datagrams around.
+    ///  let applied = conn_client
+    ///      .limit_queued_datagrams(64, 1472)
+    ///      .expect("couldn't size receive buffer");
+    ///  println!("recv buffer: {}", applied);
+    /// # }
+    /// ```
+    pub fn limit_queued_datagrams(&self, count: usize, typical_size: usize) -> io::Result<usize> {
+        if count == 0 || typical_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram count and size must be non-zero",
+            ));
+        }
+        // `setsockopt` takes an `int`, so anything larger can't be requested.
+        let size = count.saturating_mul(typical_size).min(i32::MAX as usize);
+        self.set_recv_buffer_size(size)?;
+        self.recv_buffer_size()
+    }
+
+    /// Returns the OS's current estimate of the path MTU to the peer, in
+    /// bytes, including IP and UDP headers.
+    ///
+    /// The estimate starts at the outgoing interface's MTU and shrinks as
+    /// the kernel learns about smaller links along the path, e.g. from ICMP
+    /// "fragmentation needed" messages.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] on platforms
+    /// other than Linux.
+    pub fn path_mtu(&self) -> io::Result<usize> {
+        sys::path_mtu(&self.socket)
+    }
+
+    /// Returns whether a datagram with a `payload_len`-byte payload would
+    /// exceed the current [`path_mtu`](Self::path_mtu) once IP and UDP
+    /// headers are added, and so would be fragmented (or rejected, with DF
+    /// set).
+    ///
+    /// This lets callers split large messages up front. The header overhead
+    /// assumes no IP options or IPv6 extension headers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] where the
+    /// path MTU isn't available.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  match conn_client.will_fragment(1200) {
+    ///      Ok(fragments) => println!("1200 bytes would fragment: {}", fragments),
+    ///      Err(e) => println!("path MTU unavailable: {}", e),
+    ///  }
+    /// # }
+    /// ```
+    pub fn will_fragment(&self, payload_len: usize) -> io::Result<bool> {
+        let mtu = self.path_mtu()?;
+        Ok(payload_len.saturating_add(self.ip_header_len() + 8) > mtu)
+    }
+
+    /// Returns the size of the IP header on datagrams to the peer, assuming
+    /// no IP options or IPv6 extension headers.
+    pub(crate) fn ip_header_len(&self) -> usize {
+        match self.peer {
+            SocketAddr::V6(peer) if peer.ip().to_ipv4_mapped().is_none() => 40,
+            _ => 20,
+        }
+    }
+
+    /// Sends data through the underlying socket.
+    ///
+    /// In blocking mode without a write timeout, this blocks until the
+    /// datagram is sent: if the OS reports a momentarily full send buffer
+    /// with [`io::ErrorKind::WouldBlock`], the socket is polled for
+    /// writability and the send retried. In nonblocking mode, or once a write
+    /// timeout expires, `WouldBlock` is returned as usual.
+    ///
+    /// A nonempty `buf` that the OS reports sending zero bytes of fails with
+    /// an error of kind [`io::ErrorKind::WriteZero`], rather than returning
+    /// `Ok(0)` to a caller that would likely retry forever. An empty `buf`
+    /// sends an empty datagram and returns `Ok(0)`.
+    /// # Examples
+    ///
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.ensure_peer()?;
+        loop {
+            match self.send_raw(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock && self.blocks_indefinitely() => {
+                    match sys::poll(&self.socket, Interest::Writable, None) {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                res => {
+                    let n = check_sent(res?, buf.len())?;
+                    self.stats.record_send(1, n);
+                    return Ok(n);
+                }
+            }
+        }
+    }
+
+    /// Sends the contents of several buffers as a single datagram, returning
+    /// the number of bytes sent.
+    ///
+    /// This gathers the buffers in one syscall, avoiding copying them into a
+    /// contiguous buffer first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::io::IoSlice;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  conn_client
+    ///      .send_vectored(&[IoSlice::new(b"pi"), IoSlice::new(b"ng")])
+    ///      .expect("couldn't send datagram");
+    ///
+    ///  let mut buf = [0; 32];
+    ///  let n = host.recv(&mut buf).expect("couldn't receive datagram");
+    ///  assert_eq!(&buf[..n], b"ping");
+    /// # }
+    /// ```
+    pub fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.ensure_peer()?;
+        // The payload has to be copied to be scrambled anyway.
+        if self.options.obfuscation_key.is_some() {
+            let datagram: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+            let n = self.send_raw(&datagram)?;
+            self.stats.record_send(1, n);
+            return Ok(n);
+        }
+        #[cfg(feature = "socks5")]
+        let n = match &self.socks5 {
+            Some(socks5) => socks5.send_vectored(&self.socket, bufs)?,
+            None => SockRef::from(&self.socket).send_vectored(bufs)?,
+        };
+        #[cfg(not(feature = "socks5"))]
+        let n = SockRef::from(&self.socket).send_vectored(bufs)?;
+        self.stats.record_send(1, n);
+        Ok(n)
+    }
+
+    /// Sends `buf` as one datagram, obfuscated if the socket was built with
+    /// a key, and through the SOCKS5 relay if there is one.
+    fn send_raw(&self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(key) = &self.options.obfuscation_key {
+            let mut scrambled = buf.to_vec();
+            xor_with_key(&mut scrambled, key);
+            return self.send_unobfuscated(&scrambled);
+        }
+        self.send_unobfuscated(buf)
+    }
+
+    /// Sends `buf` as is, through the SOCKS5 relay if there is one.
+    fn send_unobfuscated(&self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "socks5")]
+        if let Some(socks5) = &self.socks5 {
+            return socks5.send(&self.socket, buf);
+        }
+        self.socket.send(buf)
+    }
+
+    /// Receives one datagram, through the SOCKS5 relay if there is one, and
+    /// reverses any obfuscation.
+    fn recv_raw(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(n) = self.recv_raw_once(buf)? {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Receives a single datagram like [`recv_raw`](Self::recv_raw), but
+    /// returns `None` instead of waiting for another one if the SOCKS5 relay
+    /// forwarded one that has to be discarded.
+    fn recv_raw_once(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        #[cfg(feature = "socks5")]
+        let n = match &self.socks5 {
+            Some(socks5) => match socks5.recv(&self.socket, buf)? {
+                Some(n) => n,
+                None => return Ok(None),
+            },
+            None => self.socket.recv(buf)?,
+        };
+        #[cfg(not(feature = "socks5"))]
+        let n = self.socket.recv(buf)?;
+        if let Some(key) = &self.options.obfuscation_key {
+            xor_with_key(&mut buf[..n], key);
+        }
+        Ok(Some(n))
+    }
+
+    /// Sends `header` followed by `payload` as a single datagram, returning
+    /// the number of bytes sent.
+    ///
+    /// The two are gathered with [`send_vectored`](Self::send_vectored), so
+    /// no buffer is allocated to concatenate them.
+    pub fn send_with_header<H: AsBytes + ?Sized>(
+        &self,
+        header: &H,
+        payload: &[u8],
+    ) -> io::Result<usize> {
+        self.send_vectored(&[IoSlice::new(header.as_bytes()), IoSlice::new(payload)])
+    }
+
+    /// Sends a single datagram to `addr` instead of the peer, leaving the
+    /// connection untouched.
+    ///
+    /// Calling `send_to` on a connected socket behaves differently across
+    /// platforms: Linux sends to the given address, macOS and the BSDs fail
+    /// with `EISCONN`, and Windows may send to the connected peer instead. To
+    /// behave the same everywhere, this sends from a transient unconnected
+    /// socket, so the datagram arrives from a different (ephemeral) source
+    /// port than the connected socket's. Replies to it won't reach this
+    /// socket.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///  let other = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to other address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  conn_client
+    ///      .send_once_to(b"notice", other.local_addr().unwrap())
+    ///      .expect("couldn't send datagram");
+    ///
+    ///  let mut buf = [0; 32];
+    ///  let n = other.recv(&mut buf).expect("couldn't receive datagram");
+    ///  assert_eq!(&buf[..n], b"notice");
+    /// # }
+    /// ```
+    pub fn send_once_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let unspecified: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let transient = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+        transient.set_write_timeout(self.socket.write_timeout()?)?;
+        transient.send_to(buf, addr)
+    }
+
+    /// Sends `payload` to the peer and waits for a reply that `is_ack`
+    /// accepts, resending it up to `retries` times.
+    ///
+    /// Each send waits up to `timeout` for the acknowledgement; replies that
+    /// `is_ack` rejects are discarded. This gives simple reliability on top of
+    /// a custom acknowledgement format. The socket's read and write timeouts
+    /// are restored afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::TimedOut`] if no
+    /// acknowledgement arrives after the last send.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use connected_udp::{spawn_echo_server, ConnectedUdpSocket};
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let (echo_addr, _echo) = spawn_echo_server()?;
+    ///
+    ///     let client = UdpSocket::bind("127.0.0.1:0")?;
+    ///     let conn_client = ConnectedUdpSocket::connect(client, echo_addr)?;
+    ///
+    ///     let ack = conn_client.send_until_ack(
+    ///         b"hello",
+    ///         |reply| reply == b"hello",
+    ///         3,
+    ///         Duration::from_millis(200),
+    ///     )?;
+    ///     assert_eq!(ack, b"hello");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn send_until_ack(
+        &self,
+        payload: &[u8],
+        is_ack: impl Fn(&[u8]) -> bool,
+        retries: u32,
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        self.restoring_timeouts(|conn| {
+            let mut buf = vec![0; MAX_REPLY_LEN];
+            for _ in 0..=retries {
+                let deadline = Instant::now() + timeout;
+                conn.socket.set_write_timeout(Some(remaining(deadline)?))?;
+                conn.send(payload).map_err(timed_out)?;
+
+                while let Ok(left) = remaining(deadline) {
+                    conn.socket.set_read_timeout(Some(left))?;
+                    match conn.recv(&mut buf) {
+                        Ok(n) if is_ack(&buf[..n]) => return Ok(buf[..n].to_vec()),
+                        Ok(_) => continue,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            break
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "peer didn't acknowledge the datagram",
+            ))
+        })
+    }
+
+    /// Sends `probe` every `probe_interval` until the peer replies, giving up
+    /// after `total_timeout`.
+    ///
+    /// This is for startup ordering, e.g. a client that may start before its
+    /// server: unlike [`connect_with_probe`](Self::connect_with_probe), which
+    /// sends a single probe, it keeps trying while the peer comes up. Any
+    /// reply counts, and is consumed and discarded. Errors reporting that
+    /// nothing is listening yet (ICMP port unreachable) are treated as "not
+    /// yet" rather than failures. The socket's read and write timeouts are
+    /// restored before returning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::TimedOut`] if the peer
+    /// doesn't reply in time, and of kind [`io::ErrorKind::InvalidInput`] if
+    /// `probe_interval` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use connected_udp::{spawn_echo_server, ConnectedUdpSocket};
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let (echo_addr, _echo) = spawn_echo_server()?;
+    ///
+    ///     let client = UdpSocket::bind("127.0.0.1:0")?;
+    ///     let conn_client = ConnectedUdpSocket::connect(client, echo_addr)?;
+    ///     conn_client.wait_until_reachable(
+    ///         b"hello?",
+    ///         Duration::from_secs(5),
+    ///         Duration::from_millis(100),
+    ///     )?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn wait_until_reachable(
+        &self,
+        probe: &[u8],
+        total_timeout: Duration,
+        probe_interval: Duration,
+    ) -> io::Result<()> {
+        if probe_interval.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "probe interval must be non-zero",
+            ));
+        }
+        let deadline = Instant::now() + total_timeout;
+        self.restoring_timeouts(|conn| {
+            let mut buf = vec![0; MAX_REPLY_LEN];
+            while let Ok(left) = remaining(deadline) {
+                conn.socket.set_write_timeout(Some(left))?;
+                match conn.send(probe) {
+                    Ok(_) => {}
+                    Err(e) if is_unreachable(&e) => {}
+                    Err(e) => return Err(timed_out(e)),
+                }
+
+                let next_probe = (Instant::now() + probe_interval).min(deadline);
+                while let Ok(left) = remaining(next_probe) {
+                    conn.socket.set_read_timeout(Some(left))?;
+                    match conn.recv(&mut buf) {
+                        Ok(_) => return Ok(()),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        // Nothing's listening yet, so hold off until the next
+                        // probe rather than spinning on the error.
+                        Err(e) if is_unreachable(&e) => {
+                            std::thread::sleep(
+                                next_probe.saturating_duration_since(Instant::now()),
+                            );
+                            break;
+                        }
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            break
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "peer didn't become reachable",
+            ))
+        })
+    }
+
+    /// Returns whether sends on this socket are expected to block until they
+    /// complete, i.e. it's in blocking mode with no write timeout.
+    fn blocks_indefinitely(&self) -> bool {
+        matches!(self.is_nonblocking(), Ok(false))
+            && matches!(self.socket.write_timeout(), Ok(None))
+    }
+
+    /// Receives data from the socket and writes it into the provided buffer.
+    ///
+    /// Zero-length datagrams are handled according to the socket's
+    /// [`EmptyDatagramPolicy`]; by default they're returned as `Ok(0)`.
+    ///
+    /// With a read timeout set, a signal arriving mid-receive doesn't cut the
+    /// wait short: the receive resumes waiting for whatever remains of the
+    /// timeout instead of returning [`io::ErrorKind::Interrupted`].
+    /// # Examples
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.ensure_peer()?;
+        let started = Instant::now();
+        loop {
+            let n = match self.recv_raw(buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    self.resume_after_interrupt(e, started)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some(n) = self.check_empty(n, buf.len())? {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Handles a receive that started at `started` being interrupted by a
+    /// signal, by waiting out the rest of the read timeout for a datagram.
+    ///
+    /// Returns `Ok(())` once the receive should be retried, or the OS's usual
+    /// timeout error if the timeout runs out first. Without a read timeout,
+    /// `interrupted` is returned as is.
+    fn resume_after_interrupt(&self, interrupted: io::Error, started: Instant) -> io::Result<()> {
+        let Some(timeout) = self.socket.read_timeout()? else {
+            return Err(interrupted);
+        };
+        let deadline = started + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(sys::recv_timed_out());
+            }
+            match sys::poll(&self.socket, Interest::Readable, Some(left)) {
+                Ok(true) => return Ok(()),
+                Ok(false) => return Err(sys::recv_timed_out()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Receives a datagram like [`recv`](Self::recv), but also checks that it
+    /// came from the peer, discarding any that didn't.
+    ///
+    /// A connected socket normally only delivers datagrams from its peer, but
+    /// datagrams that were queued before the socket was connected (or
+    /// reconnected) are still delivered, whatever their source. Use this when
+    /// that matters, e.g. right after [`reconnect`](Self::reconnect). On a
+    /// socket relayed through SOCKS5, the source is the one the relay reports
+    /// for each datagram, so this behaves like [`recv`](Self::recv).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::TooManyForeignDatagrams`] (reported as
+    /// [`io::ErrorKind::InvalidData`]) once more than `max_discards`
+    /// datagrams from other addresses have been discarded, so a flood of
+    /// spoofed datagrams can't keep the caller looping forever.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  host.send_to(b"pong", conn_client.local_addr().unwrap()).expect("couldn't send datagram");
+    ///
+    ///  let mut buf = [0; 4];
+    ///  let n = conn_client.recv_verified(&mut buf, 16).expect("couldn't receive datagram");
+    ///  assert_eq!(&buf[..n], b"pong");
+    /// # }
+    /// ```
+    pub fn recv_verified(&self, buf: &mut [u8], max_discards: usize) -> io::Result<usize> {
+        self.ensure_peer()?;
+        // Every datagram comes from the relay, which tags it with its actual
+        // source; the association already discards those not from the peer.
+        #[cfg(feature = "socks5")]
+        if self.socks5.is_some() {
+            return self.recv(buf);
+        }
+        let mut discarded = 0;
+        loop {
+            let (n, from) = self.socket.recv_from(buf)?;
+            if !self.matches_peer(from) {
+                if discarded == max_discards {
+                    return Err(Error::TooManyForeignDatagrams.into());
+                }
+                discarded += 1;
+                continue;
+            }
+            if let Some(key) = &self.options.obfuscation_key {
+                xor_with_key(&mut buf[..n], key);
+            }
+            if let Some(n) = self.check_empty(n, buf.len())? {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Receives a datagram from any address, adopting its source as the new
+    /// peer if it differs from the current one, and returns whether it did.
+    ///
+    /// This follows a peer whose address changes mid-conversation, e.g. when
+    /// a NAT between the two rebinds the peer to a new port. The OS-level
+    /// connection filters out datagrams from other addresses, so it's
+    /// dissolved while waiting and re-established afterwards, to the new
+    /// source or (on error) the old peer. That means a datagram from a new
+    /// address is only seen if it arrives while this is waiting, so use it
+    /// as the socket's main receive call rather than an occasional one.
+    /// While it's waiting, clones of the socket can't send, and without a
+    /// read timeout it waits indefinitely.
+    ///
+    /// On Linux, dissolving the connection also releases a local port the OS
+    /// picked, which is claimed again right away. If another socket binds it
+    /// in between, this fails and the socket is connected back to the peer
+    /// from a new port, so bind to an explicit port where that matters.
+    ///
+    /// Any host that can reach the socket can take over the connection this
+    /// way, so only use this where that's acceptable, e.g. when the payload
+    /// is authenticated.
+    ///
+    /// With [`Builder::match_peer_by_ip_only`], a source that only differs
+    /// from the peer in port is still adopted, but isn't reported as a
+    /// change.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if the socket
+    /// relays through SOCKS5, along with any error from receiving.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let client = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to client address");
+    ///  let mut conn_client = ConnectedUdpSocket::connect(client, "192.0.2.1:4433".parse().unwrap())
+    ///      .expect("couldn't client to host");
+    ///
+    ///  let mut buf = [0; 1500];
+    ///  loop {
+    ///      let (n, changed) = conn_client.recv_tracking_peer(&mut buf).expect("couldn't receive datagram");
+    ///      if changed {
+    ///          println!("peer moved to {}", conn_client.peer_addr());
+    ///      }
+    ///      println!("received {} bytes", n);
+    ///  }
+    /// # }
+    /// ```
+    pub fn recv_tracking_peer(&mut self, buf: &mut [u8]) -> io::Result<(usize, bool)> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        if let Err(e) = sys::disconnect(&self.socket) {
+            // The socket may have been reconnected on a new port.
+            self.local = OnceLock::new();
+            return Err(e);
+        }
+        let res = self.recv_unfiltered(buf);
+
+        // A source that only differs in port still matches when matching by
+        // IP alone, but the connection follows it all the same.
+        let (moved, changed) = match &res {
+            Ok((_, from)) => (
+                canonicalize(*from, &self.options) != self.peer,
+                !self.matches_peer(*from),
+            ),
+            Err(_) => (false, false),
+        };
+        match &res {
+            Ok((_, from)) if moved => {
+                if let Err(e) = self.reconnect(*from) {
+                    // Rather stay with the old peer than stay disconnected.
+                    let _ = connect_socket(&self.socket, self.peer, &self.options);
+                    return Err(e);
+                }
+            }
+            _ => {
+                connect_socket(&self.socket, self.peer, &self.options)?;
+            }
+        }
+        let (n, _) = res?;
+        Ok((n, changed))
+    }
+
+    /// Receives a datagram from any address, returning the number of bytes
+    /// read and the source, without changing the peer.
+    ///
+    /// A connected socket only delivers datagrams from its peer, so this is
+    /// for diagnostics that need to look past that for a single read, e.g.
+    /// to capture a rogue sender. The OS-level connection is dissolved while
+    /// waiting and re-established to the peer afterwards. That leaves a race
+    /// window: while this waits, clones of the socket can't send, and
+    /// datagrams from other addresses that arrive after it returns but before
+    /// the socket is reconnected stay queued, so plain receives can still
+    /// return them later (see [`recv_verified`](Self::recv_verified)).
+    /// Without a read timeout, the wait (and with it the window) lasts until
+    /// a datagram arrives.
+    ///
+    /// On Linux, dissolving the connection also releases a local port the OS
+    /// picked, which is claimed again right away. If another socket binds it
+    /// in between, this fails and the socket is connected back to the peer
+    /// from a new port, so bind to an explicit port where that matters. The
+    /// socket is reconnected to the peer whether or not receiving succeeds,
+    /// though [`fingerprint`](Self::fingerprint) keeps the old local address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if the socket
+    /// relays through SOCKS5, along with any error from receiving.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let client = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, "192.0.2.1:4433".parse().unwrap())
+    ///      .expect("couldn't client to host");
+    ///
+    ///  let mut buf = [0; 1500];
+    ///  let (n, from) = conn_client.recv_from_any(&mut buf).expect("couldn't receive datagram");
+    ///  if from != conn_client.peer_addr() {
+    ///      println!("{} bytes from unexpected sender {}", n, from);
+    ///  }
+    /// # }
+    /// ```
+    pub fn recv_from_any(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        sys::disconnect(&self.socket)?;
+        let res = self.recv_unfiltered(buf);
+        connect_socket(&self.socket, self.peer, &self.options)?;
+        res
+    }
+
+    /// Receives a datagram and its source on a disconnected socket, applying
+    /// the empty datagram policy.
+    fn recv_unfiltered(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            let (n, from) = self.socket.recv_from(buf)?;
+            if let Some(key) = &self.options.obfuscation_key {
+                xor_with_key(&mut buf[..n], key);
+            }
+            if let Some(n) = self.check_empty(n, buf.len())? {
+                return Ok((n, from));
+            }
+        }
+    }
+
+    /// Returns whether a datagram received from `addr` came from the peer.
+    ///
+    /// Only the IP address is compared if the socket was built with
+    /// [`Builder::match_peer_by_ip_only`].
+    fn matches_peer(&self, addr: SocketAddr) -> bool {
+        let addr = canonicalize(addr, &self.options);
+        if self.options.match_peer_by_ip_only {
+            return addr.ip() == self.peer.ip();
+        }
+        addr == self.peer
+    }
+
+    /// Applies the socket's obfuscation key, if any, to `buf`, which holds
+    /// consecutive datagrams of `segment_size` bytes (the last may be
+    /// shorter). Applying it twice restores the original bytes.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn obfuscate_segments(&self, buf: &mut [u8], segment_size: usize) {
+        if let Some(key) = &self.options.obfuscation_key {
+            for datagram in buf.chunks_mut(segment_size.max(1)) {
+                xor_with_key(datagram, key);
+            }
+        }
+    }
+
+    /// Returns whether payloads are obfuscated, i.e. whether raw buffers need
+    /// to go through [`obfuscate_segments`](Self::obfuscate_segments).
+    #[cfg(target_os = "linux")]
+    pub(crate) fn is_obfuscated(&self) -> bool {
+        self.options.obfuscation_key.is_some()
+    }
+
+    /// Returns the clock tracking when a datagram was last received.
+    pub(crate) fn recv_clock(&self) -> &RecvClock {
+        &self.recv_clock
+    }
+
+    /// Returns the socket's traffic counters.
+    pub(crate) fn stats_counters(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Applies the empty datagram policy to a datagram of `n` bytes received
+    /// into a `buf_len`-byte buffer, returning `None` if it should be skipped.
+    ///
+    /// Every datagram received from the peer passes through here, so this
+    /// also records the receive time and counts the datagram.
+    fn check_empty(&self, n: usize, buf_len: usize) -> io::Result<Option<usize>> {
+        self.recv_clock.touch();
+        self.stats.record_recv(1, n);
+        // An empty buffer can't tell empty datagrams apart from others.
+        if n > 0 || buf_len == 0 {
+            return Ok(Some(n));
+        }
+        match self.options.empty_datagrams {
+            EmptyDatagramPolicy::ReturnZero => Ok(Some(0)),
+            EmptyDatagramPolicy::Skip => Ok(None),
+            EmptyDatagramPolicy::Error => Err(Error::EmptyDatagram.into()),
+        }
+    }
+
+    /// Sends the contents of `buf` through the underlying socket.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use bytes::Bytes;
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  conn_client.send_bytes(&Bytes::from_static(b"ping")).expect("couldn't send datagram");
+    /// # }
+    /// ```
+    #[cfg(feature = "bytes")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bytes")))]
+    pub fn send_bytes(&self, buf: &bytes::Bytes) -> io::Result<usize> {
+        self.send(buf)
+    }
+
+    /// Receives a single datagram of up to `capacity` bytes into a freshly
+    /// allocated [`Bytes`](bytes::Bytes).
+    ///
+    /// As with [`recv`](Self::recv), a datagram longer than `capacity` is
+    /// truncated.
+    #[cfg(feature = "bytes")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bytes")))]
+    pub fn recv_bytes(&self, capacity: usize) -> io::Result<bytes::Bytes> {
+        let mut buf = bytes::BytesMut::zeroed(capacity);
+        let n = self.recv(&mut buf)?;
+        buf.truncate(n);
+        Ok(buf.freeze())
+    }
+
+    /// Receives data from the socket into a possibly-uninitialized buffer,
+    /// returning the number of bytes received.
+    ///
+    /// This avoids zeroing the buffer before every receive in hot loops. Only
+    /// the first `n` bytes of `buf` are initialized when this returns
+    /// `Ok(n)`; the rest must still be treated as uninitialized.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if the socket
+    /// relays through SOCKS5, along with any error from receiving.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::mem::MaybeUninit;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  host.send_to(b"ping", conn_client.local_addr().unwrap()).expect("couldn't send datagram");
+    ///
+    ///  let mut buf = [MaybeUninit::<u8>::uninit(); 32];
+    ///  let n = conn_client.recv_uninit(&mut buf).expect("couldn't receive datagram");
+    ///  // SAFETY: `recv_uninit` initialized the first `n` bytes.
+    ///  let data = unsafe { std::slice::from_raw_parts(buf.as_ptr().cast::<u8>(), n) };
+    ///  assert_eq!(data, b"ping");
+    /// # }
+    /// ```
+    pub fn recv_uninit(&self, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        loop {
+            let n = SockRef::from(&self.socket).recv(buf)?;
+            if let Some(key) = &self.options.obfuscation_key {
+                // SAFETY: the receive initialized the first `n` bytes.
+                let data =
+                    unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), n) };
+                xor_with_key(data, key);
+            }
+            if let Some(n) = self.check_empty(n, buf.len())? {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Receives a single datagram into a stack-allocated `N`-byte array,
+    /// returning the array along with the number of bytes received.
+    ///
+    /// As with [`recv`](Self::recv), a datagram longer than `N` bytes is
+    /// truncated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  host.send_to(b"ping", conn_client.local_addr().unwrap()).expect("couldn't send datagram");
+    ///
+    ///  let (buf, n) = conn_client.recv_array::<16>().expect("couldn't receive datagram");
+    ///  assert_eq!(&buf[..n], b"ping");
+    /// # }
+    /// ```
+    pub fn recv_array<const N: usize>(&self) -> io::Result<([u8; N], usize)> {
+        let mut buf = [0; N];
+        let n = self.recv(&mut buf)?;
+        Ok((buf, n))
+    }
+
+    /// Receives a single datagram of up to `max` bytes and appends it to
+    /// `ring`, returning the number of bytes added.
+    ///
+    /// This lets streaming consumers accumulate data across receives without
+    /// allocating once `ring` has grown to its working size. Datagram
+    /// boundaries aren't preserved: consecutive datagrams run together in
+    /// `ring`, so only use this when the payload is a byte stream or frames
+    /// itself. A datagram longer than `max` bytes is truncated. The deque may
+    /// be rearranged in place to receive into contiguous memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::collections::VecDeque;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  host.send_to(b"ping", conn_client.local_addr().unwrap()).expect("couldn't send datagram");
+    ///
+    ///  let mut ring = VecDeque::with_capacity(4096);
+    ///  let n = conn_client.recv_into_ring(&mut ring, 1500).expect("couldn't receive datagram");
+    ///  assert_eq!(n, 4);
+    ///  assert!(ring.iter().eq(b"ping"));
+    /// # }
+    /// ```
+    pub fn recv_into_ring(&self, ring: &mut VecDeque<u8>, max: usize) -> io::Result<usize> {
+        let start = ring.len();
+        ring.resize(start + max, 0);
+        let res = self.recv(&mut ring.make_contiguous()[start..]);
+        ring.truncate(start + *res.as_ref().unwrap_or(&0));
+        res
+    }
+
+    /// Receives a single datagram into a buffer obtained from `alloc`,
+    /// returning the buffer truncated to the datagram's length.
+    ///
+    /// `alloc` is called once with `capacity` and should return a buffer
+    /// that can hold at least that many bytes, e.g. one drawn from a pool or
+    /// arena. It may return an empty buffer with enough spare capacity, as
+    /// [`Vec::with_capacity`] does; the buffer is zero-filled up to
+    /// `capacity` before receiving, which only reallocates if the buffer
+    /// falls short. A datagram longer than `capacity` is truncated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  host.send_to(b"ping", conn_client.local_addr().unwrap()).expect("couldn't send datagram");
+    ///
+    ///  let datagram = conn_client
+    ///      .recv_with(1500, Vec::with_capacity)
+    ///      .expect("couldn't receive datagram");
+    ///  assert_eq!(datagram, b"ping");
+    /// # }
+    /// ```
+    pub fn recv_with<F: FnOnce(usize) -> Vec<u8>>(
+        &self,
+        capacity: usize,
+        alloc: F,
+    ) -> io::Result<Vec<u8>> {
+        let mut buf = alloc(capacity);
+        if buf.len() < capacity {
+            buf.resize(capacity, 0);
+        }
+        let n = self.recv(&mut buf[..capacity])?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Receives every datagram that's already queued, without waiting for
+    /// more, returning them in arrival order.
+    ///
+    /// Each datagram is read into a buffer of `max_per_datagram` bytes, so
+    /// longer ones are truncated. The socket is switched to nonblocking mode
+    /// for the duration of the call and then restored (see
+    /// [`is_nonblocking`](Self::is_nonblocking) for how the mode is known on
+    /// Windows).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let batch = conn_client.recv_all_available(1500).expect("couldn't receive datagrams");
+    ///  assert!(batch.is_empty());
+    /// # }
+    /// ```
+    pub fn recv_all_available(&self, max_per_datagram: usize) -> io::Result<Vec<Vec<u8>>> {
+        self.ensure_peer()?;
+        let was_nonblocking = self.is_nonblocking()?;
+        if !was_nonblocking {
+            self.set_nonblocking(true)?;
+        }
+        let res = self.recv_queued(max_per_datagram);
+        let restored = if was_nonblocking {
+            Ok(())
+        } else {
+            self.set_nonblocking(false)
+        };
+        let datagrams = res?;
+        restored?;
+        Ok(datagrams)
+    }
+
+    /// Receives up to `n` datagrams of at most `max_per` bytes each, stopping
+    /// early once `deadline` passes, and returns them in arrival order.
+    ///
+    /// This suits protocols that expect a known number of response
+    /// datagrams. Running out of time isn't an error: whatever arrived by
+    /// then is returned, so compare the result's length with `n` to tell if
+    /// some are missing, or use [`recv_n_exact`](Self::recv_n_exact) to get
+    /// an error instead. Longer datagrams are truncated. The socket's read
+    /// timeout is restored before returning. A nonblocking socket waits for
+    /// datagrams until the deadline too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::{Duration, Instant};
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let client_addr = conn_client.local_addr().unwrap();
+    ///  host.send_to(b"one", client_addr).expect("couldn't send datagram");
+    ///  host.send_to(b"two", client_addr).expect("couldn't send datagram");
+    ///
+    ///  let deadline = Instant::now() + Duration::from_millis(100);
+    ///  let datagrams = conn_client.recv_n(3, 1500, deadline).expect("couldn't receive datagrams");
+    ///  assert_eq!(datagrams, [b"one", b"two"]);
+    /// # }
+    /// ```
+    pub fn recv_n(&self, n: usize, max_per: usize, deadline: Instant) -> io::Result<Vec<Vec<u8>>> {
+        self.ensure_peer()?;
+        self.restoring_timeouts(|conn| {
+            let mut datagrams = Vec::with_capacity(n);
+            while datagrams.len() < n {
+                let Ok(left) = remaining(deadline) else {
+                    break;
+                };
+                conn.socket.set_read_timeout(Some(left))?;
+                let mut buf = vec![0; max_per];
+                match conn.recv(&mut buf) {
+                    Ok(len) => {
+                        buf.truncate(len);
+                        datagrams.push(buf);
+                    }
+                    // The timeout may expire slightly early, so check the
+                    // deadline again. A nonblocking socket ignores the
+                    // timeout, so wait for a datagram here instead of
+                    // spinning.
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        let Ok(left) = remaining(deadline) else {
+                            break;
+                        };
+                        match sys::poll(&conn.socket, Interest::Readable, Some(left)) {
+                            Ok(_) => {}
+                            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(datagrams)
+        })
+    }
+
+    /// Receives exactly `n` datagrams of at most `max_per` bytes each,
+    /// failing if they don't all arrive by `deadline`.
+    ///
+    /// This is [`recv_n`](Self::recv_n) for callers that treat a missing
+    /// datagram as a failure, so they don't have to check the length
+    /// themselves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::TimedOut`] if fewer than `n`
+    /// datagrams arrive in time. The ones that did arrive are discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::io;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::{Duration, Instant};
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let client_addr = conn_client.local_addr().unwrap();
+    ///  host.send_to(b"one", client_addr).expect("couldn't send datagram");
+    ///
+    ///  let deadline = Instant::now() + Duration::from_millis(100);
+    ///  let err = conn_client.recv_n_exact(2, 1500, deadline).unwrap_err();
+    ///  assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    /// # }
+    /// ```
+    pub fn recv_n_exact(
+        &self,
+        n: usize,
+        max_per: usize,
+        deadline: Instant,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let datagrams = self.recv_n(n, max_per, deadline)?;
+        if datagrams.len() < n {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("only {} of {n} datagrams arrived in time", datagrams.len()),
+            ));
+        }
+        Ok(datagrams)
+    }
+
+    /// Receives datagrams until the socket reports `WouldBlock`.
+    fn recv_queued(&self, max_per_datagram: usize) -> io::Result<Vec<Vec<u8>>> {
+        let mut datagrams = Vec::new();
+        loop {
+            let mut buf = vec![0; max_per_datagram];
+            match self.recv(&mut buf) {
+                Ok(n) => {
+                    buf.truncate(n);
+                    datagrams.push(buf);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(datagrams),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Sends `v` as a single 4-byte datagram in big-endian (network) byte
+    /// order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  conn_client.send_u32_be(0xdead_beef).expect("couldn't send value");
+    ///
+    ///  let mut buf = [0; 4];
+    ///  host.recv(&mut buf).expect("couldn't receive value");
+    ///  assert_eq!(buf, [0xde, 0xad, 0xbe, 0xef]);
+    /// # }
+    /// ```
+    pub fn send_u32_be(&self, v: u32) -> io::Result<usize> {
+        self.send(&v.to_be_bytes())
+    }
+
+    /// Sends `v` as a single 2-byte datagram in big-endian (network) byte
+    /// order.
+    pub fn send_u16_be(&self, v: u16) -> io::Result<usize> {
+        self.send(&v.to_be_bytes())
+    }
+
+    /// Receives a single 4-byte datagram and decodes it as a big-endian
+    /// (network byte order) `u32`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::UnexpectedEof`] if the
+    /// datagram is shorter than 4 bytes, or [`io::ErrorKind::InvalidData`] if
+    /// it's longer.
+    pub fn recv_u32_be(&self) -> io::Result<u32> {
+        self.recv_exact().map(u32::from_be_bytes)
+    }
+
+    /// Receives a single 2-byte datagram and decodes it as a big-endian
+    /// (network byte order) `u16`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::UnexpectedEof`] if the
+    /// datagram is shorter than 2 bytes, or [`io::ErrorKind::InvalidData`] if
+    /// it's longer.
+    pub fn recv_u16_be(&self) -> io::Result<u16> {
+        self.recv_exact().map(u16::from_be_bytes)
+    }
+
+    /// Sends `v` as a single 4-byte datagram in the byte order configured
+    /// with [`Builder::byte_order`] (big-endian by default).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::{ByteOrder, ConnectedUdpSocket};
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::builder()
+    ///      .byte_order(ByteOrder::Little)
+    ///      .connect(client, host_addr)
+    ///      .expect("couldn't client to host");
+    ///
+    ///  conn_client.send_u32(0xdead_beef).expect("couldn't send value");
+    ///
+    ///  let mut buf = [0; 4];
+    ///  host.recv(&mut buf).expect("couldn't receive value");
+    ///  assert_eq!(buf, [0xef, 0xbe, 0xad, 0xde]);
+    /// # }
+    /// ```
+    pub fn send_u32(&self, v: u32) -> io::Result<usize> {
+        match self.options.byte_order {
+            ByteOrder::Big => self.send(&v.to_be_bytes()),
+            ByteOrder::Little => self.send(&v.to_le_bytes()),
+        }
+    }
+
+    /// Sends `v` as a single 2-byte datagram in the byte order configured
+    /// with [`Builder::byte_order`] (big-endian by default).
+    pub fn send_u16(&self, v: u16) -> io::Result<usize> {
+        match self.options.byte_order {
+            ByteOrder::Big => self.send(&v.to_be_bytes()),
+            ByteOrder::Little => self.send(&v.to_le_bytes()),
+        }
+    }
+
+    /// Receives a single 4-byte datagram and decodes it as a `u32` in the
+    /// byte order configured with [`Builder::byte_order`] (big-endian by
+    /// default).
+    ///
+    /// # Errors
+    ///
+    /// Fails in the same cases as [`recv_u32_be`](Self::recv_u32_be).
+    pub fn recv_u32(&self) -> io::Result<u32> {
+        let bytes = self.recv_exact()?;
+        Ok(match self.options.byte_order {
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+        })
+    }
+
+    /// Receives a single 2-byte datagram and decodes it as a `u16` in the
+    /// byte order configured with [`Builder::byte_order`] (big-endian by
+    /// default).
+    ///
+    /// # Errors
+    ///
+    /// Fails in the same cases as [`recv_u16_be`](Self::recv_u16_be).
+    pub fn recv_u16(&self) -> io::Result<u16> {
+        let bytes = self.recv_exact()?;
+        Ok(match self.options.byte_order {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        })
+    }
+
+    /// Receives a single datagram that must be exactly `N` bytes long.
+    fn recv_exact<const N: usize>(&self) -> io::Result<[u8; N]> {
+        // One spare byte lets us tell an oversized datagram apart from an
+        // exact fit.
+        let mut buf = vec![0; N + 1];
+        let n = self.recv(&mut buf)?;
+        if n < N {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("expected a {N}-byte datagram, received {n} bytes"),
+            ));
+        }
+        if n > N {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected a {N}-byte datagram, received a longer one"),
+            ));
+        }
+
+        let mut out = [0; N];
+        out.copy_from_slice(&buf[..N]);
+        Ok(out)
+    }
+
+    /// Receives data from the socket like [`recv`](Self::recv), but gives up
+    /// and returns `Ok(None)` once `cancel` is set.
+    ///
+    /// The socket is polled in short slices and `cancel` is checked between
+    /// them, so another thread can stop a blocked receive loop without
+    /// resorting to shutting the socket down.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::sync::atomic::AtomicBool;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let cancel = AtomicBool::new(true);
+    ///  let mut buf = [0; 32];
+    ///  assert_eq!(conn_client.recv_cancellable(&mut buf, &cancel).unwrap(), None);
+    /// # }
+    /// ```
+    pub fn recv_cancellable(
+        &self,
+        buf: &mut [u8],
+        cancel: &AtomicBool,
+    ) -> io::Result<Option<usize>> {
+        self.ensure_peer()?;
+        loop {
+            if cancel.load(Ordering::Acquire) {
+                return Ok(None);
+            }
+
+            match sys::poll(&self.socket, Interest::Readable, Some(CANCEL_POLL_INTERVAL)) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+
+            // A datagram the relay forwarded from elsewhere is discarded
+            // without blocking, so cancellation is still checked.
+            match self.recv_raw_once(buf) {
+                Ok(Some(n)) => match self.check_empty(n, buf.len())? {
+                    Some(n) => return Ok(Some(n)),
+                    None => continue,
+                },
+                Ok(None) => continue,
+                // Another reader may have raced us to the datagram.
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl TryFrom<UdpSocket> for ConnectedUdpSocket {
+    type Error = io::Error;
+
+    fn try_from(socket: UdpSocket) -> Result<Self, Self::Error> {
+        let options = Options::default();
+        let peer = canonicalize(socket.peer_addr()?, &options);
+        validate_peer(peer)?;
+        Ok(Self::from_parts(socket, peer, options))
+    }
+}
+
+/// Connects `socket` to `peer`, returning the peer in the form it should be
+/// cached in.
+fn connect_socket(
+    socket: &UdpSocket,
+    peer: SocketAddr,
+    options: &Options,
+) -> io::Result<SocketAddr> {
+    let (os_peer, peer) = resolve_peer(socket.local_addr()?, peer, options)?;
+    socket.connect(os_peer)?;
+    Ok(peer)
+}
+
+/// Validates `peer` for a socket bound to `local`, returning the address to
+/// connect the socket to and the form to cache, in that order.
+pub(crate) fn resolve_peer(
+    local: SocketAddr,
+    peer: SocketAddr,
+    options: &Options,
+) -> io::Result<(SocketAddr, SocketAddr)> {
+    // An IPv6 socket can only be connected to an IPv4 peer through the
+    // peer's IPv4-mapped address.
+    let os_peer = match peer {
+        SocketAddr::V4(v4) if local.is_ipv6() => {
+            SocketAddr::V6(SocketAddrV6::new(v4.ip().to_ipv6_mapped(), v4.port(), 0, 0))
+        }
+        _ => peer,
+    };
+    let peer = canonicalize(os_peer, options);
+    validate_peer(peer)?;
+    Ok((os_peer, peer))
+}
+
+/// XORs `buf` with `key` repeated from its start. Applying it twice
+/// restores the original bytes.
+pub(crate) fn xor_with_key(buf: &mut [u8], key: &[u8]) {
+    for (byte, k) in buf.iter_mut().zip(key.iter().cycle()) {
+        *byte ^= k;
+    }
+}
+
+/// Puts `addr` into the form peers are cached in, per `options`.
+pub(crate) fn canonicalize(addr: SocketAddr, options: &Options) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) if options.unmap_ipv4 => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::V4(SocketAddrV4::new(ip, v6.port())),
+            None => addr,
+        },
+        _ => addr,
+    }
+}
+
+/// Returns a fresh random 16-byte nonce for
+/// [`ConnectedUdpSocket::challenge_connect`].
+fn nonce() -> [u8; 16] {
+    let state = RandomState::new();
+    let mut nonce = [0; 16];
+    for (i, chunk) in nonce.chunks_exact_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        i.hash(&mut hasher);
+        Instant::now().hash(&mut hasher);
+        chunk.copy_from_slice(&hasher.finish().to_ne_bytes());
+    }
+    nonce
+}
+
+/// The error reported when a peer fails the challenge in
+/// [`ConnectedUdpSocket::challenge_connect`].
+fn challenge_failed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "peer didn't echo the challenge nonce",
+    )
+}
+
+/// Returns the time left until `deadline`, or a `TimedOut` error if it has
+/// passed.
+pub(crate) fn remaining(deadline: Instant) -> io::Result<Duration> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "operation timed out",
+        ));
+    }
+    Ok(left)
+}
+
+/// Reports an expired socket timeout, which the OS signals as `WouldBlock`
+/// on some platforms, as `TimedOut`.
+pub(crate) fn timed_out(err: io::Error) -> io::Error {
+    if err.kind() == io::ErrorKind::WouldBlock {
+        return io::Error::new(io::ErrorKind::TimedOut, "operation timed out");
+    }
+    err
+}
+
+/// Turns a send of `n` bytes out of `len` into an error if nothing was sent
+/// from a nonempty buffer.
+fn check_sent(n: usize, len: usize) -> io::Result<usize> {
+    if n == 0 && len > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "datagram was sent with no bytes",
+        ));
+    }
+    Ok(n)
+}
+
+/// Returns whether `err` reports that nothing is listening at the peer's
+/// address, which connected UDP sockets learn from ICMP port unreachable
+/// messages.
+fn is_unreachable(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Rejects peer addresses that can't meaningfully be connected to.
+fn validate_peer(peer: SocketAddr) -> io::Result<()> {
+    if peer.ip().is_unspecified() || peer.port() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot connect to unspecified peer address {peer}"),
+        ));
+    }
+    Ok(())
+}
+
+impl From<ConnectedUdpSocket> for (UdpSocket, SocketAddr) {
+    /// Decomposes the socket into the underlying [`UdpSocket`] and the cached
+    /// peer address.
+    fn from(conn: ConnectedUdpSocket) -> Self {
+        (conn.socket, conn.peer)
+    }
+}
+
+impl Clone for ConnectedUdpSocket {
+    /// Creates a new handle to the same socket, like
+    /// [`try_clone`](Self::try_clone).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the OS fails to duplicate the socket handle, e.g. because
+    /// the process has run out of file descriptors. Use
+    /// [`try_clone`](Self::try_clone) to handle that case.
+    fn clone(&self) -> Self {
+        self.try_clone()
+            .expect("failed to duplicate the socket handle")
+    }
+}
+
+impl AsRef<UdpSocket> for ConnectedUdpSocket {
+    fn as_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn try_from_not_connected_error() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = ConnectedUdpSocket::try_from(socket).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn connect_unspecified_peer_error() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = ConnectedUdpSocket::connect(socket, "0.0.0.0:0".parse().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn try_from_unspecified_peer_error() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect("0.0.0.0:0").unwrap();
+
+        // Some kernels (e.g. Linux) treat this as a disconnect and report
+        // `NotConnected` from `peer_addr`; others hand back the zero address.
+        let err = ConnectedUdpSocket::try_from(socket).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::InvalidInput | io::ErrorKind::NotConnected
+        ));
+    }
+
+    #[test]
+    fn dual_stack_peer_is_unmapped() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let Ok(sender) = UdpSocket::bind("[::]:0") else {
+            return;
+        };
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+
+        sender_conn.send(b"mapped").unwrap();
+        let mut buf = [0u8; 32];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"mapped");
+
+        // Converting a socket connected through the mapped form canonicalizes
+        // the same way.
+        let (sender, _) = sender_conn.into();
+        let sender_conn = ConnectedUdpSocket::try_from(sender).unwrap();
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+    }
+
+    #[test]
+    fn dual_stack_peer_stays_mapped() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let Ok(sender) = UdpSocket::bind("[::]:0") else {
+            return;
+        };
+        let sender_conn = Builder::new()
+            .unmap_ipv4(false)
+            .connect(sender, recv_addr)
+            .unwrap();
+        assert_eq!(
+            sender_conn.peer_addr(),
+            format!("[::ffff:127.0.0.1]:{}", recv_addr.port())
+                .parse::<SocketAddr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn connect_with_probe_gets_reply() {
+        let (echo_addr, _echo) = crate::spawn_echo_server().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_client = ConnectedUdpSocket::connect_with_probe(
+            client,
+            echo_addr,
+            b"probe",
+            Duration::from_secs(2),
+        )
+        .unwrap();
+        assert_eq!(conn_client.peer_addr(), echo_addr);
+        assert_eq!(conn_client.as_ref().read_timeout().unwrap(), None);
+        assert_eq!(conn_client.as_ref().write_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn connect_with_probe_bounded_with_tiny_send_buffer() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        SockRef::from(&client).set_send_buffer_size(1).unwrap();
+
+        let start = Instant::now();
+        let err = ConnectedUdpSocket::connect_with_probe(
+            client,
+            silent_addr,
+            &[0u8; 1024],
+            Duration::from_millis(100),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn connect_from_port_range_skips_taken_ports() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        // Occupy the first port of the range.
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let first = taken.local_addr().unwrap().port();
+        let range = first..=first.saturating_add(16);
+
+        let conn = ConnectedUdpSocket::connect_from_port_range(
+            Ipv4Addr::LOCALHOST.into(),
+            range.clone(),
+            recv_addr,
+        )
+        .unwrap();
+        let port = conn.local_addr().unwrap().port();
+        assert!(range.contains(&port));
+        assert_ne!(port, first);
+    }
+
+    #[test]
+    fn connect_from_port_range_exhausted() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let err = ConnectedUdpSocket::connect_from_port_range(
+            Ipv4Addr::LOCALHOST.into(),
+            port..=port,
+            recv_addr,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+
+    #[test]
+    fn challenge_connect_with_echoing_peer() {
+        let (echo_addr, _echo) = crate::spawn_echo_server().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn = ConnectedUdpSocket::challenge_connect(client, echo_addr, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(conn.peer_addr(), echo_addr);
+        assert_eq!(conn.as_ref().read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn challenge_connect_wrong_echo_denied() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let responder = thread::spawn(move || {
+            let mut buf = [0u8; 32];
+            let (_, from) = peer.recv_from(&mut buf).unwrap();
+            peer.send_to(b"not the nonce", from).unwrap();
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = ConnectedUdpSocket::challenge_connect(client, peer_addr, Duration::from_secs(5))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn challenge_connect_silent_peer_denied() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = ConnectedUdpSocket::challenge_connect(
+            client,
+            peer.local_addr().unwrap(),
+            Duration::from_millis(50),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn unset_peer_until_reconnect() {
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second_addr = second.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender_conn =
+            ConnectedUdpSocket::connect(sender, first.local_addr().unwrap()).unwrap();
+
+        sender_conn.unset_peer();
+        let err = sender_conn.send(b"ping").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        assert_eq!(
+            err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+            Some(&Error::NotConnected)
+        );
+        let mut buf = [0u8; 32];
+        let err = sender_conn.recv(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+
+        sender_conn.reconnect(second_addr).unwrap();
+        assert_eq!(sender_conn.peer_addr(), second_addr);
+        sender_conn.send(b"ping").unwrap();
+        let n = second.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+    }
+
+    #[test]
+    fn reconnect_failure_keeps_peer() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let err = sender_conn
+            .reconnect("0.0.0.0:0".parse().unwrap())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+    }
+
+    #[test]
+    fn replace_socket_sends_to_new_peer() {
+        let old_peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let new_peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        old_peer.set_nonblocking(true).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender_conn = ConnectedUdpSocket::builder()
+            .treat_empty_datagram_as(EmptyDatagramPolicy::Error)
+            .connect(sender, old_peer.local_addr().unwrap())
+            .unwrap();
+        let replacement = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let replacement =
+            ConnectedUdpSocket::connect(replacement, new_peer.local_addr().unwrap()).unwrap();
+        let replacement_addr = replacement.local_addr().unwrap();
+
+        let old = sender_conn.replace_socket(replacement);
+        assert_eq!(old.peer_addr(), old_peer.local_addr().unwrap());
+        assert_eq!(sender_conn.peer_addr(), new_peer.local_addr().unwrap());
+        assert_eq!(
+            sender_conn.options.empty_datagrams,
+            EmptyDatagramPolicy::Error
+        );
+
+        sender_conn.send(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        let (n, from) = new_peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert_eq!(from, replacement_addr);
+        assert_eq!(
+            old_peer.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn try_from_connected() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(recv_addr).unwrap();
+
+        let sender_conn = ConnectedUdpSocket::try_from(sender.try_clone().unwrap()).unwrap();
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+
+        let local = sender_conn.local_addr().unwrap();
+        assert_eq!(
+            local.ip(),
+            std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST)
+        );
+
+        assert_eq!(
+            sender_conn.local_addr().unwrap(),
+            sender.local_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn connect_and_send_recv() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 32];
+            let (n, from) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"ping");
+            from
+        });
+
+        let n = sender_conn.send(b"ping").unwrap();
+        assert_eq!(n, 4);
+
+        let from = handle.join().unwrap();
+        assert_eq!(from, sender_conn.local_addr().unwrap());
+    }
+
+    #[test]
+    fn into_socket_and_peer_tuple() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let send_addr = sender.local_addr().unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let (socket, peer): (UdpSocket, SocketAddr) = sender_conn.into();
+        assert_eq!(peer, recv_addr);
+        assert_eq!(socket.local_addr().unwrap(), send_addr);
+        assert_eq!(socket.peer_addr().unwrap(), recv_addr);
+    }
+
+    #[test]
+    fn as_ref_udp_socket() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let raw: &UdpSocket = sender_conn.as_ref();
+
+        assert_eq!(raw.peer_addr().unwrap(), recv_addr);
+        assert_eq!(raw.local_addr().unwrap(), sender_conn.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 32];
+            let (n, from) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"asref");
+            from
+        });
+
+        let n = raw.send(b"asref").unwrap();
+        assert_eq!(n, 5);
+
+        let from = handle.join().unwrap();
+        assert_eq!(from, sender_conn.local_addr().unwrap());
+    }
+
+    #[test]
+    fn recv_verified_skips_foreign_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let foreign = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        // Datagrams queued before connecting are delivered regardless of
+        // their source.
+        foreign.send_to(b"spoof", recv_addr).unwrap();
+        foreign.send_to(b"spoof", recv_addr).unwrap();
+        let conn = ConnectedUdpSocket::connect(receiver, peer.local_addr().unwrap()).unwrap();
+        peer.send_to(b"real", recv_addr).unwrap();
+
+        let mut buf = [0u8; 8];
+        let n = conn.recv_verified(&mut buf, 2).unwrap();
+        assert_eq!(&buf[..n], b"real");
+    }
+
+    #[test]
+    fn recv_verified_bounds_discards() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let foreign = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        for _ in 0..10 {
+            foreign.send_to(b"spoof", recv_addr).unwrap();
+        }
+        let conn = ConnectedUdpSocket::connect(receiver, peer.local_addr().unwrap()).unwrap();
+        peer.send_to(b"real", recv_addr).unwrap();
+
+        let mut buf = [0u8; 8];
+        let err = conn.recv_verified(&mut buf, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+            Some(&Error::TooManyForeignDatagrams)
+        );
+    }
+
+    #[test]
+    fn recv_cancellable_returns_none_when_cancelled() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let cancel = std::sync::Arc::new(AtomicBool::new(false));
+        let handle = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                cancel.store(true, Ordering::Release);
+            })
+        };
+
+        let start = std::time::Instant::now();
+        let mut buf = [0u8; 32];
+        let res = sender_conn.recv_cancellable(&mut buf, &cancel).unwrap();
+        assert_eq!(res, None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn recv_cancellable_receives_datagram() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        receiver
+            .send_to(b"ping", sender_conn.local_addr().unwrap())
+            .unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let mut buf = [0u8; 32];
+        let n = sender_conn.recv_cancellable(&mut buf, &cancel).unwrap();
+        assert_eq!(n, Some(4));
+        assert_eq!(&buf[..4], b"ping");
+    }
+
+    #[test]
+    fn recv_uninit_initializes_prefix() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        receiver
+            .send_to(b"uninit", sender_conn.local_addr().unwrap())
+            .unwrap();
+
+        let mut buf = [MaybeUninit::<u8>::uninit(); 32];
+        let n = sender_conn.recv_uninit(&mut buf).unwrap();
+        assert_eq!(n, 6);
+        // SAFETY: `recv_uninit` initialized the first `n` bytes.
+        let data = unsafe { std::slice::from_raw_parts(buf.as_ptr().cast::<u8>(), n) };
+        assert_eq!(data, b"uninit");
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn bytes_round_trip() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        let a_conn = ConnectedUdpSocket::connect(a, b_addr).unwrap();
+        let b_conn = ConnectedUdpSocket::connect(b, a_addr).unwrap();
+
+        let msg = bytes::Bytes::from_static(b"zero-copy");
+        assert_eq!(a_conn.send_bytes(&msg).unwrap(), msg.len());
+        assert_eq!(b_conn.recv_bytes(64).unwrap(), msg);
+    }
+
+    #[test]
+    fn recv_array_fixed_size() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        receiver
+            .send_to(b"sixteen bytes!!!", sender_conn.local_addr().unwrap())
+            .unwrap();
+
+        let (buf, n): ([u8; 16], usize) = sender_conn.recv_array().unwrap();
+        assert_eq!(n, 16);
+        assert_eq!(&buf, b"sixteen bytes!!!");
+    }
+
+    #[test]
+    fn u32_be_round_trip() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        let a_conn = ConnectedUdpSocket::connect(a, b_addr).unwrap();
+        let b_conn = ConnectedUdpSocket::connect(b, a_addr).unwrap();
+
+        assert_eq!(a_conn.send_u32_be(0x0102_0304).unwrap(), 4);
+        assert_eq!(b_conn.recv_u32_be().unwrap(), 0x0102_0304);
+
+        assert_eq!(a_conn.send_u16_be(0xbeef).unwrap(), 2);
+        assert_eq!(b_conn.recv_u16_be().unwrap(), 0xbeef);
+    }
+
+    #[test]
+    fn recv_u32_be_too_short() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        receiver
+            .send_to(&[1, 2], sender_conn.local_addr().unwrap())
+            .unwrap();
+
+        let err = sender_conn.recv_u32_be().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn into_shared_concurrent_send() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let shared = ConnectedUdpSocket::connect(sender, recv_addr)
+            .unwrap()
+            .into_shared();
+
+        let handles: Vec<_> = [b"one", b"two"]
+            .into_iter()
+            .map(|msg| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || shared.send(msg).unwrap())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 3);
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let mut buf = [0u8; 32];
+            let (n, from) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(from, shared.local_addr().unwrap());
+            received.push(buf[..n].to_vec());
+        }
+        received.sort();
+        assert_eq!(received, [b"one".to_vec(), b"two".to_vec()]);
+    }
+
+    #[test]
+    fn endpoints_match_getters() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let (local, peer) = sender_conn.endpoints().unwrap();
+        assert_eq!(local, sender_conn.local_addr().unwrap());
+        assert_eq!(peer, sender_conn.peer_addr());
+    }
+
+    #[test]
+    fn blocking_send_with_full_send_buffer() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        SockRef::from(&sender).set_send_buffer_size(4096).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let payload = [0u8; 1024];
+        for _ in 0..1000 {
+            assert_eq!(sender_conn.send(&payload).unwrap(), payload.len());
+        }
+    }
+
+    #[test]
+    fn blocks_indefinitely_follows_mode_and_timeout() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.set_nonblocking(true).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        assert!(!sender_conn.blocks_indefinitely());
+        sender_conn.as_ref().set_nonblocking(false).unwrap();
+        assert!(sender_conn.blocks_indefinitely());
+        sender_conn
+            .as_ref()
+            .set_write_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(!sender_conn.blocks_indefinitely());
+    }
+
+    #[test]
+    fn tune_buffers_applies_bdp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // 8 Mbit/s over 10ms is a 10,000-byte bandwidth-delay product.
+        let (send, recv) = sender_conn
+            .tune_buffers(8_000_000, Duration::from_millis(10))
+            .unwrap();
+        assert!(send >= 10_000, "send buffer {send} too small");
+        assert!(recv >= 10_000, "recv buffer {recv} too small");
+        assert_eq!(send, sender_conn.send_buffer_size().unwrap());
+        assert_eq!(recv, sender_conn.recv_buffer_size().unwrap());
+    }
+
+    #[test]
+    fn limit_queued_datagrams_sizes_recv_buffer() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let applied = sender_conn.limit_queued_datagrams(8, 512).unwrap();
+        assert_eq!(applied, sender_conn.recv_buffer_size().unwrap());
+        // Linux doubles the request for bookkeeping; others apply it as is.
+        assert!(
+            (4096..=8192).contains(&applied),
+            "recv buffer {applied} isn't sized for the limit"
+        );
+
+        let err = sender_conn.limit_queued_datagrams(0, 512).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn will_fragment_on_loopback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        match sender_conn.will_fragment(100) {
+            Ok(fragments) => {
+                assert!(!fragments);
+                assert!(sender_conn.will_fragment(70_000).unwrap());
+            }
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+        }
+    }
+
+    #[test]
+    fn recv_all_available_drains_queue() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_addr = sender.local_addr().unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, sender_addr).unwrap();
+
+        for msg in [&b"one"[..], b"two", b"three"] {
+            sender_conn.send(msg).unwrap();
+        }
+        // Give loopback a moment to queue all three.
+        thread::sleep(Duration::from_millis(20));
+
+        let datagrams = receiver_conn.recv_all_available(8).unwrap();
+        assert_eq!(
+            datagrams,
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+        assert!(!receiver_conn.is_nonblocking().unwrap());
+    }
+
+    #[test]
+    fn send_once_to_third_party() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let third = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        sender_conn
+            .send_once_to(b"notice", third.local_addr().unwrap())
+            .unwrap();
+        let mut buf = [0u8; 8];
+        let n = third.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"notice");
+        assert_eq!(
+            receiver.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // The connection is left as it was.
+        sender_conn.send(b"ping").unwrap();
+        receiver.set_nonblocking(false).unwrap();
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+    }
+
+    #[test]
+    fn nonblocking_empty_recv_would_block() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        sender_conn.as_ref().set_nonblocking(true).unwrap();
+
+        let mut buf = [0u8; 8];
+        let err = sender_conn.recv(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        let err = sender_conn.recv_verified(&mut buf, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        let mut uninit = [MaybeUninit::uninit(); 8];
+        let err = sender_conn.recv_uninit(&mut uninit).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn recv_tracking_peer_adopts_rebound_peer() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let mut receiver_conn = ConnectedUdpSocket::connect(receiver, peer_addr).unwrap();
+
+        let mut buf = [0u8; 16];
+        peer.send_to(b"first", recv_addr).unwrap();
+        let (n, changed) = receiver_conn.recv_tracking_peer(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"first");
+        assert!(!changed);
+        assert_eq!(receiver_conn.local_addr().unwrap(), recv_addr);
+
+        // The peer "rebinds" to a new local port while the receiver waits.
+        let rebound = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rebound_addr = rebound.local_addr().unwrap();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            rebound.send_to(b"second", recv_addr).unwrap();
+            rebound
+        });
+        let (n, changed) = receiver_conn.recv_tracking_peer(&mut buf).unwrap();
+        let rebound = sender.join().unwrap();
+        assert_eq!(&buf[..n], b"second");
+        assert!(changed);
+        assert_eq!(receiver_conn.peer_addr(), rebound_addr);
+
+        // Replies go to the new address, and the old one is filtered out.
+        receiver_conn.send(b"reply").unwrap();
+        let (n, from) = rebound.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"reply");
+        assert_eq!(from, recv_addr);
+        peer.send_to(b"stale", recv_addr).unwrap();
+        rebound.send_to(b"fresh", recv_addr).unwrap();
+        let n = receiver_conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"fresh");
+    }
+
+    #[test]
+    fn recv_from_any_captures_foreign_sender() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, peer_addr).unwrap();
+
+        // The kernel only delivers the foreign datagram while disconnected.
+        let foreign = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let foreign_addr = foreign.local_addr().unwrap();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            foreign.send_to(b"rogue", recv_addr).unwrap();
+        });
+        let mut buf = [0u8; 16];
+        let (n, from) = receiver_conn.recv_from_any(&mut buf).unwrap();
+        sender.join().unwrap();
+        assert_eq!(&buf[..n], b"rogue");
+        assert_eq!(from, foreign_addr);
+
+        // The socket is connected to the original peer again.
+        assert_eq!(receiver_conn.peer_addr(), peer_addr);
+        receiver_conn.send(b"still here").unwrap();
+        let (n, from) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"still here");
+        assert_eq!(from, recv_addr);
+    }
+
+    #[test]
+    fn fingerprint_shared_by_clone() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let clone = sender_conn.try_clone().unwrap();
+        assert_eq!(sender_conn.fingerprint(), clone.fingerprint());
+        assert_eq!(sender_conn.fingerprint(), sender_conn.fingerprint());
+
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other_conn = ConnectedUdpSocket::connect(other, recv_addr).unwrap();
+        assert_ne!(sender_conn.fingerprint(), other_conn.fingerprint());
+    }
+
+    fn connected_pair(policy: EmptyDatagramPolicy) -> (UdpSocket, ConnectedUdpSocket) {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn = Builder::new()
+            .treat_empty_datagram_as(policy)
+            .connect(socket, peer.local_addr().unwrap())
+            .unwrap();
+        peer.connect(conn.local_addr().unwrap()).unwrap();
+        (peer, conn)
+    }
+
+    #[test]
+    fn empty_datagram_returns_zero_by_default() {
+        let (peer, conn) = connected_pair(EmptyDatagramPolicy::default());
+        peer.send(&[]).unwrap();
+
+        let mut buf = [0u8; 32];
+        assert_eq!(conn.recv(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn empty_datagram_skipped() {
+        let (peer, conn) = connected_pair(EmptyDatagramPolicy::Skip);
+        peer.send(&[]).unwrap();
+        peer.send(b"data").unwrap();
+
+        let mut buf = [0u8; 32];
+        let n = conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"data");
+    }
+
+    #[test]
+    fn empty_datagram_error() {
+        let (peer, conn) = connected_pair(EmptyDatagramPolicy::Error);
+        peer.send(&[]).unwrap();
+
+        let mut buf = [0u8; 32];
+        let err = conn.recv(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+            Some(&Error::EmptyDatagram)
+        );
+    }
+
+    #[test]
+    fn send_with_header_single_datagram() {
+        #[repr(C)]
+        struct Header {
+            kind: u8,
+            flags: u8,
+            len: [u8; 2],
+        }
+
+        impl AsBytes for Header {
+            fn as_bytes(&self) -> &[u8] {
+                // SAFETY: `Header` is `repr(C)` with no padding.
+                unsafe {
+                    std::slice::from_raw_parts(
+                        (self as *const Self).cast::<u8>(),
+                        std::mem::size_of::<Self>(),
+                    )
+                }
+            }
+        }
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
         let recv_addr = receiver.local_addr().unwrap();
 
         let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
-        sender.connect(recv_addr).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
 
-        let sender_conn = ConnectedUdpSocket::try_from(sender.try_clone().unwrap()).unwrap();
-        assert_eq!(sender_conn.peer_addr(), recv_addr);
+        let header = Header {
+            kind: 7,
+            flags: 0x80,
+            len: 5u16.to_be_bytes(),
+        };
+        assert_eq!(sender_conn.send_with_header(&header, b"hello").unwrap(), 9);
 
-        let local = sender_conn.local_addr().unwrap();
+        let mut buf = [0u8; 32];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], &[7, 0x80, 0, 5, b'h', b'e', b'l', b'l', b'o']);
+    }
+
+    #[test]
+    fn egress_addr_from_wildcard_bind() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let bound_port = sender.local_addr().unwrap().port();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let egress = sender_conn.egress_addr().unwrap();
         assert_eq!(
-            local.ip(),
+            egress.ip(),
             std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST)
         );
+        assert_eq!(egress.port(), bound_port);
+    }
+
+    #[test]
+    fn is_ephemeral_local_for_port_zero_bind() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        assert!(sender_conn.is_ephemeral_local().unwrap());
+    }
+
+    #[test]
+    fn connect_with_backoff_retries_factory_failures() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let mut calls = 0;
+        let start = Instant::now();
+        let sender_conn = ConnectedUdpSocket::connect_with_backoff(
+            || {
+                calls += 1;
+                if calls <= 2 {
+                    Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "not yet"))
+                } else {
+                    UdpSocket::bind("127.0.0.1:0")
+                }
+            },
+            recv_addr,
+            5,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+
+        assert_eq!(calls, 3);
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+        // Waited 10ms, then 20ms.
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn connect_with_backoff_returns_last_error() {
+        let mut calls = 0;
+        let err = ConnectedUdpSocket::connect_with_backoff(
+            || {
+                calls += 1;
+                Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "never"))
+            },
+            "127.0.0.1:9".parse().unwrap(),
+            3,
+            Duration::from_millis(1),
+        )
+        .unwrap_err();
+
+        assert_eq!(calls, 3);
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[test]
+    fn local_port_matches_local_addr() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
 
         assert_eq!(
-            sender_conn.local_addr().unwrap(),
-            sender.local_addr().unwrap()
+            sender_conn.local_port().unwrap(),
+            sender_conn.local_addr().unwrap().port()
         );
     }
 
     #[test]
-    fn connect_and_send_recv() {
+    fn send_until_ack_skips_non_acks() {
         let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
         let recv_addr = receiver.local_addr().unwrap();
 
         let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
         let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
 
-        let handle = thread::spawn(move || {
+        let peer = thread::spawn(move || {
             let mut buf = [0u8; 32];
             let (n, from) = receiver.recv_from(&mut buf).unwrap();
-            assert_eq!(&buf[..n], b"ping");
-            from
+            assert_eq!(&buf[..n], b"data");
+            receiver.send_to(b"busy", from).unwrap();
+            receiver.send_to(b"ack", from).unwrap();
         });
 
-        let n = sender_conn.send(b"ping").unwrap();
-        assert_eq!(n, 4);
+        let ack = sender_conn
+            .send_until_ack(b"data", |r| r == b"ack", 0, Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(ack, b"ack");
+        peer.join().unwrap();
+    }
 
-        let from = handle.join().unwrap();
+    #[test]
+    fn send_until_ack_resends_then_times_out() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let err = sender_conn
+            .send_until_ack(b"data", |_| true, 2, Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // One send plus two retries.
+        receiver.set_nonblocking(true).unwrap();
+        let mut buf = [0u8; 32];
+        let mut sends = 0;
+        while receiver.recv(&mut buf).is_ok() {
+            sends += 1;
+        }
+        assert_eq!(sends, 3);
+    }
+
+    #[test]
+    fn recv_into_ring_appends_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+
+        receiver.send_to(b"hello ", sender_addr).unwrap();
+        receiver.send_to(b"world", sender_addr).unwrap();
+
+        let mut ring = VecDeque::from(b"> ".to_vec());
+        assert_eq!(sender_conn.recv_into_ring(&mut ring, 64).unwrap(), 6);
+        assert_eq!(sender_conn.recv_into_ring(&mut ring, 64).unwrap(), 5);
+        assert_eq!(ring.make_contiguous(), b"> hello world");
+    }
+
+    #[test]
+    fn recv_into_ring_leaves_ring_on_error() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        sender_conn.as_ref().set_nonblocking(true).unwrap();
+
+        let mut ring = VecDeque::from(b"kept".to_vec());
+        let err = sender_conn.recv_into_ring(&mut ring, 64).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(ring.make_contiguous(), b"kept");
+    }
+
+    #[test]
+    fn traffic_class_round_trip_v4() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        sender_conn.set_traffic_class(0xb8).unwrap();
+        assert_eq!(sender_conn.traffic_class().unwrap(), 0xb8);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn traffic_class_round_trip_v6() {
+        // Not every environment has IPv6 loopback.
+        let Ok(receiver) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("[::1]:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        sender_conn.set_traffic_class(0xb8).unwrap();
+        assert_eq!(sender_conn.traffic_class().unwrap(), 0xb8);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recv_timeout_survives_signal() {
+        extern "C" fn ignore(_: libc::c_int) {}
+
+        // Install a handler without `SA_RESTART`, so the signal interrupts
+        // the receive with `EINTR`.
+        // SAFETY: an all-zero `sigaction` is valid, and `ignore` is
+        // async-signal-safe.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            assert_eq!(
+                libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()),
+                0
+            );
+        }
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let timeout = Duration::from_millis(300);
+        sender_conn
+            .as_ref()
+            .set_read_timeout(Some(timeout))
+            .unwrap();
+
+        // SAFETY: always safe to call.
+        let this_thread = unsafe { libc::pthread_self() } as usize;
+        let signaller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            // SAFETY: the receiving thread is still alive, blocked in `recv`.
+            unsafe { libc::pthread_kill(this_thread as libc::pthread_t, libc::SIGUSR1) };
+        });
+
+        let start = Instant::now();
+        let mut buf = [0u8; 8];
+        let err = sender_conn.recv(&mut buf).unwrap_err();
+        signaller.join().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn is_nonblocking_tracks_mode() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        assert!(!sender_conn.is_nonblocking().unwrap());
+
+        sender_conn.set_nonblocking(true).unwrap();
+        assert!(sender_conn.is_nonblocking().unwrap());
+        assert!(sender_conn.try_clone().unwrap().is_nonblocking().unwrap());
+
+        sender_conn.set_nonblocking(false).unwrap();
+        assert!(!sender_conn.is_nonblocking().unwrap());
+    }
+
+    #[test]
+    fn clone_shares_peer() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let clone = sender_conn.clone();
+        assert_eq!(clone.peer_addr(), recv_addr);
+
+        clone.send(b"ping").unwrap();
+        let mut buf = [0u8; 8];
+        let (n, from) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
         assert_eq!(from, sender_conn.local_addr().unwrap());
     }
 
     #[test]
-    fn as_ref_udp_socket() {
+    fn peer_addr_result_synced() {
         let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
         let recv_addr = receiver.local_addr().unwrap();
 
         let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
         let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        assert_eq!(sender_conn.peer_addr_result().unwrap(), recv_addr);
+    }
 
-        let raw: &UdpSocket = sender_conn.as_ref();
+    #[test]
+    fn peer_addr_result_desynced() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
 
-        assert_eq!(raw.peer_addr().unwrap(), recv_addr);
-        assert_eq!(raw.local_addr().unwrap(), sender_conn.local_addr().unwrap());
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        sender_conn
+            .as_ref()
+            .connect(other.local_addr().unwrap())
+            .unwrap();
 
-        let handle = thread::spawn(move || {
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+        let err = sender_conn.peer_addr_result().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        let err = err.get_ref().and_then(|e| e.downcast_ref::<Error>());
+        assert_eq!(
+            err,
+            Some(&Error::PeerMismatch {
+                expected: recv_addr,
+                actual: other.local_addr().unwrap(),
+            })
+        );
+        assert_eq!(
+            err.unwrap().to_string(),
+            format!(
+                "socket is connected to {}, not the peer {recv_addr}",
+                other.local_addr().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn wait_until_reachable_server_starts_late() {
+        // Reserve a port for the server, which only starts listening on it
+        // partway through the wait.
+        let server_addr = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, server_addr).unwrap();
+
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            let server = UdpSocket::bind(server_addr).unwrap();
             let mut buf = [0u8; 32];
-            let (n, from) = receiver.recv_from(&mut buf).unwrap();
-            assert_eq!(&buf[..n], b"asref");
-            from
+            let (n, from) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..n], from).unwrap();
         });
 
-        let n = raw.send(b"asref").unwrap();
-        assert_eq!(n, 5);
+        sender_conn
+            .wait_until_reachable(b"ping", Duration::from_secs(5), Duration::from_millis(20))
+            .unwrap();
+        server.join().unwrap();
+    }
 
-        let from = handle.join().unwrap();
-        assert_eq!(from, sender_conn.local_addr().unwrap());
+    #[test]
+    fn wait_until_reachable_times_out() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let err = sender_conn
+            .wait_until_reachable(
+                b"ping",
+                Duration::from_millis(100),
+                Duration::from_millis(20),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(sender_conn.as_ref().read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn check_sent_rejects_zero_from_nonempty() {
+        assert_eq!(check_sent(0, 0).unwrap(), 0);
+        assert_eq!(check_sent(4, 4).unwrap(), 4);
+        let err = check_sent(0, 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn send_empty_buffer_returns_zero() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        assert_eq!(sender_conn.send(&[]).unwrap(), 0);
+        assert_eq!(sender_conn.send(b"ping").unwrap(), 4);
+    }
+
+    #[test]
+    fn recv_with_uses_allocator() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        receiver
+            .send_to(b"ping", sender_conn.local_addr().unwrap())
+            .unwrap();
+
+        let mut requests = Vec::new();
+        let datagram = sender_conn
+            .recv_with(64, |capacity| {
+                requests.push(capacity);
+                Vec::with_capacity(capacity)
+            })
+            .unwrap();
+        assert_eq!(requests, [64]);
+        assert_eq!(datagram, b"ping");
+        assert!(datagram.capacity() >= 64);
+    }
+
+    #[test]
+    fn recv_verified_ip_only_accepts_other_port() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let old_port = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        // The peer's datagram from its previous port was queued before
+        // connecting.
+        old_port.send_to(b"moved", recv_addr).unwrap();
+        let conn = Builder::new()
+            .match_peer_by_ip_only(true)
+            .connect(receiver, peer.local_addr().unwrap())
+            .unwrap();
+
+        let mut buf = [0u8; 8];
+        let n = conn.recv_verified(&mut buf, 0).unwrap();
+        assert_eq!(&buf[..n], b"moved");
+    }
+
+    #[test]
+    fn recv_tracking_peer_ip_only_follows_port_quietly() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let mut receiver_conn = Builder::new()
+            .match_peer_by_ip_only(true)
+            .connect(receiver, peer_addr)
+            .unwrap();
+
+        // The peer "rebinds" to a new local port while the receiver waits.
+        let rebound = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rebound_addr = rebound.local_addr().unwrap();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            rebound.send_to(b"second", recv_addr).unwrap();
+            rebound
+        });
+        let mut buf = [0u8; 16];
+        let (n, changed) = receiver_conn.recv_tracking_peer(&mut buf).unwrap();
+        let rebound = sender.join().unwrap();
+        assert_eq!(&buf[..n], b"second");
+        assert!(!changed);
+        assert_eq!(receiver_conn.peer_addr(), rebound_addr);
+
+        receiver_conn.send(b"reply").unwrap();
+        let n = rebound.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"reply");
+    }
+
+    #[test]
+    fn recv_n_collects_all() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        for msg in [b"one", b"two", b"six"] {
+            receiver.send_to(msg, sender_addr).unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let datagrams = sender_conn.recv_n(3, 16, deadline).unwrap();
+        assert_eq!(datagrams, [b"one", b"two", b"six"]);
+    }
+
+    #[test]
+    fn recv_n_returns_partial_at_deadline() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        receiver.send_to(b"one", sender_addr).unwrap();
+        receiver.send_to(b"two", sender_addr).unwrap();
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let datagrams = sender_conn.recv_n(3, 16, deadline).unwrap();
+        assert_eq!(datagrams, [b"one", b"two"]);
+        assert!(Instant::now() >= deadline);
+        assert_eq!(sender_conn.as_ref().read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn recv_n_exact_times_out_when_short() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        receiver.send_to(b"one", sender_addr).unwrap();
+        receiver.send_to(b"two", sender_addr).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let datagrams = sender_conn.recv_n_exact(2, 16, deadline).unwrap();
+        assert_eq!(datagrams, [b"one", b"two"]);
+
+        receiver.send_to(b"six", sender_addr).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let err = sender_conn.recv_n_exact(2, 16, deadline).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn recv_n_waits_on_nonblocking_socket() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        sender_conn.set_nonblocking(true).unwrap();
+
+        let late = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            receiver.send_to(b"late", sender_addr).unwrap();
+        });
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let datagrams = sender_conn.recv_n(1, 16, deadline).unwrap();
+        assert_eq!(datagrams, [b"late"]);
+        late.join().unwrap();
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(sender_conn.recv_n(1, 16, deadline).unwrap().is_empty());
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    fn socket_type_is_datagram() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        assert_eq!(sender_conn.socket_type().unwrap(), Type::DGRAM);
     }
 }
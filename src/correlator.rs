@@ -0,0 +1,208 @@
+use crate::ConnectedUdpSocket;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The size of the request id prepended to every datagram.
+const ID_LEN: usize = 8;
+
+/// The largest reply the receive thread accepts.
+const MAX_REPLY_LEN: usize = 64 * 1024;
+
+type Pending = Arc<Mutex<HashMap<u64, mpsc::Sender<Vec<u8>>>>>;
+
+/// Matches replies to requests on a shared [`ConnectedUdpSocket`], allowing
+/// several requests to be in flight at once.
+///
+/// Every request sent through [`call`](Self::call) is prefixed with an
+/// auto-generated 8-byte big-endian request id. The peer is expected to echo
+/// that id at the start of its reply; a dedicated receive thread routes each
+/// reply back to the caller waiting on the matching id. Replies with an
+/// unknown id, or that are too short to carry one, are discarded.
+///
+/// The receive thread consumes every datagram arriving on the socket, so
+/// nothing else should receive from it while the correlator is alive. The
+/// thread stops once the correlator is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, Correlator};
+/// use std::net::UdpSocket;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///
+///     // Echo a single request back, id and all.
+///     thread::spawn(move || {
+///         let mut buf = [0; 64];
+///         let (n, from) = host.recv_from(&mut buf).unwrap();
+///         host.send_to(&buf[..n], from).unwrap();
+///     });
+///
+///     let correlator = Correlator::new(conn_client.into_shared());
+///     let reply = correlator.call(b"ping", Duration::from_secs(1))?;
+///     assert_eq!(reply, b"ping");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Correlator {
+    socket: Arc<ConnectedUdpSocket>,
+    next_id: AtomicU64,
+    pending: Pending,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Correlator {
+    /// Creates a correlator over `socket` and starts its receive thread.
+    pub fn new(socket: Arc<ConnectedUdpSocket>) -> Self {
+        let pending = Pending::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let socket = Arc::clone(&socket);
+            let pending = Arc::clone(&pending);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || route_replies(&socket, &pending, &stop))
+        };
+
+        Self {
+            socket,
+            next_id: AtomicU64::new(0),
+            pending,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Sends `payload` to the peer and waits up to `timeout` for the reply
+    /// carrying the same request id, returning the reply with the id
+    /// stripped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::TimedOut`] if no matching
+    /// reply arrives in time, or the send error if the request can't be sent.
+    pub fn call(&self, payload: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        let mut request = Vec::with_capacity(ID_LEN + payload.len());
+        request.extend_from_slice(&id.to_be_bytes());
+        request.extend_from_slice(payload);
+
+        let res = self
+            .socket
+            .send(&request)
+            .and_then(|_| match rx.recv_timeout(timeout) {
+                Ok(reply) => Ok(reply),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => Err(
+                    io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for reply"),
+                ),
+            });
+        self.pending.lock().unwrap().remove(&id);
+        res
+    }
+}
+
+impl Drop for Correlator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Receives replies until `stop` is set, handing each to the caller waiting
+/// on its request id.
+fn route_replies(socket: &ConnectedUdpSocket, pending: &Pending, stop: &AtomicBool) {
+    let mut buf = vec![0; MAX_REPLY_LEN];
+    loop {
+        let n = match socket.recv_cancellable(&mut buf, stop) {
+            Ok(Some(n)) => n,
+            Ok(None) => return,
+            // Errors such as a refused connection only affect the datagram
+            // that caused them.
+            Err(_) => continue,
+        };
+        if n < ID_LEN {
+            continue;
+        }
+
+        let id = u64::from_be_bytes(buf[..ID_LEN].try_into().unwrap());
+        if let Some(tx) = pending.lock().unwrap().remove(&id) {
+            let _ = tx.send(buf[ID_LEN..n].to_vec());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn concurrent_calls_get_matching_replies() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_client = ConnectedUdpSocket::connect(client, server_addr).unwrap();
+
+        // Wait for both requests, then answer them in reverse order.
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let mut buf = [0u8; 64];
+                let (n, from) = server.recv_from(&mut buf).unwrap();
+                requests.push((buf[..n].to_vec(), from));
+            }
+            for (request, from) in requests.into_iter().rev() {
+                let mut reply = request[..ID_LEN].to_vec();
+                reply.extend_from_slice(b"re:");
+                reply.extend_from_slice(&request[ID_LEN..]);
+                server.send_to(&reply, from).unwrap();
+            }
+        });
+
+        let correlator = Arc::new(Correlator::new(conn_client.into_shared()));
+        let calls: Vec<_> = [&b"one"[..], &b"two"[..]]
+            .into_iter()
+            .map(|payload| {
+                let correlator = Arc::clone(&correlator);
+                thread::spawn(move || correlator.call(payload, Duration::from_secs(2)).unwrap())
+            })
+            .collect();
+        let replies: Vec<_> = calls.into_iter().map(|c| c.join().unwrap()).collect();
+
+        assert_eq!(replies, [b"re:one".to_vec(), b"re:two".to_vec()]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn call_times_out_without_reply() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_client = ConnectedUdpSocket::connect(client, server_addr).unwrap();
+
+        let correlator = Correlator::new(conn_client.into_shared());
+        let err = correlator
+            .call(b"ping", Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::ConnectedUdpSocket;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashSet, VecDeque};
+use std::hash::BuildHasher;
+use std::io;
+use std::sync::Mutex;
+
+/// A receiver that filters out duplicates of recently received datagrams.
+///
+/// Some networks occasionally deliver a datagram twice. The receiver
+/// remembers a hash of each of the last `window` distinct datagrams it
+/// received, and reports a datagram whose contents match one of them as a
+/// duplicate. Hashes are randomly keyed per receiver, so a peer can't craft
+/// collisions to get distinct datagrams dropped.
+///
+/// Since duplicates are detected by content alone, two deliberately
+/// identical datagrams within the window are also treated as duplicates;
+/// include a sequence number or nonce in each one if that matters.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, DedupReceiver};
+/// use std::net::UdpSocket;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///     let client_addr = conn_client.local_addr()?;
+///
+///     host.send_to(b"event 1", client_addr)?;
+///     host.send_to(b"event 1", client_addr)?;
+///
+///     let receiver = DedupReceiver::new(conn_client, 64);
+///     let mut buf = [0; 32];
+///     assert_eq!(receiver.recv_dedup(&mut buf)?, Some(7));
+///     assert_eq!(receiver.recv_dedup(&mut buf)?, None);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DedupReceiver {
+    socket: ConnectedUdpSocket,
+    window: usize,
+    hasher: RandomState,
+    seen: Mutex<Seen>,
+}
+
+/// The hashes of recently received datagrams, oldest first.
+#[derive(Debug, Default)]
+struct Seen {
+    order: VecDeque<u64>,
+    hashes: HashSet<u64>,
+}
+
+impl DedupReceiver {
+    /// Wraps `socket`, remembering the last `window` distinct datagrams. A
+    /// `window` of zero remembers none, so nothing is filtered.
+    pub fn new(socket: ConnectedUdpSocket, window: usize) -> Self {
+        Self {
+            socket,
+            window,
+            hasher: RandomState::new(),
+            seen: Mutex::default(),
+        }
+    }
+
+    /// Receives a datagram like [`ConnectedUdpSocket::recv`], returning
+    /// `Ok(None)` if it duplicates one received recently.
+    ///
+    /// A duplicate's contents are still written into `buf`.
+    pub fn recv_dedup(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let n = self.socket.recv(buf)?;
+        if self.window == 0 {
+            return Ok(Some(n));
+        }
+
+        let hash = self.hasher.hash_one(&buf[..n]);
+        let mut seen = self.seen.lock().unwrap();
+        if !seen.hashes.insert(hash) {
+            return Ok(None);
+        }
+        seen.order.push_back(hash);
+        if seen.order.len() > self.window {
+            let oldest = seen.order.pop_front().unwrap();
+            seen.hashes.remove(&oldest);
+        }
+        Ok(Some(n))
+    }
+
+    /// Returns the wrapped socket, e.g. for sending.
+    pub fn socket(&self) -> &ConnectedUdpSocket {
+        &self.socket
+    }
+
+    /// Unwraps the socket, forgetting the datagrams seen so far.
+    pub fn into_inner(self) -> ConnectedUdpSocket {
+        self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn recv_dedup_filters_duplicate() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let dedup = DedupReceiver::new(sender_conn, 8);
+
+        receiver.send_to(b"ping", sender_addr).unwrap();
+        receiver.send_to(b"ping", sender_addr).unwrap();
+        receiver.send_to(b"pong", sender_addr).unwrap();
+
+        let mut buf = [0u8; 8];
+        assert_eq!(dedup.recv_dedup(&mut buf).unwrap(), Some(4));
+        assert_eq!(dedup.recv_dedup(&mut buf).unwrap(), None);
+        assert_eq!(dedup.recv_dedup(&mut buf).unwrap(), Some(4));
+        assert_eq!(&buf[..4], b"pong");
+    }
+
+    #[test]
+    fn recv_dedup_forgets_outside_window() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let dedup = DedupReceiver::new(sender_conn, 1);
+
+        for msg in [b"one", b"two", b"one"] {
+            receiver.send_to(msg, sender_addr).unwrap();
+        }
+
+        // "two" pushed "one" out of the single-datagram window.
+        let mut buf = [0u8; 8];
+        for _ in 0..3 {
+            assert_eq!(dedup.recv_dedup(&mut buf).unwrap(), Some(3));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::lossy::Rng;
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::ops::RangeInclusive;
+use std::thread;
+use std::time::Duration;
+
+/// A wrapper that holds up sends and receives, to simulate the latency of a
+/// WAN link when testing timeout handling.
+///
+/// Each [`send`](Self::send) waits out the delay before sending, and each
+/// [`recv`](Self::recv) waits it out after receiving, so the caller sees
+/// every datagram that much later. The delay is either fixed or drawn
+/// uniformly at random from a range for every call. The calling thread
+/// sleeps through it, so datagrams are delayed one after another rather
+/// than in flight together.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, DelayedSocket};
+/// use std::net::UdpSocket;
+/// use std::time::{Duration, Instant};
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///
+///     let delayed = DelayedSocket::new(conn_client, Duration::from_millis(20));
+///     let started = Instant::now();
+///     delayed.send(b"ping")?;
+///     assert!(started.elapsed() >= Duration::from_millis(20));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DelayedSocket {
+    socket: ConnectedUdpSocket,
+    min: Duration,
+    max: Duration,
+    rng: Rng,
+}
+
+impl DelayedSocket {
+    /// Wraps `socket`, delaying every send and receive by `delay`.
+    pub fn new(socket: ConnectedUdpSocket, delay: Duration) -> Self {
+        Self::with_random_delay(socket, delay..=delay)
+    }
+
+    /// Wraps `socket`, delaying every send and receive by a random duration
+    /// within `delays`.
+    ///
+    /// A range whose start is after its end is treated as running from the
+    /// end to the start.
+    pub fn with_random_delay(socket: ConnectedUdpSocket, delays: RangeInclusive<Duration>) -> Self {
+        let (start, end) = delays.into_inner();
+        Self {
+            socket,
+            min: start.min(end),
+            max: start.max(end),
+            rng: Rng::new(),
+        }
+    }
+
+    /// Sends a datagram like [`ConnectedUdpSocket::send`], after the delay.
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        thread::sleep(self.delay());
+        self.socket.send(buf)
+    }
+
+    /// Receives a datagram like [`ConnectedUdpSocket::recv`], returning it
+    /// only after the delay.
+    ///
+    /// The delay comes on top of any wait for the datagram, and doesn't count
+    /// against the socket's read timeout. Errors are returned right away.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.socket.recv(buf)?;
+        thread::sleep(self.delay());
+        Ok(n)
+    }
+
+    /// Returns the wrapped socket, e.g. for traffic that shouldn't be
+    /// delayed.
+    pub fn socket(&self) -> &ConnectedUdpSocket {
+        &self.socket
+    }
+
+    /// Unwraps the socket.
+    pub fn into_inner(self) -> ConnectedUdpSocket {
+        self.socket
+    }
+
+    /// Picks the delay for the next call.
+    fn delay(&self) -> Duration {
+        self.min + (self.max - self.min).mul_f64(self.rng.next_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::time::Instant;
+
+    #[test]
+    fn send_is_delayed() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let delayed = DelayedSocket::new(sender_conn, Duration::from_millis(50));
+
+        let started = Instant::now();
+        assert_eq!(delayed.send(b"ping").unwrap(), 4);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let mut buf = [0u8; 8];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 4);
+    }
+
+    #[test]
+    fn random_delay_stays_in_range() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let delayed = DelayedSocket::with_random_delay(
+            sender_conn,
+            Duration::from_millis(30)..=Duration::from_millis(10),
+        );
+
+        for _ in 0..100 {
+            let delay = delayed.delay();
+            assert!((Duration::from_millis(10)..=Duration::from_millis(30)).contains(&delay));
+        }
+
+        receiver.send_to(b"pong", sender_addr).unwrap();
+        let mut buf = [0u8; 8];
+        let started = Instant::now();
+        assert_eq!(delayed.recv(&mut buf).unwrap(), 4);
+        assert!(started.elapsed() >= Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the notifier thread checks the send buffer.
+const POLL_SLICE: Duration = Duration::from_millis(10);
+
+impl ConnectedUdpSocket {
+    /// Spawns a thread that calls `on_drained` whenever the room left in the
+    /// send buffer rises above `low_watermark` bytes.
+    ///
+    /// This is a portable stand-in for edge-triggered writability: a
+    /// nonblocking producer that hits [`WouldBlock`](io::ErrorKind::WouldBlock)
+    /// can pause until it's notified, then resume. The thread polls
+    /// [`send_buffer_available`](Self::send_buffer_available) every 10ms, and
+    /// only fires on a rise, i.e. after having seen no more than
+    /// `low_watermark` bytes available. The first reading is taken before
+    /// this returns, so a buffer that's already above the watermark doesn't
+    /// fire until it's dropped to or below it again.
+    ///
+    /// The notifier runs until the returned [`NotifierHandle`] is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the socket can't be cloned for the thread, or
+    /// its send buffer can't be queried.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::sync::mpsc;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let (resume, _resumed) = mpsc::channel();
+    ///  let notifier = conn_client
+    ///      .spawn_drain_notifier(16 * 1024, move || {
+    ///          let _ = resume.send(());
+    ///      })
+    ///      .expect("couldn't start notifier");
+    ///  drop(notifier);
+    /// # }
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn spawn_drain_notifier(
+        &self,
+        low_watermark: usize,
+        on_drained: impl Fn() + Send + 'static,
+    ) -> io::Result<NotifierHandle> {
+        let conn = self.try_clone()?;
+        let drained = conn.send_buffer_available()? > low_watermark;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || notify(&conn, low_watermark, drained, &stop, on_drained))
+        };
+
+        Ok(NotifierHandle {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A handle to a notifier started by
+/// [`ConnectedUdpSocket::spawn_drain_notifier`].
+///
+/// Dropping the handle stops the notifier.
+#[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+#[derive(Debug)]
+pub struct NotifierHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for NotifierHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Calls `on_drained` each time the room in `conn`'s send buffer rises above
+/// `low_watermark`, until `stop` is set. `drained` is whether it was above
+/// the watermark at the last reading.
+fn notify(
+    conn: &ConnectedUdpSocket,
+    low_watermark: usize,
+    mut drained: bool,
+    stop: &AtomicBool,
+    on_drained: impl Fn(),
+) {
+    while !stop.load(Ordering::Acquire) {
+        // A failed reading is unlikely to mean anything but a closing
+        // socket, so it's simply retried.
+        if let Ok(available) = conn.send_buffer_available() {
+            let above = available > low_watermark;
+            if above && !drained {
+                on_drained();
+            }
+            drained = above;
+        }
+        thread::sleep(POLL_SLICE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn drain_notifier_fires_once_buffer_drains() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // Loopback hands datagrams straight to the receiver, so the send
+        // queue never backs up. Shrinking the buffer below the watermark
+        // stands in for filling it up, and growing it again for draining it.
+        sender_conn.set_send_buffer_size(1).unwrap();
+        let low_watermark = sender_conn.send_buffer_available().unwrap();
+
+        let fired = Arc::new(AtomicUsize::new(0));
+        let notifier = {
+            let fired = Arc::clone(&fired);
+            sender_conn
+                .spawn_drain_notifier(low_watermark, move || {
+                    fired.fetch_add(1, Ordering::Relaxed);
+                })
+                .unwrap()
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(fired.load(Ordering::Relaxed), 0);
+
+        sender_conn.set_send_buffer_size(64 * 1024).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        drop(notifier);
+        assert_eq!(fired.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::sys::{self, Interest};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long the echo thread waits for a datagram before checking its stop
+/// flag again.
+const POLL_SLICE: Duration = Duration::from_millis(10);
+
+/// The largest datagram the echo server echoes back in full.
+const MAX_DATAGRAM_LEN: usize = 64 * 1024;
+
+/// Spawns a UDP echo server on a loopback port, returning its address and a
+/// handle that stops it when dropped.
+///
+/// The server sends every datagram it receives straight back to its sender.
+/// It's meant as a quick peer for tests and tooling.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{spawn_echo_server, ConnectedUdpSocket};
+/// use std::net::UdpSocket;
+///
+/// fn main() -> std::io::Result<()> {
+///     let (echo_addr, _echo) = spawn_echo_server()?;
+///
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, echo_addr)?;
+///     conn_client.send(b"ping")?;
+///
+///     let mut buf = [0; 32];
+///     let n = conn_client.recv(&mut buf)?;
+///     assert_eq!(&buf[..n], b"ping");
+///
+///     Ok(())
+/// }
+/// ```
+pub fn spawn_echo_server() -> io::Result<(SocketAddr, EchoHandle)> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    let addr = socket.local_addr()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || echo(&socket, &stop))
+    };
+
+    Ok((
+        addr,
+        EchoHandle {
+            stop,
+            thread: Some(thread),
+        },
+    ))
+}
+
+/// A handle to an echo server started by [`spawn_echo_server`].
+///
+/// Dropping the handle stops the server.
+#[derive(Debug)]
+pub struct EchoHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for EchoHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Echoes datagrams back to their senders until `stop` is set.
+fn echo(socket: &UdpSocket, stop: &AtomicBool) {
+    let mut buf = vec![0; MAX_DATAGRAM_LEN];
+    while !stop.load(Ordering::Acquire) {
+        if !matches!(
+            sys::poll(socket, Interest::Readable, Some(POLL_SLICE)),
+            Ok(true)
+        ) {
+            continue;
+        }
+        if let Ok((n, from)) = socket.recv_from(&mut buf) {
+            let _ = socket.send_to(&buf[..n], from);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConnectedUdpSocket;
+
+    #[test]
+    fn echo_round_trip() {
+        let (echo_addr, echo) = spawn_echo_server().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_client = ConnectedUdpSocket::connect(client, echo_addr).unwrap();
+
+        for msg in [&b"ping"[..], &b"pong"[..]] {
+            conn_client.send(msg).unwrap();
+            let mut buf = [0u8; 32];
+            let n = conn_client.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], msg);
+        }
+
+        drop(echo);
+    }
+}
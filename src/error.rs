@@ -0,0 +1,99 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+/// Errors specific to `connected-udp`.
+///
+/// These are returned wrapped in an [`io::Error`] of a matching
+/// [`io::ErrorKind`], so every method keeps returning [`io::Result`]. To tell
+/// them apart, downcast the inner error:
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, Error};
+/// use std::net::UdpSocket;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let mut conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///
+///     conn_client.unset_peer();
+///     let err = conn_client.send(b"ping").unwrap_err();
+///     assert!(matches!(
+///         err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+///         Some(Error::NotConnected)
+///     ));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The socket's peer was cleared with
+    /// [`unset_peer`](crate::ConnectedUdpSocket::unset_peer) and it hasn't
+    /// been reconnected since.
+    NotConnected,
+    /// A zero-length datagram was received on a socket whose
+    /// [`EmptyDatagramPolicy`](crate::EmptyDatagramPolicy) treats them as
+    /// errors.
+    EmptyDatagram,
+    /// [`recv_verified`](crate::ConnectedUdpSocket::recv_verified) discarded
+    /// as many datagrams from addresses other than the peer as it was allowed
+    /// to without receiving one from the peer.
+    TooManyForeignDatagrams,
+    /// A datagram's authentication tag didn't match its contents, so it was
+    /// forged, corrupted or sent with a different key. Returned by
+    /// `recv_authed`, which needs the `auth` feature.
+    AuthenticationFailed,
+    /// The underlying socket is connected to a different address than the
+    /// cached peer, e.g. because it was reconnected directly. Returned by
+    /// [`peer_addr_result`](crate::ConnectedUdpSocket::peer_addr_result).
+    PeerMismatch {
+        /// The cached peer.
+        expected: SocketAddr,
+        /// The address the socket is actually connected to.
+        actual: SocketAddr,
+    },
+}
+
+impl Error {
+    /// Returns the [`io::ErrorKind`] this error is reported as.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::NotConnected => io::ErrorKind::NotConnected,
+            Error::EmptyDatagram => io::ErrorKind::InvalidData,
+            Error::TooManyForeignDatagrams => io::ErrorKind::InvalidData,
+            Error::AuthenticationFailed => io::ErrorKind::PermissionDenied,
+            Error::PeerMismatch { .. } => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotConnected => f.write_str("socket has no peer; reconnect it first"),
+            Error::EmptyDatagram => f.write_str("received an empty datagram"),
+            Error::TooManyForeignDatagrams => {
+                f.write_str("too many datagrams received from addresses other than the peer")
+            }
+            Error::AuthenticationFailed => f.write_str("datagram failed authentication"),
+            Error::PeerMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "socket is connected to {actual}, not the peer {expected}"
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
@@ -0,0 +1,258 @@
+use crate::connected_udp::remaining;
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+impl ConnectedUdpSocket {
+    /// Resolves `host`, then races probed connections to its addresses in
+    /// the "Happy Eyeballs" order of RFC 8305, returning the first that gets
+    /// a reply.
+    ///
+    /// Addresses are tried alternating between IPv6 and IPv4, starting with
+    /// IPv6. Each attempt takes a fresh socket from the factory for its
+    /// family and [probes](Self::connect_with_probe) the address with
+    /// `probe`. The next attempt starts once the previous one fails, or
+    /// after `delay` if it's still waiting for a reply, so a slow IPv6 path
+    /// costs at most `delay` before IPv4 gets a chance. Attempts that are
+    /// still waiting when one succeeds are abandoned; they give up on their
+    /// own by `timeout`, which bounds the whole connection.
+    ///
+    /// The peer must reply to `probe`, since an unanswered UDP datagram
+    /// can't be told apart from a lost one. Resolution goes through
+    /// [`ToSocketAddrs`], so it blocks like any lookup of `(host, port)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `host`
+    /// resolves to no addresses, and of kind [`io::ErrorKind::TimedOut`] if
+    /// no attempt succeeds within `timeout`. If every attempt fails sooner,
+    /// the last attempt's error is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use connected_udp::{spawn_echo_server, ConnectedUdpSocket};
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let (echo_addr, _echo) = spawn_echo_server()?;
+    ///
+    ///     let conn = ConnectedUdpSocket::connect_happy_eyeballs(
+    ///         || UdpSocket::bind("0.0.0.0:0"),
+    ///         || UdpSocket::bind("[::]:0"),
+    ///         "localhost",
+    ///         echo_addr.port(),
+    ///         b"hello?",
+    ///         Duration::from_millis(250),
+    ///         Duration::from_secs(1),
+    ///     )?;
+    ///     assert_eq!(conn.peer_addr().port(), echo_addr.port());
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn connect_happy_eyeballs<F4, F6>(
+        mut socket_v4_factory: F4,
+        mut socket_v6_factory: F6,
+        host: &str,
+        port: u16,
+        probe: &[u8],
+        delay: Duration,
+        timeout: Duration,
+    ) -> io::Result<Self>
+    where
+        F4: FnMut() -> io::Result<UdpSocket>,
+        F6: FnMut() -> io::Result<UdpSocket>,
+    {
+        let deadline = Instant::now() + timeout;
+        let addrs = (host, port).to_socket_addrs()?.collect();
+        race(
+            interleave(addrs),
+            |addr| match addr {
+                SocketAddr::V4(_) => socket_v4_factory(),
+                SocketAddr::V6(_) => socket_v6_factory(),
+            },
+            probe,
+            delay,
+            deadline,
+        )
+    }
+}
+
+/// Orders `addrs` alternating between IPv6 and IPv4, starting with IPv6 and
+/// otherwise keeping the resolver's order.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    let mut ordered = Vec::new();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (first, second) => ordered.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+/// Probes `candidates` in order, starting each attempt `delay` after the
+/// previous one (or as soon as it fails), and returns the first connection
+/// that gets a reply before `deadline`.
+fn race(
+    candidates: Vec<SocketAddr>,
+    mut socket_for: impl FnMut(SocketAddr) -> io::Result<UdpSocket>,
+    probe: &[u8],
+    delay: Duration,
+    deadline: Instant,
+) -> io::Result<ConnectedUdpSocket> {
+    let probe: Arc<[u8]> = probe.into();
+    let (tx, rx) = mpsc::channel();
+    let mut candidates = candidates.into_iter().peekable();
+    let mut last_err = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "could not resolve to any addresses",
+    );
+    let mut in_flight = 0;
+    let mut next_start = Instant::now();
+
+    loop {
+        if Instant::now() >= next_start {
+            if let Some(addr) = candidates.next() {
+                match socket_for(addr) {
+                    Ok(socket) => {
+                        let timeout = remaining(deadline)?;
+                        let probe = Arc::clone(&probe);
+                        let tx = tx.clone();
+                        thread::spawn(move || {
+                            let res = ConnectedUdpSocket::connect_with_probe(
+                                socket, addr, &probe, timeout,
+                            );
+                            // The race may already be over.
+                            let _ = tx.send(res);
+                        });
+                        in_flight += 1;
+                        next_start = Instant::now() + delay;
+                    }
+                    // A family that can't be used shouldn't hold up the other.
+                    Err(e) => last_err = e,
+                }
+                continue;
+            }
+        }
+        if in_flight == 0 && candidates.peek().is_none() {
+            return Err(last_err);
+        }
+
+        let wait_until = match candidates.peek() {
+            Some(_) => next_start.min(deadline),
+            None => deadline,
+        };
+        match rx.recv_timeout(wait_until.saturating_duration_since(Instant::now())) {
+            Ok(Ok(conn)) => return Ok(conn),
+            Ok(Err(e)) => {
+                in_flight -= 1;
+                last_err = e;
+                next_start = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                remaining(deadline)?;
+            }
+            Err(RecvTimeoutError::Disconnected) => unreachable!("the sender is still held"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    /// Binds `addr` and echoes the first datagram it receives.
+    fn echo_once(addr: &str) -> io::Result<SocketAddr> {
+        let socket = UdpSocket::bind(addr)?;
+        let addr = socket.local_addr()?;
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            if let Ok((n, from)) = socket.recv_from(&mut buf) {
+                let _ = socket.send_to(&buf[..n], from);
+            }
+        });
+        Ok(addr)
+    }
+
+    fn family_socket(addr: SocketAddr) -> io::Result<UdpSocket> {
+        match addr {
+            SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)),
+            SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)),
+        }
+    }
+
+    #[test]
+    fn interleave_alternates_starting_with_v6() {
+        let v4a: SocketAddr = "192.0.2.1:53".parse().unwrap();
+        let v4b: SocketAddr = "192.0.2.2:53".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:53".parse().unwrap();
+        assert_eq!(interleave(vec![v4a, v4b, v6]), vec![v6, v4a, v4b]);
+    }
+
+    #[test]
+    fn happy_eyeballs_prefers_v6() {
+        // Not every environment has IPv6 loopback.
+        let Ok(v6_addr) = echo_once("[::1]:0") else {
+            return;
+        };
+        let v4_addr = echo_once("127.0.0.1:0").unwrap();
+
+        let conn = race(
+            interleave(vec![v4_addr, v6_addr]),
+            family_socket,
+            b"hello?",
+            Duration::from_secs(1),
+            Instant::now() + Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(conn.peer_addr(), v6_addr);
+    }
+
+    #[test]
+    fn happy_eyeballs_falls_back_to_v4() {
+        // Not every environment has IPv6 loopback.
+        let Ok(silent) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        let v6_addr = silent.local_addr().unwrap();
+        let v4_addr = echo_once("127.0.0.1:0").unwrap();
+
+        let started = Instant::now();
+        let conn = race(
+            interleave(vec![v4_addr, v6_addr]),
+            family_socket,
+            b"hello?",
+            Duration::from_millis(100),
+            Instant::now() + Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(conn.peer_addr(), v4_addr);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn happy_eyeballs_resolves_host() {
+        let v4_addr = echo_once("127.0.0.1:0").unwrap();
+
+        let conn = ConnectedUdpSocket::connect_happy_eyeballs(
+            || UdpSocket::bind("127.0.0.1:0"),
+            || UdpSocket::bind("[::1]:0"),
+            "127.0.0.1",
+            v4_addr.port(),
+            b"hello?",
+            Duration::from_millis(100),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(conn.peer_addr(), v4_addr);
+    }
+}
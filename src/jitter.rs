@@ -0,0 +1,189 @@
+use crate::connected_udp::{remaining, timed_out};
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Round-trip jitter measured by [`ConnectedUdpSocket::measure_jitter`].
+///
+/// Each sample is how much the round-trip time of one echoed probe differed
+/// from that of the probe before it, i.e. the variation in their spacing on
+/// arrival compared to when they were sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct JitterStats {
+    /// The smallest variation between consecutive probes.
+    pub min: Duration,
+    /// The largest variation between consecutive probes.
+    pub max: Duration,
+    /// The mean variation.
+    pub mean: Duration,
+    /// The standard deviation of the variation.
+    pub stddev: Duration,
+    /// How many probes were echoed back in time.
+    pub received: u32,
+}
+
+impl ConnectedUdpSocket {
+    /// Sends `count` numbered probes, one every `interval`, to a peer that
+    /// echoes them back, and measures how much their round-trip times vary.
+    ///
+    /// Each probe is its sequence number as a 4-byte big-endian datagram,
+    /// and is given up on if its echo doesn't arrive within `timeout`.
+    /// Variation is only measured between consecutive probes that were both
+    /// echoed, so lost probes don't skew the result; late echoes and other
+    /// datagrams are discarded. The socket's read and write timeouts are
+    /// restored before returning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `count` is
+    /// less than 2, and of kind [`io::ErrorKind::TimedOut`] if no two
+    /// consecutive probes were echoed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use connected_udp::{spawn_echo_server, ConnectedUdpSocket};
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let (echo_addr, _echo) = spawn_echo_server()?;
+    ///
+    ///     let client = UdpSocket::bind("127.0.0.1:0")?;
+    ///     let conn_client = ConnectedUdpSocket::connect(client, echo_addr)?;
+    ///     let jitter = conn_client.measure_jitter(
+    ///         10,
+    ///         Duration::from_millis(5),
+    ///         Duration::from_secs(1),
+    ///     )?;
+    ///     println!("mean jitter: {:?}", jitter.mean);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn measure_jitter(
+        &self,
+        count: u32,
+        interval: Duration,
+        timeout: Duration,
+    ) -> io::Result<JitterStats> {
+        if count < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "measuring jitter needs at least 2 probes",
+            ));
+        }
+        let rtts = self.restoring_timeouts(|conn| conn.probe_sequence(count, interval, timeout))?;
+        let received = rtts.iter().flatten().count() as u32;
+        let samples: Vec<f64> = rtts
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Some(prev), Some(next)] => Some(next.abs_diff(*prev).as_secs_f64()),
+                _ => None,
+            })
+            .collect();
+        if samples.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "too few consecutive probes were echoed to measure jitter",
+            ));
+        }
+
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        Ok(JitterStats {
+            min: Duration::from_secs_f64(samples.iter().copied().fold(f64::INFINITY, f64::min)),
+            max: Duration::from_secs_f64(samples.iter().copied().fold(0.0, f64::max)),
+            mean: Duration::from_secs_f64(mean),
+            stddev: Duration::from_secs_f64(variance.sqrt()),
+            received,
+        })
+    }
+
+    /// Sends the probes for [`measure_jitter`](Self::measure_jitter),
+    /// returning each one's round-trip time, or `None` if it wasn't echoed
+    /// in time.
+    fn probe_sequence(
+        &self,
+        count: u32,
+        interval: Duration,
+        timeout: Duration,
+    ) -> io::Result<Vec<Option<Duration>>> {
+        let start = Instant::now();
+        let mut rtts = Vec::with_capacity(count as usize);
+        let mut buf = [0; 4];
+        for seq in 0..count {
+            let next_send = start + interval * seq;
+            thread::sleep(next_send.saturating_duration_since(Instant::now()));
+
+            let sent = Instant::now();
+            let deadline = sent + timeout;
+            self.as_ref().set_write_timeout(Some(timeout))?;
+            self.send(&seq.to_be_bytes()).map_err(timed_out)?;
+
+            let mut rtt = None;
+            while let Ok(left) = remaining(deadline) {
+                self.as_ref().set_read_timeout(Some(left))?;
+                match self.recv(&mut buf) {
+                    Ok(4) if buf == seq.to_be_bytes() => {
+                        rtt = Some(sent.elapsed());
+                        break;
+                    }
+                    Ok(_) => continue,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        continue
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            rtts.push(rtt);
+        }
+        Ok(rtts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn_echo_server;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn measure_jitter_on_loopback() {
+        let (echo_addr, _echo) = spawn_echo_server().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, echo_addr).unwrap();
+
+        let jitter = sender_conn
+            .measure_jitter(10, Duration::from_millis(2), Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(jitter.received, 10);
+        assert!(jitter.min <= jitter.mean && jitter.mean <= jitter.max);
+        // Loopback round trips barely vary.
+        assert!(jitter.max < Duration::from_millis(100), "{jitter:?}");
+        assert_eq!(sender_conn.as_ref().read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn measure_jitter_without_echoes_times_out() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let err = sender_conn
+            .measure_jitter(2, Duration::ZERO, Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
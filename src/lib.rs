@@ -31,7 +31,89 @@
 //! standard library types.
 //!
 //! See the [struct docs](`ConnectedUdpSocket`) for more details.
+//!
+//! # `Builder`
+//!
+//! [`Builder`] configures extra behavior that's applied while a
+//! [`ConnectedUdpSocket`] is being connected.
+//!
+//! # `Transport`
+//!
+//! The [`Transport`] trait abstracts over connection-oriented transports so
+//! code can be generic over whether it runs on a [`ConnectedUdpSocket`] or
+//! something else. [`SecureTransport`] is the seam for layering a security
+//! protocol such as DTLS over a socket.
 
+mod allowlist;
+mod as_bytes;
+#[cfg(feature = "auth")]
+mod auth;
+mod builder;
+mod chunked;
+mod coalescing;
+mod config;
 mod connected_udp;
+mod correlator;
+mod dedup;
+mod delayed;
+#[cfg(target_os = "linux")]
+mod drain;
+mod echo;
+mod error;
+mod happy_eyeballs;
+mod jitter;
+mod limits;
+#[cfg(target_os = "linux")]
+mod linux;
+mod liveness;
+mod lossy;
+mod peer_group;
+mod reorder;
+pub mod sans_io;
+mod scheduled;
+mod secure;
+#[cfg(feature = "socks5")]
+mod socks5;
+mod stats;
+mod sys;
+#[cfg(feature = "tokio")]
+mod tokio_socket;
+mod transport;
+#[cfg(unix)]
+mod unix;
+mod v6;
+mod watchdog;
+
+pub use allowlist::AllowlistUdp;
+pub use as_bytes::AsBytes;
+pub use builder::Builder;
+pub use coalescing::CoalescingSender;
+pub use config::SocketConfigSnapshot;
+pub use connected_udp::{ByteOrder, ConnectedUdpSocket, EmptyDatagramPolicy};
+pub use correlator::Correlator;
+pub use dedup::DedupReceiver;
+pub use delayed::DelayedSocket;
+#[cfg(target_os = "linux")]
+pub use drain::NotifierHandle;
+pub use echo::{spawn_echo_server, EchoHandle};
+pub use error::Error;
+pub use jitter::JitterStats;
+pub use limits::max_send_buffer_size;
+#[cfg(target_os = "linux")]
+pub use linux::{IcmpError, KernelSocketStats};
+pub use liveness::LivenessHandle;
+pub use lossy::LossySocket;
+pub use peer_group::PeerGroup;
+pub use reorder::Reorderer;
+pub use scheduled::SendHandle;
+pub use secure::{SecureSocket, SecureTransport};
+pub use stats::ConnectedUdpStats;
+#[cfg(feature = "tokio")]
+pub use tokio_socket::AsyncConnectedUdpSocket;
+pub use transport::Transport;
+pub use v6::ConnectedUdpSocketV6View;
+pub use watchdog::WatchdogHandle;
 
-pub use connected_udp::ConnectedUdpSocket;
+/// The type of an OS socket, as returned by
+/// [`ConnectedUdpSocket::socket_type`].
+pub use socket2::Type as SockType;
@@ -0,0 +1,71 @@
+use std::io;
+
+/// Returns the largest send buffer (`SO_SNDBUF`) size, in bytes, the OS lets
+/// an unprivileged process request, e.g. as the ceiling for
+/// [`ConnectedUdpSocket::set_send_buffer_size`](crate::ConnectedUdpSocket::set_send_buffer_size).
+///
+/// On Linux this reads `net.core.wmem_max` from
+/// `/proc/sys/net/core/wmem_max`. Note that Linux doubles a requested size to
+/// account for bookkeeping overhead, so
+/// [`send_buffer_size`](crate::ConnectedUdpSocket::send_buffer_size) can
+/// report up to twice this value. Elsewhere a scratch socket is probed with
+/// increasingly large sizes, up to 1 GiB, and the largest size that sticks is
+/// returned.
+///
+/// # Examples
+///
+/// ```
+/// let max = connected_udp::max_send_buffer_size().expect("couldn't probe the limit");
+/// println!("send buffers can be up to {} bytes", max);
+/// ```
+pub fn max_send_buffer_size() -> io::Result<usize> {
+    #[cfg(target_os = "linux")]
+    return read_wmem_max();
+    #[cfg(not(target_os = "linux"))]
+    return probe_send_buffer_size();
+}
+
+#[cfg(target_os = "linux")]
+fn read_wmem_max() -> io::Result<usize> {
+    let max = std::fs::read_to_string("/proc/sys/net/core/wmem_max")?;
+    max.trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed wmem_max"))
+}
+
+/// Doubles the send buffer size of a scratch socket until the OS refuses or
+/// clamps it, returning the largest size that was applied.
+#[cfg(not(target_os = "linux"))]
+fn probe_send_buffer_size() -> io::Result<usize> {
+    use socket2::{Domain, Socket, Type};
+
+    const LIMIT: usize = 1 << 30;
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    let mut max = socket.send_buffer_size()?;
+    let mut size = max.max(4096);
+    while size <= LIMIT {
+        if socket.set_send_buffer_size(size).is_err() {
+            break;
+        }
+        let applied = socket.send_buffer_size()?;
+        if applied < size {
+            return Ok(applied.max(max));
+        }
+        max = applied;
+        size *= 2;
+    }
+    Ok(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_send_buffer_size_is_plausible() {
+        let max = max_send_buffer_size().unwrap();
+        assert!(max >= 1024);
+        assert!(max <= 1 << 31);
+    }
+}
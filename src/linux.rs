@@ -0,0 +1,1467 @@
+//! Linux-specific extensions to [`ConnectedUdpSocket`].
+
+use crate::sys::{getsockopt, setsockopt};
+use crate::ConnectedUdpSocket;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::fd::{AsRawFd, RawFd};
+use std::ptr;
+
+/// The most segments the kernel accepts in a single GSO send
+/// (`UDP_MAX_SEGMENTS`).
+const UDP_MAX_SEGMENTS: usize = 64;
+
+/// The largest UDP payload the kernel accepts in a single GSO send.
+const MAX_GSO_PAYLOAD: usize = u16::MAX as usize - 8 - 40;
+
+/// `SO_MEMINFO`, which `libc` doesn't define.
+#[cfg(not(any(target_arch = "sparc", target_arch = "sparc64")))]
+const SO_MEMINFO: libc::c_int = 55;
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+const SO_MEMINFO: libc::c_int = 0x0039;
+
+/// The number of counters `SO_MEMINFO` reports (`SK_MEMINFO_VARS`).
+const SK_MEMINFO_VARS: usize = 9;
+
+/// Kernel-side memory usage and drop counts for a socket, as returned by
+/// [`ConnectedUdpSocket::socket_stats`].
+#[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct KernelSocketStats {
+    /// Bytes of receive buffer in use by queued datagrams, including the
+    /// kernel's bookkeeping overhead.
+    pub recv_queued: u32,
+    /// The receive buffer's size limit (`SO_RCVBUF`).
+    pub recv_buffer: u32,
+    /// Bytes of send buffer in use by datagrams not yet handed to the
+    /// device, including the kernel's bookkeeping overhead.
+    pub send_queued: u32,
+    /// The send buffer's size limit (`SO_SNDBUF`).
+    pub send_buffer: u32,
+    /// The number of incoming datagrams dropped over the socket's lifetime,
+    /// e.g. because the receive buffer was full.
+    pub drops: u32,
+}
+
+/// An ICMP error (or locally generated error) reported for a datagram sent
+/// on the socket, as returned by [`ConnectedUdpSocket::recv_error`].
+#[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IcmpError {
+    /// The error number the kernel translated the error to, e.g.
+    /// `ECONNREFUSED` for an ICMP port unreachable. See
+    /// [`error`](Self::error) for the equivalent [`io::Error`].
+    pub errno: i32,
+    /// Where the error came from (`SO_EE_ORIGIN_*`): `1` for the local
+    /// stack, `2` for ICMP, and `3` for ICMPv6.
+    pub origin: u8,
+    /// The ICMP type, if the error came from an ICMP message.
+    pub icmp_type: u8,
+    /// The ICMP code, if the error came from an ICMP message.
+    pub icmp_code: u8,
+    /// Extra information for some errors, e.g. the next-hop MTU for a
+    /// "fragmentation needed" error.
+    pub info: u32,
+    /// The address of the host that reported the error, if known.
+    pub offender: Option<SocketAddr>,
+}
+
+impl IcmpError {
+    /// Returns the error as an [`io::Error`], e.g. one of kind
+    /// [`io::ErrorKind::ConnectionRefused`] for an ICMP port unreachable.
+    pub fn error(&self) -> io::Error {
+        io::Error::from_raw_os_error(self.errno)
+    }
+}
+
+impl ConnectedUdpSocket {
+    /// Creates one socket per peer, all bound to `addr` with `SO_REUSEPORT`,
+    /// and connects each to its peer.
+    ///
+    /// This scales a server across threads: each socket can be served by its
+    /// own thread, and the kernel steers a peer's datagrams to the socket
+    /// connected to it, even though they all share one local address. If
+    /// `addr` has port 0, the first socket picks a port and the rest bind to
+    /// the same one. Sockets bound to `addr` without `SO_REUSEPORT`, or by
+    /// another user, make binding fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let a = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to peer address");
+    ///  let b = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to peer address");
+    ///  let peers = [a.local_addr().unwrap(), b.local_addr().unwrap()];
+    ///
+    ///  let group = ConnectedUdpSocket::reuseport_group("127.0.0.1:0".parse().unwrap(), &peers)
+    ///      .expect("couldn't create group");
+    ///  assert_eq!(group[0].local_addr().unwrap(), group[1].local_addr().unwrap());
+    /// # }
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn reuseport_group(addr: SocketAddr, peers: &[SocketAddr]) -> io::Result<Vec<Self>> {
+        let mut bind_addr = addr;
+        peers
+            .iter()
+            .map(|&peer| {
+                let socket =
+                    Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+                socket.set_reuse_port(true)?;
+                socket.bind(&bind_addr.into())?;
+                let socket = UdpSocket::from(socket);
+                bind_addr = socket.local_addr()?;
+                Self::connect(socket, peer)
+            })
+            .collect()
+    }
+
+    /// Sends `buf` as a series of `segment_size`-byte datagrams using Generic
+    /// Segmentation Offload (`UDP_SEGMENT`), returning the number of bytes
+    /// sent.
+    ///
+    /// The kernel splits the buffer into datagrams itself, so a large buffer
+    /// can go out in a few syscalls instead of one per datagram. The final
+    /// datagram may be shorter than `segment_size`.
+    ///
+    /// GSO requires Linux 4.18 or later. On older kernels, or when the
+    /// outgoing device can't offload checksums, this falls back to sending
+    /// each segment with its own `send` call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if
+    /// `segment_size` is zero, and of kind [`io::ErrorKind::Unsupported`] if
+    /// the socket relays through SOCKS5.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn send_gso(&self, buf: &[u8], segment_size: u16) -> io::Result<usize> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        let segment_size = usize::from(segment_size);
+        if segment_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GSO segment size must be non-zero",
+            ));
+        }
+        if buf.len() <= segment_size {
+            return self.send(buf);
+        }
+        if !self.gso_supported() {
+            return self.send_segments(buf, segment_size);
+        }
+
+        let segments_per_send = (MAX_GSO_PAYLOAD / segment_size).clamp(1, UDP_MAX_SEGMENTS);
+        let mut sent = 0;
+        for batch in buf.chunks(segments_per_send * segment_size) {
+            let mut scrambled = Vec::new();
+            let wire = if self.is_obfuscated() {
+                scrambled.extend_from_slice(batch);
+                self.obfuscate_segments(&mut scrambled, segment_size);
+                &scrambled
+            } else {
+                batch
+            };
+            match sendmsg_gso(self.raw_fd(), wire, segment_size as u16) {
+                Ok(n) => {
+                    self.stats_counters()
+                        .record_send(n.div_ceil(segment_size), n);
+                    sent += n;
+                }
+                // The device can't offload this send, so do the work ourselves.
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => {
+                    sent += self.send_segments(batch, segment_size)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Receives a datagram using Generic Receive Offload (`UDP_GRO`),
+    /// returning the number of bytes read and the size of each coalesced
+    /// segment.
+    ///
+    /// Once GRO is turned on with [`set_gro`](Self::set_gro), the kernel may
+    /// hand back several same-sized datagrams from the peer as one buffer;
+    /// split it into `segment_size`-byte chunks (the last one may be shorter)
+    /// to recover the individual datagrams. `buf` should be large enough to
+    /// hold a full coalesced buffer, i.e. up to 64 KiB.
+    ///
+    /// With GRO off, this behaves like a plain [`recv`](Self::recv) and
+    /// reports the datagram's own length as the segment size.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn recv_gro(&self, buf: &mut [u8]) -> io::Result<(usize, u16)> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        let mut segment_size = None;
+        let msg = recvmsg(self.raw_fd(), buf, 0, |cmsg, data| {
+            if cmsg.cmsg_level == libc::SOL_UDP && cmsg.cmsg_type == libc::UDP_GRO {
+                segment_size = read_cmsg::<libc::c_int>(data).map(|size| size as u16);
+            }
+        })?;
+        self.recv_clock().touch();
+        let segment_size = segment_size.unwrap_or_else(|| clamp_segment_size(msg.len));
+        self.obfuscate_segments(&mut buf[..msg.len], usize::from(segment_size));
+        self.stats_counters().record_recv(
+            msg.len.div_ceil(usize::from(segment_size).max(1)).max(1),
+            msg.len,
+        );
+        Ok((msg.len, segment_size))
+    }
+
+    /// Sets whether the kernel coalesces incoming datagrams using Generic
+    /// Receive Offload (`UDP_GRO`).
+    ///
+    /// This affects every receive on the socket, not just
+    /// [`recv_gro`](Self::recv_gro): with GRO on, [`recv`](Self::recv) and
+    /// the other receive methods may also return several datagrams glued
+    /// into one buffer without a way to tell where they split, so only turn
+    /// it on for sockets read solely with `recv_gro`. Only datagrams queued
+    /// after GRO is turned on are coalesced.
+    ///
+    /// GRO requires Linux 5.0 or later; on older kernels this returns an
+    /// error.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn set_gro(&self, gro: bool) -> io::Result<()> {
+        setsockopt(
+            self.raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_GRO,
+            libc::c_int::from(gro),
+        )
+    }
+
+    /// Returns whether the kernel coalesces incoming datagrams (`UDP_GRO`).
+    ///
+    /// See [`set_gro`](Self::set_gro) for details.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn gro(&self) -> io::Result<bool> {
+        let value: libc::c_int = getsockopt(self.raw_fd(), libc::SOL_UDP, libc::UDP_GRO)?;
+        Ok(value != 0)
+    }
+
+    /// Receives a datagram along with the kernel's count of datagrams dropped
+    /// on this socket (`SO_RXQ_OVFL`), returning `(bytes_read, drops)`.
+    ///
+    /// The drop counter is cumulative over the socket's lifetime and reflects
+    /// drops that happened before the returned datagram was queued, e.g.
+    /// because the receive buffer was full. Counting starts once the first
+    /// call enables `SO_RXQ_OVFL`.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn recv_with_drops(&self, buf: &mut [u8]) -> io::Result<(usize, u32)> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        self.enable_rxq_ovfl()?;
+
+        let mut drops = 0;
+        let msg = recvmsg(self.raw_fd(), buf, 0, |cmsg, data| {
+            if cmsg.cmsg_level == libc::SOL_SOCKET && cmsg.cmsg_type == libc::SO_RXQ_OVFL {
+                drops = read_cmsg::<u32>(data).unwrap_or(0);
+            }
+        })?;
+        self.obfuscate_segments(&mut buf[..msg.len], msg.len);
+        self.recv_clock().touch();
+        self.stats_counters().record_recv(1, msg.len);
+        Ok((msg.len, drops))
+    }
+
+    /// Turns on `SO_RXQ_OVFL` for the socket.
+    fn enable_rxq_ovfl(&self) -> io::Result<()> {
+        setsockopt::<libc::c_int>(self.raw_fd(), libc::SOL_SOCKET, libc::SO_RXQ_OVFL, 1)
+    }
+
+    /// Receives a datagram along with the TTL (or, over IPv6, the hop limit)
+    /// it arrived with, returning `(bytes_read, ttl)`.
+    ///
+    /// Comparing this against the TTL the peer sends with reveals how many
+    /// hops the return path takes, which helps spot asymmetric routing. The
+    /// first call enables `IP_RECVTTL` (and `IPV6_RECVHOPLIMIT` for IPv6
+    /// sockets); a datagram that was queued before then, or that arrives
+    /// without the control message, fails with an error of kind
+    /// [`io::ErrorKind::InvalidData`].
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn recv_with_ttl(&self, buf: &mut [u8]) -> io::Result<(usize, u8)> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        self.enable_recvttl()?;
+
+        let mut ttl = None;
+        let msg = recvmsg(self.raw_fd(), buf, 0, |cmsg, data| {
+            let is_ttl = (cmsg.cmsg_level == libc::IPPROTO_IP && cmsg.cmsg_type == libc::IP_TTL)
+                || (cmsg.cmsg_level == libc::IPPROTO_IPV6 && cmsg.cmsg_type == libc::IPV6_HOPLIMIT);
+            if is_ttl {
+                ttl = read_cmsg::<libc::c_int>(data).map(|ttl| ttl as u8);
+            }
+        })?;
+        self.obfuscate_segments(&mut buf[..msg.len], msg.len);
+        self.recv_clock().touch();
+        self.stats_counters().record_recv(1, msg.len);
+        let ttl = ttl.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "datagram arrived without its TTL",
+            )
+        })?;
+        Ok((msg.len, ttl))
+    }
+
+    /// Turns on `IP_RECVTTL` (and `IPV6_RECVHOPLIMIT` for IPv6 sockets, which
+    /// can also carry IPv4 traffic).
+    fn enable_recvttl(&self) -> io::Result<()> {
+        if SockRef::from(self.as_ref()).domain()? == Domain::IPV6 {
+            setsockopt::<libc::c_int>(
+                self.raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_RECVHOPLIMIT,
+                1,
+            )?;
+            // Dual-stack sockets report IPv4 TTLs through the IPv4 option,
+            // which IPv6-only sockets may reject.
+            let _ = setsockopt::<libc::c_int>(self.raw_fd(), libc::IPPROTO_IP, libc::IP_RECVTTL, 1);
+            Ok(())
+        } else {
+            setsockopt::<libc::c_int>(self.raw_fd(), libc::IPPROTO_IP, libc::IP_RECVTTL, 1)
+        }
+    }
+
+    /// Receives a datagram along with the address it was originally sent to,
+    /// returning `(bytes_read, original_destination)`.
+    ///
+    /// On a socket receiving traffic redirected by `TPROXY`, the original
+    /// destination is where the client meant the datagram to go, which a
+    /// transparent proxy needs to forward it. Otherwise it's the local
+    /// address the datagram arrived on, which is still useful for
+    /// wildcard-bound sockets. The first call enables `IP_RECVORIGDSTADDR`
+    /// (and `IPV6_RECVORIGDSTADDR` for IPv6 sockets); a datagram that was
+    /// queued before then fails with an error of kind
+    /// [`io::ErrorKind::InvalidData`].
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn recv_orig_dst(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        self.enable_recvorigdstaddr()?;
+
+        let mut orig_dst = None;
+        let msg = recvmsg(self.raw_fd(), buf, 0, |cmsg, data| {
+            let is_orig_dst = (cmsg.cmsg_level == libc::IPPROTO_IP
+                && cmsg.cmsg_type == libc::IP_ORIGDSTADDR)
+                || (cmsg.cmsg_level == libc::IPPROTO_IPV6
+                    && cmsg.cmsg_type == libc::IPV6_ORIGDSTADDR);
+            if is_orig_dst {
+                orig_dst = read_sockaddr(data);
+            }
+        })?;
+        self.obfuscate_segments(&mut buf[..msg.len], msg.len);
+        self.recv_clock().touch();
+        self.stats_counters().record_recv(1, msg.len);
+        let orig_dst = orig_dst.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "datagram arrived without its original destination",
+            )
+        })?;
+        Ok((msg.len, orig_dst))
+    }
+
+    /// Turns on `IP_RECVORIGDSTADDR` (and `IPV6_RECVORIGDSTADDR` for IPv6
+    /// sockets, which can also carry IPv4 traffic).
+    fn enable_recvorigdstaddr(&self) -> io::Result<()> {
+        if SockRef::from(self.as_ref()).domain()? == Domain::IPV6 {
+            setsockopt::<libc::c_int>(
+                self.raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_RECVORIGDSTADDR,
+                1,
+            )?;
+            // Dual-stack sockets report IPv4 destinations through the IPv4
+            // option, which IPv6-only sockets may reject.
+            let _ = setsockopt::<libc::c_int>(
+                self.raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_RECVORIGDSTADDR,
+                1,
+            );
+            Ok(())
+        } else {
+            setsockopt::<libc::c_int>(self.raw_fd(), libc::IPPROTO_IP, libc::IP_RECVORIGDSTADDR, 1)
+        }
+    }
+
+    /// Receives a datagram like [`recv`](Self::recv), also returning whether
+    /// it was truncated: `(bytes_read, truncated)`.
+    ///
+    /// A datagram longer than `buf` has its excess silently discarded by the
+    /// OS, so an undersized buffer otherwise goes unnoticed. This checks the
+    /// `MSG_TRUNC` flag `recvmsg` reports. `bytes_read` is what fit in
+    /// `buf`, not the datagram's full length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  host.send_to(b"a longer reply", conn_client.local_addr().unwrap())
+    ///      .expect("couldn't send datagram");
+    ///
+    ///  let mut buf = [0; 8];
+    ///  let (n, truncated) = conn_client.recv_checked_trunc(&mut buf).expect("couldn't receive datagram");
+    ///  assert_eq!(n, 8);
+    ///  assert!(truncated);
+    /// # }
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn recv_checked_trunc(&self, buf: &mut [u8]) -> io::Result<(usize, bool)> {
+        self.ensure_peer()?;
+        self.ensure_direct()?;
+        let msg = recvmsg(self.raw_fd(), buf, 0, |_, _| {})?;
+        self.obfuscate_segments(&mut buf[..msg.len], msg.len);
+        self.recv_clock().touch();
+        self.stats_counters().record_recv(1, msg.len);
+        Ok((msg.len, msg.truncated))
+    }
+
+    /// Steers the socket's receive processing to the given CPU
+    /// (`SO_INCOMING_CPU`).
+    ///
+    /// Pinning a receiver thread and its socket to the same core keeps
+    /// packet processing cache- and NUMA-local. Requires Linux 3.19 or
+    /// later; on older kernels this returns an error.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn set_incoming_cpu(&self, cpu: i32) -> io::Result<()> {
+        setsockopt(self.raw_fd(), libc::SOL_SOCKET, libc::SO_INCOMING_CPU, cpu)
+    }
+
+    /// Returns the CPU the socket's receive processing is steered to
+    /// (`SO_INCOMING_CPU`).
+    ///
+    /// See [`set_incoming_cpu`](Self::set_incoming_cpu) for details.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn incoming_cpu(&self) -> io::Result<i32> {
+        getsockopt(self.raw_fd(), libc::SOL_SOCKET, libc::SO_INCOMING_CPU)
+    }
+
+    /// Sets how long, in microseconds, a blocking receive busy-polls the
+    /// device queue for new packets before sleeping (`SO_BUSY_POLL`).
+    ///
+    /// Busy-polling can shave microseconds off receive latency, at the cost
+    /// of keeping a CPU core spinning while the socket waits. Zero disables
+    /// it. Raising the value above the `net.core.busy_read` sysctl requires
+    /// `CAP_NET_ADMIN`; without it this returns an error of kind
+    /// [`io::ErrorKind::PermissionDenied`].
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn set_busy_poll(&self, microseconds: u32) -> io::Result<()> {
+        let value = libc::c_int::try_from(microseconds).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "busy poll time is too large")
+        })?;
+        setsockopt(self.raw_fd(), libc::SOL_SOCKET, libc::SO_BUSY_POLL, value)
+    }
+
+    /// Returns how long, in microseconds, a blocking receive busy-polls
+    /// before sleeping (`SO_BUSY_POLL`).
+    ///
+    /// See [`set_busy_poll`](Self::set_busy_poll) for details.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn busy_poll(&self) -> io::Result<u32> {
+        let value: libc::c_int = getsockopt(self.raw_fd(), libc::SOL_SOCKET, libc::SO_BUSY_POLL)?;
+        Ok(value as u32)
+    }
+
+    /// Reads the next error from the socket's error queue, or returns `None`
+    /// if it's empty.
+    ///
+    /// The kernel only queues errors once `IP_RECVERR` (or `IPV6_RECVERR`)
+    /// is enabled, which the first call does, so call this once up front to
+    /// start collecting them. Each queued error carries the ICMP type and
+    /// code and the reporting host, which pins down failures much better
+    /// than the bare [`io::ErrorKind`] a later send or receive would fail
+    /// with. This never blocks.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn recv_error(&self) -> io::Result<Option<IcmpError>> {
+        self.enable_recverr()?;
+
+        let mut error = None;
+        let res = recvmsg(
+            self.raw_fd(),
+            &mut [],
+            libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+            |cmsg, data| {
+                let is_recverr = (cmsg.cmsg_level == libc::IPPROTO_IP
+                    && cmsg.cmsg_type == libc::IP_RECVERR)
+                    || (cmsg.cmsg_level == libc::IPPROTO_IPV6
+                        && cmsg.cmsg_type == libc::IPV6_RECVERR);
+                if is_recverr {
+                    error = read_extended_err(data);
+                }
+            },
+        );
+        match res {
+            Ok(_) => Ok(error),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Turns on `IP_RECVERR` (and `IPV6_RECVERR` for IPv6 sockets, which can
+    /// also carry IPv4 traffic).
+    fn enable_recverr(&self) -> io::Result<()> {
+        if SockRef::from(self.as_ref()).domain()? == Domain::IPV6 {
+            setsockopt::<libc::c_int>(self.raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
+            // Dual-stack sockets report IPv4 errors through the IPv4 option,
+            // which IPv6-only sockets may reject.
+            let _ = setsockopt::<libc::c_int>(self.raw_fd(), libc::IPPROTO_IP, libc::IP_RECVERR, 1);
+            Ok(())
+        } else {
+            setsockopt::<libc::c_int>(self.raw_fd(), libc::IPPROTO_IP, libc::IP_RECVERR, 1)
+        }
+    }
+
+    /// Returns roughly how many more bytes can be queued for sending before
+    /// the send buffer fills up: `SO_SNDBUF` minus the bytes still queued
+    /// (`SIOCOUTQ`).
+    ///
+    /// The kernel charges each queued datagram for its bookkeeping overhead
+    /// as well as its payload, so the estimate is optimistic for many small
+    /// datagrams. It's still useful for pacing: a sender can hold off on a
+    /// large datagram that clearly won't fit.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn send_buffer_available(&self) -> io::Result<usize> {
+        let capacity: libc::c_int = getsockopt(self.raw_fd(), libc::SOL_SOCKET, libc::SO_SNDBUF)?;
+
+        let mut queued: libc::c_int = 0;
+        // SAFETY: `SIOCOUTQ` (which shares its value with `TIOCOUTQ`) writes
+        // a single `c_int` through the pointer.
+        let ret = unsafe { libc::ioctl(self.raw_fd(), libc::TIOCOUTQ, &mut queued) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((capacity as usize).saturating_sub(queued as usize))
+    }
+
+    /// Returns the socket's cookie (`SO_COOKIE`), a kernel-assigned
+    /// identifier that's unique among all sockets for as long as the system
+    /// runs.
+    ///
+    /// The cookie is what eBPF programs and `ss -e` report, so it ties this
+    /// socket to what those tools observe. Requires Linux 4.12 or later.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn so_cookie(&self) -> io::Result<u64> {
+        getsockopt(self.raw_fd(), libc::SOL_SOCKET, libc::SO_COOKIE)
+    }
+
+    /// Returns the kernel's memory usage and drop counts for the socket
+    /// (`SO_MEMINFO`).
+    ///
+    /// Unlike [`stats`](Self::stats), which counts traffic through this
+    /// wrapper, these come from the kernel, so they include datagrams it
+    /// dropped before the application ever saw them. Requires Linux 4.3 or
+    /// later.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn socket_stats(&self) -> io::Result<KernelSocketStats> {
+        let meminfo: [u32; SK_MEMINFO_VARS] =
+            getsockopt(self.raw_fd(), libc::SOL_SOCKET, SO_MEMINFO)?;
+        let field = |index: libc::c_int| meminfo[index as usize];
+        Ok(KernelSocketStats {
+            recv_queued: field(libc::SK_MEMINFO_RMEM_ALLOC),
+            recv_buffer: field(libc::SK_MEMINFO_RCVBUF),
+            send_queued: field(libc::SK_MEMINFO_WMEM_ALLOC),
+            send_buffer: field(libc::SK_MEMINFO_SNDBUF),
+            drops: field(libc::SK_MEMINFO_DROPS),
+        })
+    }
+
+    /// Returns the index of the network interface that datagrams to the peer
+    /// leave through.
+    ///
+    /// This is the interface that owns the local address the kernel chose
+    /// for the peer (see [`egress_addr`](Self::egress_addr)). For IPv6
+    /// link-local addresses it's the address's scope id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::AddrNotAvailable`] if no
+    /// local address has been chosen, and [`io::ErrorKind::NotFound`] if no
+    /// interface owns the local address (e.g. it was just removed).
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn egress_ifindex(&self) -> io::Result<u32> {
+        let local = self.egress_addr()?;
+        if let SocketAddr::V6(local) = local {
+            if local.scope_id() != 0 {
+                return Ok(local.scope_id());
+            }
+        }
+        ifindex_of(local.ip().to_canonical())
+    }
+
+    /// Sets whether outgoing datagrams have the "don't fragment" (DF) bit set.
+    ///
+    /// With DF enabled (`IP_PMTUDISC_DO`), datagrams larger than the known
+    /// path MTU fail to send instead of being fragmented. With it disabled
+    /// (`IP_PMTUDISC_DONT`), the kernel fragments them as needed.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn set_dont_fragment(&self, dont_fragment: bool) -> io::Result<()> {
+        let mode = if dont_fragment {
+            libc::IP_PMTUDISC_DO
+        } else {
+            libc::IP_PMTUDISC_DONT
+        };
+        self.set_mtu_discover(mode)
+    }
+
+    /// Returns whether outgoing datagrams have the "don't fragment" (DF) bit
+    /// set.
+    ///
+    /// See [`set_dont_fragment`](Self::set_dont_fragment) for details.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn dont_fragment(&self) -> io::Result<bool> {
+        let mode = self.mtu_discover()?;
+        Ok(mode == libc::IP_PMTUDISC_DO || mode == libc::IP_PMTUDISC_PROBE)
+    }
+
+    /// Returns the largest payload [`send`](Self::send) accepts right now.
+    ///
+    /// This is the most UDP can carry in one IP packet (65,507 bytes over
+    /// IPv4, 65,527 over IPv6), further limited with
+    /// [DF](Self::set_dont_fragment) enabled to what fits in the kernel's
+    /// current [path MTU](Self::path_mtu) estimate, less the IP and UDP
+    /// headers. The answer changes as DF is toggled and as the kernel learns
+    /// about the path, so query it right before sizing a datagram. Like
+    /// [`will_fragment`](Self::will_fragment), it assumes no IP options or
+    /// IPv6 extension headers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let max = conn_client.max_sendable().expect("couldn't compute the limit");
+    ///  conn_client.send(&vec![0; max]).expect("couldn't send datagram");
+    /// # }
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn max_sendable(&self) -> io::Result<usize> {
+        let ip_header = self.ip_header_len();
+        // The IPv4 total length covers the IP header; the IPv6 payload
+        // length doesn't.
+        let protocol_limit = match ip_header {
+            20 => u16::MAX as usize - 20 - 8,
+            _ => u16::MAX as usize - 8,
+        };
+        if !self.dont_fragment()? {
+            return Ok(protocol_limit);
+        }
+        let mtu_limit = self.path_mtu()?.saturating_sub(ip_header + 8);
+        Ok(protocol_limit.min(mtu_limit))
+    }
+
+    /// Discovers the largest payload between `low` and `high` (inclusive)
+    /// that can be sent to the peer without fragmentation.
+    ///
+    /// DF is enabled for the duration of the search, then restored to its
+    /// previous setting. The search binary-searches the range by actually
+    /// sending datagrams of the candidate sizes to the peer, so the peer
+    /// should be prepared to receive (and ignore) them. No UDP payload is
+    /// larger than 65,535 bytes, so `high` is capped at that.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `low` is
+    /// greater than `high` or 65,535, and the send error if even a
+    /// `low`-byte payload can't be sent.
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn discover_pmtu(&self, low: usize, high: usize) -> io::Result<usize> {
+        let high = high.min(usize::from(u16::MAX));
+        if low > high {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PMTU search range is empty",
+            ));
+        }
+
+        let prior = self.mtu_discover()?;
+        self.set_dont_fragment(true)?;
+        let res = self.search_pmtu(low, high);
+        let restored = self.set_mtu_discover(prior);
+        let mtu = res?;
+        restored?;
+        Ok(mtu)
+    }
+
+    /// Binary-searches `low..=high` for the largest payload that sends.
+    fn search_pmtu(&self, low: usize, high: usize) -> io::Result<usize> {
+        let probe = vec![0; high];
+        self.send(&probe[..low])?;
+
+        // Invariant: `low` bytes fit, `high + 1` bytes may not.
+        let (mut low, mut high) = (low, high);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            match self.send(&probe[..mid]) {
+                Ok(_) => low = mid,
+                Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => high = mid - 1,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(low)
+    }
+
+    /// Returns the socket's path MTU discovery mode (`IP_MTU_DISCOVER` or
+    /// `IPV6_MTU_DISCOVER`).
+    fn mtu_discover(&self) -> io::Result<libc::c_int> {
+        let (level, name) = self.mtu_discover_opt()?;
+        getsockopt(self.raw_fd(), level, name)
+    }
+
+    fn set_mtu_discover(&self, mode: libc::c_int) -> io::Result<()> {
+        let (level, name) = self.mtu_discover_opt()?;
+        setsockopt(self.raw_fd(), level, name, mode)
+    }
+
+    /// Returns the level and name of the path MTU discovery option for this
+    /// socket's address family.
+    fn mtu_discover_opt(&self) -> io::Result<(libc::c_int, libc::c_int)> {
+        if SockRef::from(self.as_ref()).domain()? == Domain::IPV6 {
+            Ok((libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER))
+        } else {
+            Ok((libc::IPPROTO_IP, libc::IP_MTU_DISCOVER))
+        }
+    }
+
+    /// Returns whether the kernel knows about `UDP_SEGMENT`.
+    fn gso_supported(&self) -> bool {
+        getsockopt::<libc::c_int>(self.raw_fd(), libc::SOL_UDP, libc::UDP_SEGMENT).is_ok()
+    }
+
+    /// Sends `buf` one `segment_size`-byte datagram at a time.
+    fn send_segments(&self, buf: &[u8], segment_size: usize) -> io::Result<usize> {
+        let mut sent = 0;
+        for segment in buf.chunks(segment_size) {
+            sent += self.send(segment)?;
+        }
+        Ok(sent)
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        self.as_ref().as_raw_fd()
+    }
+}
+
+/// Sends `buf` with a `UDP_SEGMENT` control message attached.
+fn sendmsg_gso(fd: RawFd, buf: &[u8], segment_size: u16) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut control = CmsgBuf::new();
+
+    // SAFETY: an all-zero `msghdr` is a valid, empty message.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    control.push(&mut msg, libc::SOL_UDP, libc::UDP_SEGMENT, segment_size);
+
+    // SAFETY: `msg` points at `iov` and `control`, which outlive the call.
+    let ret = unsafe { libc::sendmsg(fd, &msg, 0) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+/// Returns the index of the interface that has `ip` assigned to it.
+fn ifindex_of(ip: IpAddr) -> io::Result<u32> {
+    let mut addrs: *mut libc::ifaddrs = ptr::null_mut();
+    // SAFETY: `addrs` is a valid place for the list head.
+    if unsafe { libc::getifaddrs(&mut addrs) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut found = None;
+    let mut ifa = addrs;
+    // SAFETY: `getifaddrs` returned a well-formed linked list, whose address
+    // structs match the family they're tagged with.
+    unsafe {
+        while !ifa.is_null() {
+            let addr = (*ifa).ifa_addr;
+            let matches = !addr.is_null()
+                && match i32::from((*addr).sa_family) {
+                    libc::AF_INET => {
+                        let addr = &*addr.cast::<libc::sockaddr_in>();
+                        IpAddr::from(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))) == ip
+                    }
+                    libc::AF_INET6 => {
+                        let addr = &*addr.cast::<libc::sockaddr_in6>();
+                        IpAddr::from(Ipv6Addr::from(addr.sin6_addr.s6_addr)) == ip
+                    }
+                    _ => false,
+                };
+            if matches {
+                found = Some(libc::if_nametoindex((*ifa).ifa_name));
+                break;
+            }
+            ifa = (*ifa).ifa_next;
+        }
+        libc::freeifaddrs(addrs);
+    }
+
+    match found {
+        Some(0) => Err(io::Error::last_os_error()),
+        Some(index) => Ok(index),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no interface has the local address",
+        )),
+    }
+}
+
+/// Parses an `IP_RECVERR` control message: a `sock_extended_err` followed by
+/// the offender's address.
+fn read_extended_err(data: &[u8]) -> Option<IcmpError> {
+    let err = read_cmsg::<libc::sock_extended_err>(data)?;
+    let offender = data
+        .get(mem::size_of::<libc::sock_extended_err>()..)
+        .and_then(read_sockaddr)
+        .filter(|addr| !addr.ip().is_unspecified());
+    Some(IcmpError {
+        errno: err.ee_errno as i32,
+        origin: err.ee_origin,
+        icmp_type: err.ee_type,
+        icmp_code: err.ee_code,
+        info: err.ee_info,
+        offender,
+    })
+}
+
+/// Parses a `sockaddr_in` or `sockaddr_in6` out of control message data.
+fn read_sockaddr(data: &[u8]) -> Option<SocketAddr> {
+    let family = read_cmsg::<libc::sa_family_t>(data)?;
+    match i32::from(family) {
+        libc::AF_INET => {
+            let addr = read_cmsg::<libc::sockaddr_in>(data)?;
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::new(ip.into(), u16::from_be(addr.sin_port)))
+        }
+        libc::AF_INET6 => {
+            let addr = read_cmsg::<libc::sockaddr_in6>(data)?;
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::new(ip.into(), u16::from_be(addr.sin6_port)))
+        }
+        _ => None,
+    }
+}
+
+/// Clamps a datagram length to the `u16` range used for segment sizes.
+fn clamp_segment_size(len: usize) -> u16 {
+    u16::try_from(len).unwrap_or(u16::MAX)
+}
+
+/// The outcome of a [`recvmsg`] call.
+struct RecvMsg {
+    /// The number of bytes written into the data buffer.
+    len: usize,
+    /// Whether the datagram didn't fit in the data buffer (`MSG_TRUNC`).
+    truncated: bool,
+}
+
+/// Receives a datagram into `buf` with `recvmsg`, passing each control
+/// message (and its data) that arrives with it to `on_cmsg`.
+fn recvmsg(
+    fd: RawFd,
+    buf: &mut [u8],
+    flags: libc::c_int,
+    mut on_cmsg: impl FnMut(&libc::cmsghdr, &[u8]),
+) -> io::Result<RecvMsg> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut control = [0u64; 16];
+
+    // SAFETY: an all-zero `msghdr` is a valid, empty message.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // SAFETY: `msg` points at `iov` and `control`, which outlive the call.
+    let ret = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: the kernel filled in `msg_control` with well-formed control
+    // messages, which the `CMSG_*` macros walk without leaving the buffer.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            let header_len = data.offset_from(cmsg.cast::<u8>()) as usize;
+            let data_len = ((*cmsg).cmsg_len as usize).saturating_sub(header_len);
+            on_cmsg(&*cmsg, std::slice::from_raw_parts(data, data_len));
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok(RecvMsg {
+        len: ret as usize,
+        truncated: msg.msg_flags & libc::MSG_TRUNC != 0,
+    })
+}
+
+/// Reads a `T` out of a control message's data, if it's large enough.
+fn read_cmsg<T: Copy>(data: &[u8]) -> Option<T> {
+    if data.len() < mem::size_of::<T>() {
+        return None;
+    }
+    // SAFETY: `data` holds at least `size_of::<T>()` bytes, and control
+    // message payloads are plain integers or structs.
+    Some(unsafe { ptr::read_unaligned(data.as_ptr().cast::<T>()) })
+}
+
+/// An aligned buffer for outgoing control messages.
+struct CmsgBuf {
+    buf: [u64; 8],
+    len: usize,
+}
+
+impl CmsgBuf {
+    fn new() -> Self {
+        Self {
+            buf: [0; 8],
+            len: 0,
+        }
+    }
+
+    /// Appends a control message carrying `value` and points `msg` at the
+    /// buffer.
+    fn push<T: Copy>(
+        &mut self,
+        msg: &mut libc::msghdr,
+        level: libc::c_int,
+        ty: libc::c_int,
+        value: T,
+    ) {
+        let data_len = mem::size_of::<T>() as libc::c_uint;
+        // SAFETY: `CMSG_SPACE` and `CMSG_LEN` are pure arithmetic.
+        let (space, len) = unsafe { (libc::CMSG_SPACE(data_len), libc::CMSG_LEN(data_len)) };
+        assert!(
+            self.len + space as usize <= mem::size_of_val(&self.buf),
+            "control message buffer overflow"
+        );
+
+        // SAFETY: the assertion above guarantees the header and data fit within
+        // `buf`, which is aligned for `cmsghdr`.
+        unsafe {
+            let cmsg = self
+                .buf
+                .as_mut_ptr()
+                .cast::<u8>()
+                .add(self.len)
+                .cast::<libc::cmsghdr>();
+            (*cmsg).cmsg_level = level;
+            (*cmsg).cmsg_type = ty;
+            (*cmsg).cmsg_len = len as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<T>(), value);
+        }
+        self.len += space as usize;
+
+        msg.msg_control = self.buf.as_mut_ptr().cast();
+        msg.msg_controllen = self.len as _;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn send_gso_splits_segments() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let payload: Vec<u8> = (0..250u32).map(|i| i as u8).collect();
+        assert_eq!(sender_conn.send_gso(&payload, 100).unwrap(), 250);
+
+        let mut received = Vec::new();
+        let mut lens = Vec::new();
+        for _ in 0..3 {
+            let mut buf = [0u8; 512];
+            let n = receiver.recv(&mut buf).unwrap();
+            lens.push(n);
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(lens, [100, 100, 50]);
+        assert_eq!(received, payload);
+    }
+
+    #[test]
+    fn recv_gro_coalesces_segments() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let send_addr = sender.local_addr().unwrap();
+
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, send_addr).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // GRO has to be on before the datagrams are queued for them to be
+        // coalesced.
+        if receiver_conn.set_gro(true).is_err() {
+            return;
+        }
+        assert!(receiver_conn.gro().unwrap());
+
+        let payload: Vec<u8> = (0..250u32).map(|i| i as u8).collect();
+        sender_conn.send_gso(&payload, 100).unwrap();
+
+        let mut buf = vec![0u8; u16::MAX as usize];
+        let mut received = Vec::new();
+        while received.len() < payload.len() {
+            let (n, segment_size) = receiver_conn.recv_gro(&mut buf).unwrap();
+            let segments: Vec<_> = buf[..n].chunks(usize::from(segment_size)).collect();
+            for segment in &segments[..segments.len() - 1] {
+                assert_eq!(segment.len(), 100);
+            }
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, payload);
+    }
+
+    #[test]
+    fn recv_gro_without_gro_reads_single_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let send_addr = sender.local_addr().unwrap();
+
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, send_addr).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        if receiver_conn.set_gro(true).is_err() {
+            return;
+        }
+        receiver_conn.set_gro(false).unwrap();
+        assert!(!receiver_conn.gro().unwrap());
+
+        let payload = [7u8; 250];
+        sender_conn.send_gso(&payload, 100).unwrap();
+        let mut buf = vec![0u8; u16::MAX as usize];
+        for expected in [100, 100, 50] {
+            let (n, segment_size) = receiver_conn.recv_gro(&mut buf).unwrap();
+            assert_eq!(n, expected);
+            assert_eq!(usize::from(segment_size), expected);
+        }
+    }
+
+    #[test]
+    fn obfuscated_gso_and_gro_round_trip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let send_addr = sender.local_addr().unwrap();
+
+        let receiver_conn = crate::Builder::new()
+            .obfuscate(b"lab key".to_vec())
+            .connect(receiver, send_addr)
+            .unwrap();
+        let sender_conn = crate::Builder::new()
+            .obfuscate(b"lab key".to_vec())
+            .connect(sender, recv_addr)
+            .unwrap();
+        let _ = receiver_conn.set_gro(true);
+        receiver_conn.enable_recvttl().unwrap();
+
+        // Every segment is obfuscated as a datagram of its own, whichever
+        // way it's received.
+        let payload: Vec<u8> = (0..250u32).map(|i| i as u8).collect();
+        sender_conn.send_gso(&payload, 100).unwrap();
+        let mut buf = vec![0u8; u16::MAX as usize];
+        let mut received = Vec::new();
+        while received.len() < payload.len() {
+            let (n, _) = receiver_conn.recv_gro(&mut buf).unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, payload);
+
+        sender_conn.send(b"ping").unwrap();
+        let (n, _) = receiver_conn.recv_with_ttl(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+    }
+
+    #[test]
+    fn recv_with_drops_counts_overflow() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let send_addr = sender.local_addr().unwrap();
+
+        SockRef::from(&receiver).set_recv_buffer_size(4096).unwrap();
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, send_addr).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // The kernel only stamps drop counts on datagrams queued while
+        // `SO_RXQ_OVFL` is on.
+        receiver_conn.enable_rxq_ovfl().unwrap();
+        for _ in 0..200 {
+            sender_conn.send(&[0u8; 1024]).unwrap();
+        }
+
+        // Drain what made it into the buffer, so the next datagram is queued
+        // after the drops happened.
+        receiver_conn.as_ref().set_nonblocking(true).unwrap();
+        let mut buf = [0u8; 2048];
+        while receiver_conn.recv_with_drops(&mut buf).is_ok() {}
+        receiver_conn.as_ref().set_nonblocking(false).unwrap();
+
+        sender_conn.send(b"after").unwrap();
+        let (n, drops) = receiver_conn.recv_with_drops(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"after");
+        assert!(drops > 0);
+    }
+
+    #[test]
+    fn nonblocking_empty_recvmsg_would_block() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        sender_conn.as_ref().set_nonblocking(true).unwrap();
+
+        let mut buf = [0u8; 8];
+        let err = sender_conn.recv_gro(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        let err = sender_conn.recv_with_drops(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn recv_with_ttl_on_loopback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let send_addr = sender.local_addr().unwrap();
+
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, send_addr).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        sender_conn.as_ref().set_ttl(42).unwrap();
+
+        // The TTL is only recorded for datagrams queued once it's enabled.
+        receiver_conn.enable_recvttl().unwrap();
+        sender_conn.send(b"ping").unwrap();
+
+        let mut buf = [0u8; 8];
+        let (n, ttl) = receiver_conn.recv_with_ttl(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        // Loopback doesn't route, so the datagram arrives as it was sent.
+        assert_eq!(ttl, 42);
+    }
+
+    #[test]
+    fn recv_checked_trunc_reports_truncation() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let send_addr = sender.local_addr().unwrap();
+
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, send_addr).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        sender_conn.send(&[7; 100]).unwrap();
+        let mut buf = [0u8; 10];
+        assert_eq!(
+            receiver_conn.recv_checked_trunc(&mut buf).unwrap(),
+            (10, true)
+        );
+        assert_eq!(buf, [7; 10]);
+
+        sender_conn.send(&[7; 10]).unwrap();
+        assert_eq!(
+            receiver_conn.recv_checked_trunc(&mut buf).unwrap(),
+            (10, false)
+        );
+    }
+
+    #[test]
+    fn recv_orig_dst_on_wildcard_socket() {
+        let receiver = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_port = receiver.local_addr().unwrap().port();
+        let send_addr = sender.local_addr().unwrap();
+
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, send_addr).unwrap();
+        receiver_conn.enable_recvorigdstaddr().unwrap();
+        let dst = SocketAddr::from((Ipv4Addr::LOCALHOST, recv_port));
+        sender.send_to(b"ping", dst).unwrap();
+
+        // Without TPROXY, the original destination is just the address the
+        // datagram was sent to.
+        let mut buf = [0u8; 8];
+        let (n, orig_dst) = receiver_conn.recv_orig_dst(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert_eq!(orig_dst, dst);
+    }
+
+    #[test]
+    fn incoming_cpu_round_trip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // Kernels without `SO_INCOMING_CPU` reject the option outright.
+        if sender_conn.set_incoming_cpu(0).is_err() {
+            return;
+        }
+        assert_eq!(sender_conn.incoming_cpu().unwrap(), 0);
+    }
+
+    #[test]
+    fn busy_poll_round_trip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // Unprivileged processes may not raise the busy poll time.
+        match sender_conn.set_busy_poll(50) {
+            Ok(()) => assert_eq!(sender_conn.busy_poll().unwrap(), 50),
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+        }
+    }
+
+    #[test]
+    fn send_buffer_available_on_idle_socket() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let available = sender_conn.send_buffer_available().unwrap();
+        assert!(available > 0);
+        assert!(available <= sender_conn.send_buffer_size().unwrap());
+    }
+
+    #[test]
+    fn socket_stats_reflect_queued_datagram() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let send_addr = sender.local_addr().unwrap();
+
+        let receiver_conn = ConnectedUdpSocket::connect(receiver, send_addr).unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        assert_eq!(receiver_conn.socket_stats().unwrap().recv_queued, 0);
+
+        sender_conn.send(&[0u8; 512]).unwrap();
+        // Wait for the datagram to be queued without consuming it.
+        let mut buf = [0u8; 1];
+        receiver_conn.as_ref().peek(&mut buf).unwrap();
+
+        let stats = receiver_conn.socket_stats().unwrap();
+        assert!(stats.recv_queued >= 512, "{stats:?}");
+        assert_eq!(
+            stats.recv_buffer as usize,
+            receiver_conn.recv_buffer_size().unwrap()
+        );
+        assert_eq!(
+            stats.send_buffer as usize,
+            receiver_conn.send_buffer_size().unwrap()
+        );
+        assert_eq!(stats.drops, 0);
+    }
+
+    #[test]
+    fn reuseport_group_connects_each_peer() {
+        let peers: Vec<_> = (0..3)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let peer_addrs: Vec<_> = peers.iter().map(|p| p.local_addr().unwrap()).collect();
+
+        let group =
+            ConnectedUdpSocket::reuseport_group("127.0.0.1:0".parse().unwrap(), &peer_addrs)
+                .unwrap();
+        assert_eq!(group.len(), 3);
+        let group_addr = group[0].local_addr().unwrap();
+
+        for ((conn, peer), peer_addr) in group.iter().zip(&peers).zip(&peer_addrs) {
+            assert_eq!(conn.local_addr().unwrap(), group_addr);
+            assert_eq!(conn.peer_addr(), *peer_addr);
+
+            // Each peer's datagrams reach the socket connected to it.
+            peer.send_to(b"hello", group_addr).unwrap();
+            let mut buf = [0u8; 8];
+            let n = conn.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"hello");
+        }
+    }
+
+    #[test]
+    fn so_cookie_is_stable() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let cookie = sender_conn.so_cookie().unwrap();
+        assert_ne!(cookie, 0);
+        assert_eq!(sender_conn.so_cookie().unwrap(), cookie);
+    }
+
+    #[test]
+    fn egress_ifindex_on_loopback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // SAFETY: the name is a valid C string.
+        let lo = unsafe { libc::if_nametoindex(c"lo".as_ptr()) };
+        assert_ne!(lo, 0);
+        assert_eq!(sender_conn.egress_ifindex().unwrap(), lo);
+    }
+
+    #[test]
+    fn recv_error_reports_port_unreachable() {
+        // Find a port nothing is listening on.
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, closed_addr).unwrap();
+
+        assert_eq!(sender_conn.recv_error().unwrap(), None);
+        sender_conn.send(b"ping").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let err = loop {
+            if let Some(err) = sender_conn.recv_error().unwrap() {
+                break err;
+            }
+            assert!(Instant::now() < deadline, "no error queued");
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(err.errno, libc::ECONNREFUSED);
+        assert_eq!(err.error().kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(err.origin, libc::SO_EE_ORIGIN_ICMP);
+        // Destination unreachable, port unreachable.
+        assert_eq!((err.icmp_type, err.icmp_code), (3, 3));
+        assert_eq!(err.offender.map(|addr| addr.ip()), Some(closed_addr.ip()));
+        assert_eq!(sender_conn.recv_error().unwrap(), None);
+    }
+
+    #[test]
+    fn dont_fragment_round_trip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        sender_conn.set_dont_fragment(true).unwrap();
+        assert!(sender_conn.dont_fragment().unwrap());
+        sender_conn.set_dont_fragment(false).unwrap();
+        assert!(!sender_conn.dont_fragment().unwrap());
+    }
+
+    #[test]
+    fn max_sendable_with_and_without_dont_fragment() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        sender_conn.set_dont_fragment(false).unwrap();
+        let fragmenting = sender_conn.max_sendable().unwrap();
+        assert_eq!(fragmenting, 65_507);
+
+        // Loopback's 64 KiB MTU holds any IPv4 packet, so DF doesn't lower
+        // the limit.
+        sender_conn.set_dont_fragment(true).unwrap();
+        let unfragmented = sender_conn.max_sendable().unwrap();
+        let mtu = sender_conn.path_mtu().unwrap();
+        assert_eq!(unfragmented, fragmenting.min(mtu - 28));
+
+        sender_conn.send(&vec![0; unfragmented]).unwrap();
+        let err = sender_conn.send(&vec![0; unfragmented + 1]).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EMSGSIZE));
+    }
+
+    #[test]
+    fn discover_pmtu_on_loopback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        sender_conn.set_dont_fragment(false).unwrap();
+
+        // Loopback's MTU is 64 KiB, so the limit is the largest UDP payload
+        // that fits in an IPv4 packet.
+        let mtu = sender_conn.discover_pmtu(1, 70_000).unwrap();
+        assert!((60_000..=65_507).contains(&mtu), "unexpected PMTU {mtu}");
+
+        assert!(!sender_conn.dont_fragment().unwrap());
+    }
+
+    #[test]
+    fn discover_pmtu_checks_range() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let mtu = sender_conn.discover_pmtu(1, usize::MAX).unwrap();
+        assert!((60_000..=65_507).contains(&mtu), "unexpected PMTU {mtu}");
+        for (low, high) in [(100, 99), (70_000, 80_000)] {
+            let err = sender_conn.discover_pmtu(low, high).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn send_gso_zero_segment_size_error() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let err = sender_conn.send_gso(b"ping", 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::sys::{self, Interest};
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the monitor thread waits between checks of its stop flag.
+const POLL_SLICE: Duration = Duration::from_millis(10);
+
+impl ConnectedUdpSocket {
+    /// Spawns a thread that periodically sends `probe` to the peer and
+    /// expects it to be echoed back, calling `on_dead` once `miss_threshold`
+    /// consecutive probes go unanswered.
+    ///
+    /// A probe counts as answered if a datagram identical to `probe` arrives
+    /// within `interval` of it being sent. The monitor only peeks at other
+    /// datagrams, leaving them queued for the socket's regular readers, but
+    /// it does consume the echoes. Once `on_dead` has been called the monitor
+    /// stops.
+    ///
+    /// The monitor runs until the returned [`LivenessHandle`] is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if
+    /// `miss_threshold` is zero, and of kind [`io::ErrorKind::Unsupported`]
+    /// if the socket relays through SOCKS5, since the monitor probes the
+    /// underlying socket directly.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let handle = conn_client
+    ///      .monitor_liveness(b"ping".to_vec(), Duration::from_secs(1), 3, || {
+    ///          eprintln!("peer went away");
+    ///      })
+    ///      .expect("couldn't start liveness monitor");
+    ///  drop(handle);
+    /// # }
+    /// ```
+    pub fn monitor_liveness(
+        &self,
+        probe: Vec<u8>,
+        interval: Duration,
+        miss_threshold: u32,
+        on_dead: impl Fn() + Send + 'static,
+    ) -> io::Result<LivenessHandle> {
+        if miss_threshold == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "liveness miss threshold must be non-zero",
+            ));
+        }
+        self.ensure_direct()?;
+        let socket = self.as_ref().try_clone()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let monitor = Monitor {
+                    socket,
+                    probe,
+                    interval,
+                    stop,
+                };
+                monitor.run(miss_threshold, on_dead);
+            })
+        };
+
+        Ok(LivenessHandle {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A handle to a liveness monitor started by
+/// [`ConnectedUdpSocket::monitor_liveness`].
+///
+/// Dropping the handle stops the monitor.
+#[derive(Debug)]
+pub struct LivenessHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for LivenessHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The state owned by a liveness monitor thread.
+struct Monitor {
+    socket: UdpSocket,
+    probe: Vec<u8>,
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+}
+
+impl Monitor {
+    fn run(&self, miss_threshold: u32, on_dead: impl Fn()) {
+        let mut misses = 0;
+        loop {
+            match self.probe_once() {
+                Some(true) => misses = 0,
+                Some(false) => misses += 1,
+                None => return,
+            }
+            if misses >= miss_threshold {
+                on_dead();
+                return;
+            }
+        }
+    }
+
+    /// Sends one probe and waits out the interval, returning whether it was
+    /// echoed, or `None` if the monitor was stopped.
+    fn probe_once(&self) -> Option<bool> {
+        let deadline = Instant::now() + self.interval;
+        // A failed send (e.g. a refused connection) is just a missed probe.
+        let _ = self.socket.send(&self.probe);
+
+        let mut answered = false;
+        let mut buf = vec![0; self.probe.len() + 1];
+        loop {
+            if self.stop.load(Ordering::Acquire) {
+                return None;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Some(answered);
+            }
+            let wait = POLL_SLICE.min(deadline - now);
+
+            if answered
+                || !matches!(
+                    sys::poll(&self.socket, Interest::Readable, Some(wait)),
+                    Ok(true)
+                )
+            {
+                thread::sleep(wait);
+                continue;
+            }
+
+            match self.socket.peek(&mut buf) {
+                Ok(n) if buf[..n] == self.probe[..] => {
+                    let _ = self.socket.recv(&mut buf);
+                    answered = true;
+                }
+                // Leave other datagrams for the socket's regular readers.
+                _ => thread::sleep(wait),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn silent_peer_is_reported_dead() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_client = ConnectedUdpSocket::connect(client, peer_addr).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let _handle = conn_client
+            .monitor_liveness(b"ping".to_vec(), Duration::from_millis(20), 3, move || {
+                tx.send(()).unwrap();
+            })
+            .unwrap();
+
+        rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    }
+
+    #[test]
+    fn zero_miss_threshold_is_rejected() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_client = ConnectedUdpSocket::connect(client, peer.local_addr().unwrap()).unwrap();
+
+        let err = conn_client
+            .monitor_liveness(b"ping".to_vec(), Duration::from_millis(20), 0, || {})
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn echoing_peer_is_alive() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_client = ConnectedUdpSocket::connect(client, peer_addr).unwrap();
+
+        let stop_echo = Arc::new(AtomicBool::new(false));
+        let echo = {
+            let stop_echo = Arc::clone(&stop_echo);
+            thread::spawn(move || {
+                let mut buf = [0u8; 32];
+                while !stop_echo.load(Ordering::Acquire) {
+                    if let Ok((n, from)) = peer.recv_from(&mut buf) {
+                        peer.send_to(&buf[..n], from).unwrap();
+                    }
+                }
+            })
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let handle = conn_client
+            .monitor_liveness(b"ping".to_vec(), Duration::from_millis(50), 2, move || {
+                tx.send(()).unwrap();
+            })
+            .unwrap();
+
+        assert!(rx.recv_timeout(Duration::from_millis(400)).is_err());
+
+        drop(handle);
+        stop_echo.store(true, Ordering::Release);
+        echo.join().unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use crate::ConnectedUdpSocket;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A wrapper that randomly drops datagrams, to simulate packet loss when
+/// testing how a protocol copes with it.
+///
+/// Each call to [`recv_lossy`](Self::recv_lossy) or
+/// [`send_lossy`](Self::send_lossy) drops its datagram independently with
+/// the given probability. The random numbers are seeded differently for
+/// every wrapper and aren't suitable for anything but tests.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, LossySocket};
+/// use std::net::UdpSocket;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///     let client_addr = conn_client.local_addr()?;
+///
+///     host.send_to(b"ping", client_addr)?;
+///
+///     let lossy = LossySocket::new(conn_client);
+///     let mut buf = [0; 32];
+///     match lossy.recv_lossy(&mut buf, 0.1)? {
+///         Some(n) => assert_eq!(&buf[..n], b"ping"),
+///         None => println!("dropped"),
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct LossySocket {
+    socket: ConnectedUdpSocket,
+    rng: Rng,
+}
+
+impl LossySocket {
+    /// Wraps `socket`.
+    pub fn new(socket: ConnectedUdpSocket) -> Self {
+        Self {
+            socket,
+            rng: Rng::new(),
+        }
+    }
+
+    /// Receives a datagram like [`ConnectedUdpSocket::recv`], then drops it
+    /// with probability `drop_probability`, returning `Ok(None)`.
+    ///
+    /// A dropped datagram is still consumed from the socket, and its contents
+    /// may have been written into `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if
+    /// `drop_probability` isn't between 0.0 and 1.0, and otherwise any error
+    /// from receiving.
+    pub fn recv_lossy(&self, buf: &mut [u8], drop_probability: f64) -> io::Result<Option<usize>> {
+        check_probability(drop_probability)?;
+        let n = self.socket.recv(buf)?;
+        Ok((!self.rng.chance(drop_probability)).then_some(n))
+    }
+
+    /// Sends a datagram like [`ConnectedUdpSocket::send`], unless it's
+    /// dropped with probability `drop_probability`, in which case nothing is
+    /// sent and `Ok(None)` is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if
+    /// `drop_probability` isn't between 0.0 and 1.0, and otherwise any error
+    /// from sending.
+    pub fn send_lossy(&self, buf: &[u8], drop_probability: f64) -> io::Result<Option<usize>> {
+        check_probability(drop_probability)?;
+        if self.rng.chance(drop_probability) {
+            return Ok(None);
+        }
+        self.socket.send(buf).map(Some)
+    }
+
+    /// Returns the wrapped socket, e.g. for traffic that shouldn't be
+    /// dropped.
+    pub fn socket(&self) -> &ConnectedUdpSocket {
+        &self.socket
+    }
+
+    /// Unwraps the socket.
+    pub fn into_inner(self) -> ConnectedUdpSocket {
+        self.socket
+    }
+}
+
+/// Checks that `probability` is a valid probability.
+fn check_probability(probability: f64) -> io::Result<()> {
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "drop probability must be between 0.0 and 1.0",
+        ));
+    }
+    Ok(())
+}
+
+/// A small, randomly seeded source of non-cryptographic random numbers for
+/// the test helpers (SplitMix64).
+#[derive(Debug)]
+pub(crate) struct Rng {
+    state: AtomicU64,
+}
+
+impl Rng {
+    /// Creates a generator seeded from the standard library's randomly keyed
+    /// hasher.
+    pub(crate) fn new() -> Self {
+        Self {
+            state: AtomicU64::new(RandomState::new().hash_one(0u8)),
+        }
+    }
+
+    /// Returns the next random number.
+    pub(crate) fn next_u64(&self) -> u64 {
+        const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a random number in `[0, 1)`.
+    pub(crate) fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns `true` with probability `probability`, which must be between
+    /// 0.0 and 1.0.
+    fn chance(&self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    #[test]
+    fn certain_loss_drops_everything() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let lossy = LossySocket::new(sender_conn);
+
+        let mut buf = [0u8; 8];
+        for _ in 0..20 {
+            assert_eq!(lossy.send_lossy(b"ping", 1.0).unwrap(), None);
+        }
+        assert!(receiver.recv(&mut buf).is_err());
+
+        for _ in 0..20 {
+            receiver.send_to(b"pong", sender_addr).unwrap();
+            assert_eq!(lossy.recv_lossy(&mut buf, 1.0).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn no_loss_delivers_everything() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let lossy = LossySocket::new(sender_conn);
+
+        let mut buf = [0u8; 8];
+        for _ in 0..20 {
+            assert_eq!(lossy.send_lossy(b"ping", 0.0).unwrap(), Some(4));
+            assert_eq!(receiver.recv(&mut buf).unwrap(), 4);
+
+            receiver.send_to(b"pong", sender_addr).unwrap();
+            assert_eq!(lossy.recv_lossy(&mut buf, 0.0).unwrap(), Some(4));
+            assert_eq!(&buf[..4], b"pong");
+        }
+
+        let err = lossy.send_lossy(b"ping", 1.5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
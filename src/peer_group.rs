@@ -0,0 +1,156 @@
+use crate::chunked::{ChunkHeader, NEXT_MESSAGE_ID};
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::sync::atomic::Ordering;
+
+/// A set of [`ConnectedUdpSocket`]s used together, e.g. one per network path
+/// to the same peer.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, PeerGroup};
+/// use std::net::UdpSocket;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let host_addr = host.local_addr()?;
+///
+///     let group = PeerGroup::new(vec![
+///         ConnectedUdpSocket::connect(UdpSocket::bind("127.0.0.1:0")?, host_addr)?,
+///         ConnectedUdpSocket::connect(UdpSocket::bind("127.0.0.1:0")?, host_addr)?,
+///     ]);
+///     for sent in group.send_striped(&[7; 3000], 1200) {
+///         sent?;
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct PeerGroup {
+    members: Vec<ConnectedUdpSocket>,
+}
+
+impl PeerGroup {
+    /// Creates a group from its member sockets.
+    pub fn new(members: Vec<ConnectedUdpSocket>) -> Self {
+        Self { members }
+    }
+
+    /// Returns the member sockets.
+    pub fn members(&self) -> &[ConnectedUdpSocket] {
+        &self.members
+    }
+
+    /// Splits `msg` into chunks of at most `chunk` bytes and sends them
+    /// round-robin across the members, returning the result of sending each
+    /// chunk in order.
+    ///
+    /// This aggregates the bandwidth of several paths. Each chunk carries
+    /// the same 8-byte header as
+    /// [`send_chunked`](ConnectedUdpSocket::send_chunked) (a message id, the
+    /// chunk's index, and the total number of chunks), so the receiver can
+    /// reorder chunks that arrive over different paths. A failed chunk
+    /// doesn't stop the rest from being sent. An empty message is sent as a
+    /// single empty chunk.
+    ///
+    /// If the group has no members, `chunk` is zero, or `msg` would need
+    /// more than 65535 chunks, nothing is sent and the only result is an
+    /// error of kind [`io::ErrorKind::InvalidInput`].
+    pub fn send_striped(&self, msg: &[u8], chunk: usize) -> Vec<io::Result<usize>> {
+        let total = match self.stripe_count(msg.len(), chunk) {
+            Ok(total) => total,
+            Err(e) => return vec![Err(e)],
+        };
+
+        let message_id = NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+        (0..total)
+            .zip(self.members.iter().cycle())
+            .map(|(index, member)| {
+                let start = usize::from(index) * chunk;
+                let end = msg.len().min(start + chunk);
+                let header = ChunkHeader {
+                    message_id,
+                    index,
+                    total,
+                };
+                member.send_with_header(&header.encode(), &msg[start..end])
+            })
+            .collect()
+    }
+
+    /// Returns how many chunks of `chunk` bytes a `len`-byte message needs.
+    fn stripe_count(&self, len: usize, chunk: usize) -> io::Result<u16> {
+        if self.members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "peer group has no members",
+            ));
+        }
+        if chunk == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "chunk size must be non-zero",
+            ));
+        }
+        u16::try_from(len.div_ceil(chunk).max(1)).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message needs more than 65535 chunks",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn send_striped_alternates_members() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let group = PeerGroup::new(vec![
+            ConnectedUdpSocket::connect(UdpSocket::bind("127.0.0.1:0").unwrap(), recv_addr)
+                .unwrap(),
+            ConnectedUdpSocket::connect(UdpSocket::bind("127.0.0.1:0").unwrap(), recv_addr)
+                .unwrap(),
+        ]);
+        let sources: Vec<_> = group
+            .members()
+            .iter()
+            .map(|member| member.local_addr().unwrap())
+            .collect();
+
+        let msg: Vec<u8> = (0..10).collect();
+        let sent = group.send_striped(&msg, 3);
+        assert_eq!(sent.len(), 4);
+        assert!(sent.iter().all(Result::is_ok));
+
+        let mut reassembled = vec![None; 4];
+        let mut buf = [0u8; 32];
+        for _ in 0..4 {
+            let (n, from) = receiver.recv_from(&mut buf).unwrap();
+            let (header, payload) = ChunkHeader::decode(&buf[..n]).unwrap();
+            assert_eq!(header.total, 4);
+            // Chunks alternate between the two members.
+            assert_eq!(from, sources[usize::from(header.index) % 2]);
+            reassembled[usize::from(header.index)] = Some(payload.to_vec());
+        }
+        let reassembled: Vec<u8> = reassembled.into_iter().flatten().flatten().collect();
+        assert_eq!(reassembled, msg);
+    }
+
+    #[test]
+    fn send_striped_without_members_error() {
+        let sent = PeerGroup::new(Vec::new()).send_striped(b"ping", 2);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].as_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
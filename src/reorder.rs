@@ -0,0 +1,250 @@
+use crate::connected_udp::remaining;
+use crate::ConnectedUdpSocket;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The size of the sequence number in front of every datagram.
+const SEQ_LEN: usize = 4;
+
+/// The largest datagram the reorderer receives.
+const MAX_DATAGRAM_LEN: usize = 64 * 1024;
+
+/// A receiver that puts sequenced datagrams back in order.
+///
+/// Every datagram is expected to start with a 4-byte big-endian sequence
+/// number, counting up from zero without wrapping. Datagrams that arrive
+/// ahead of the next expected one are buffered until the gap before them is
+/// filled, then released in order by [`recv_ordered`](Self::recv_ordered).
+/// Late and duplicate datagrams, and ones too short to carry a sequence
+/// number, are discarded.
+///
+/// Missing datagrams are given up on, and the gap skipped, once a later one
+/// has waited `skip_after`, or once a datagram arrives `window` or more
+/// sequence numbers ahead, which bounds how much is buffered.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, Reorderer};
+/// use std::net::UdpSocket;
+/// use std::time::Duration;
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///     let client_addr = conn_client.local_addr()?;
+///
+///     host.send_to(b"\0\0\0\x01second", client_addr)?;
+///     host.send_to(b"\0\0\0\x00first", client_addr)?;
+///
+///     let reorderer = Reorderer::new(conn_client, 64, Duration::from_millis(200));
+///     assert_eq!(reorderer.recv_ordered()?, b"first");
+///     assert_eq!(reorderer.recv_ordered()?, b"second");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Reorderer {
+    socket: ConnectedUdpSocket,
+    window: u32,
+    skip_after: Duration,
+    state: Mutex<State>,
+}
+
+/// The datagrams waiting to be released.
+#[derive(Debug, Default)]
+struct State {
+    /// The sequence number to release next.
+    next: u32,
+    buffered: BTreeMap<u32, Vec<u8>>,
+    /// When the reorderer started waiting for `next` while later datagrams
+    /// were buffered.
+    gap_since: Option<Instant>,
+}
+
+impl State {
+    /// Releases the payload of the next datagram, if it's arrived, skipping
+    /// gaps the window no longer covers.
+    fn pop_ready(&mut self, window: u32) -> Option<Vec<u8>> {
+        let (&first, _) = self.buffered.first_key_value()?;
+        let (&last, _) = self.buffered.last_key_value()?;
+        if last - self.next >= window {
+            self.next = first;
+        }
+        let payload = self.buffered.remove(&self.next)?;
+        self.next = self.next.saturating_add(1);
+        self.gap_since = if self.buffered.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        };
+        Some(payload)
+    }
+
+    /// Buffers a received datagram, unless it's malformed, late or a
+    /// duplicate.
+    fn insert(&mut self, datagram: &[u8]) {
+        let Some((seq, payload)) = datagram.split_first_chunk::<SEQ_LEN>() else {
+            return;
+        };
+        let seq = u32::from_be_bytes(*seq);
+        if seq < self.next || self.buffered.contains_key(&seq) {
+            return;
+        }
+        self.buffered.insert(seq, payload.to_vec());
+        self.gap_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Gives up on the missing datagrams before the first buffered one.
+    fn skip_gap(&mut self) {
+        if let Some((&first, _)) = self.buffered.first_key_value() {
+            self.next = first;
+        }
+    }
+}
+
+impl Reorderer {
+    /// Wraps `socket`, buffering datagrams up to `window` sequence numbers
+    /// ahead and skipping gaps that last longer than `skip_after`.
+    ///
+    /// A `window` of zero is treated as one, which releases every datagram
+    /// that isn't late as soon as it arrives.
+    pub fn new(socket: ConnectedUdpSocket, window: u32, skip_after: Duration) -> Self {
+        Self {
+            socket,
+            window: window.max(1),
+            skip_after,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns the payload of the next datagram in sequence order, with its
+    /// sequence number stripped.
+    ///
+    /// This blocks until that datagram is available or its gap is skipped.
+    /// While nothing is buffered, it waits for as long as a plain
+    /// [`recv`](ConnectedUdpSocket::recv) would; otherwise the socket's read
+    /// timeout is temporarily shortened to wait out the gap, and restored
+    /// afterwards. Concurrent calls take turns.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from receiving, e.g. an expired read timeout while
+    /// nothing is buffered.
+    pub fn recv_ordered(&self) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; MAX_DATAGRAM_LEN];
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(payload) = state.pop_ready(self.window) {
+                return Ok(payload);
+            }
+
+            let res = match state.gap_since {
+                None => self.socket.recv(&mut buf),
+                Some(since) => {
+                    let Ok(left) = remaining(since + self.skip_after) else {
+                        state.skip_gap();
+                        continue;
+                    };
+                    self.socket.restoring_timeouts(|socket| {
+                        socket.as_ref().set_read_timeout(Some(left))?;
+                        socket.recv(&mut buf)
+                    })
+                }
+            };
+            match res {
+                Ok(n) => state.insert(&buf[..n]),
+                Err(e)
+                    if state.gap_since.is_some()
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns the wrapped socket, e.g. for sending.
+    pub fn socket(&self) -> &ConnectedUdpSocket {
+        &self.socket
+    }
+
+    /// Unwraps the socket, dropping any buffered datagrams.
+    pub fn into_inner(self) -> ConnectedUdpSocket {
+        self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, UdpSocket};
+
+    fn send_seq(socket: &UdpSocket, seq: u32, payload: &[u8], to: SocketAddr) {
+        let mut datagram = seq.to_be_bytes().to_vec();
+        datagram.extend_from_slice(payload);
+        socket.send_to(&datagram, to).unwrap();
+    }
+
+    #[test]
+    fn recv_ordered_restores_order() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let reorderer = Reorderer::new(sender_conn, 16, Duration::from_secs(5));
+
+        for (seq, payload) in [(2, b"c"), (0, b"a"), (3, b"d"), (0, b"a"), (1, b"b")] {
+            send_seq(&receiver, seq, payload, sender_addr);
+        }
+
+        for expected in [b"a", b"b", b"c", b"d"] {
+            assert_eq!(reorderer.recv_ordered().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn recv_ordered_skips_missing_after_timeout() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let reorderer = Reorderer::new(sender_conn, 16, Duration::from_millis(50));
+
+        // Sequence number 1 never arrives.
+        send_seq(&receiver, 0, b"a", sender_addr);
+        send_seq(&receiver, 2, b"c", sender_addr);
+
+        assert_eq!(reorderer.recv_ordered().unwrap(), b"a");
+        let started = Instant::now();
+        assert_eq!(reorderer.recv_ordered().unwrap(), b"c");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(reorderer.socket().as_ref().read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn recv_ordered_skips_gaps_beyond_window() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let sender_addr = sender_conn.local_addr().unwrap();
+        let reorderer = Reorderer::new(sender_conn, 4, Duration::from_secs(5));
+
+        send_seq(&receiver, 10, b"k", sender_addr);
+        assert_eq!(reorderer.recv_ordered().unwrap(), b"k");
+    }
+}
@@ -0,0 +1,156 @@
+//! IO-free building blocks for protocols on top of connected UDP.
+//!
+//! The types in this module never touch a socket. They transform bytes into
+//! datagrams and back, so the same logic can be driven by the blocking
+//! [`ConnectedUdpSocket`](crate::ConnectedUdpSocket), an async runtime, or an
+//! embedded network stack.
+
+/// The size of the length prefix [`FramedCodec`] puts in front of each
+/// message.
+pub const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Length-prefix framing for messages carried one per datagram.
+///
+/// Each message is encoded as a 4-byte big-endian length followed by the
+/// message itself. On decode, the prefix must match the datagram's actual
+/// payload length, so truncated or padded datagrams are rejected rather than
+/// silently accepted.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::sans_io::FramedCodec;
+///
+/// let mut codec = FramedCodec::new();
+///
+/// let mut datagram = Vec::new();
+/// codec.encode(b"hello", &mut datagram);
+/// assert_eq!(datagram, b"\0\0\0\x05hello");
+///
+/// assert_eq!(codec.decode(&datagram), Some(b"hello".to_vec()));
+/// assert_eq!(codec.decode(&datagram[..6]), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FramedCodec {
+    max_message_len: usize,
+}
+
+impl FramedCodec {
+    /// Creates a codec that accepts messages of any length representable in
+    /// the prefix.
+    pub fn new() -> Self {
+        Self::with_max_message_len(u32::MAX as usize)
+    }
+
+    /// Creates a codec that rejects decoded messages longer than
+    /// `max_message_len` bytes.
+    pub fn with_max_message_len(max_message_len: usize) -> Self {
+        Self { max_message_len }
+    }
+
+    /// Appends the framed form of `msg` to `out`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `msg` is longer than `u32::MAX` bytes, which no datagram can
+    /// carry anyway.
+    pub fn encode(&mut self, msg: &[u8], out: &mut Vec<u8>) {
+        let len = u32::try_from(msg.len()).expect("message too long to frame");
+        out.reserve(LENGTH_PREFIX_LEN + msg.len());
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(msg);
+    }
+
+    /// Extracts the message framed in `datagram`, or returns `None` if the
+    /// datagram is malformed.
+    ///
+    /// A datagram is malformed if it's too short to hold the prefix, if the
+    /// prefix doesn't match the length of the rest of the datagram, or if the
+    /// message exceeds the codec's maximum length.
+    pub fn decode(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let (prefix, msg) = datagram.split_first_chunk::<LENGTH_PREFIX_LEN>()?;
+        let len = u32::from_be_bytes(*prefix) as usize;
+        if len != msg.len() || len > self.max_message_len {
+            return None;
+        }
+        Some(msg.to_vec())
+    }
+
+    /// Extracts every message framed back to back in `datagram`, as sent by
+    /// a [`CoalescingSender`](crate::CoalescingSender), or returns `None` if
+    /// the datagram is malformed.
+    ///
+    /// A datagram is malformed if a prefix claims more bytes than are left,
+    /// if bytes are left over that are too few to hold a prefix, or if a
+    /// message exceeds the codec's maximum length.
+    pub fn decode_all(&mut self, mut datagram: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let mut messages = Vec::new();
+        while !datagram.is_empty() {
+            let (prefix, rest) = datagram.split_first_chunk::<LENGTH_PREFIX_LEN>()?;
+            let len = u32::from_be_bytes(*prefix) as usize;
+            if len > rest.len() || len > self.max_message_len {
+                return None;
+            }
+            let (msg, rest) = rest.split_at(len);
+            messages.push(msg.to_vec());
+            datagram = rest;
+        }
+        Some(messages)
+    }
+}
+
+impl Default for FramedCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_symmetry() {
+        let mut codec = FramedCodec::new();
+        for msg in [&b""[..], &b"x"[..], &[0xffu8; 1200][..]] {
+            let mut datagram = Vec::new();
+            codec.encode(msg, &mut datagram);
+            assert_eq!(datagram.len(), LENGTH_PREFIX_LEN + msg.len());
+            assert_eq!(codec.decode(&datagram).as_deref(), Some(msg));
+        }
+    }
+
+    #[test]
+    fn decode_all_splits_messages() {
+        let mut codec = FramedCodec::with_max_message_len(8);
+        let mut datagram = Vec::new();
+        codec.encode(b"one", &mut datagram);
+        codec.encode(b"", &mut datagram);
+        codec.encode(b"three", &mut datagram);
+
+        assert_eq!(
+            codec.decode_all(&datagram),
+            Some(vec![b"one".to_vec(), Vec::new(), b"three".to_vec()])
+        );
+        assert_eq!(codec.decode_all(&[]), Some(Vec::new()));
+        // Truncated in the last message, and in the last prefix.
+        assert_eq!(codec.decode_all(&datagram[..datagram.len() - 1]), None);
+        assert_eq!(codec.decode_all(&datagram[..9]), None);
+    }
+
+    #[test]
+    fn decode_malformed() {
+        let mut codec = FramedCodec::with_max_message_len(8);
+
+        // Too short for the prefix.
+        assert_eq!(codec.decode(&[0, 0, 1]), None);
+        // Prefix claims more than the datagram holds.
+        assert_eq!(codec.decode(&[0, 0, 0, 5, b'a', b'b']), None);
+        // Prefix claims less than the datagram holds.
+        assert_eq!(codec.decode(&[0, 0, 0, 1, b'a', b'b']), None);
+        // Over the maximum length.
+        let mut datagram = Vec::new();
+        codec.encode(&[0; 9], &mut datagram);
+        assert_eq!(codec.decode(&datagram), None);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the timer thread sleeps between checks for cancellation.
+const POLL_SLICE: Duration = Duration::from_millis(10);
+
+/// The send is still waiting for its delay to pass.
+const PENDING: u8 = 0;
+/// The send was cancelled before it fired.
+const CANCELLED: u8 = 1;
+/// The send fired, or is about to.
+const FIRED: u8 = 2;
+
+impl ConnectedUdpSocket {
+    /// Spawns a thread that sends `buf` to the peer once `delay` has passed.
+    ///
+    /// This is for pacing a single message, e.g. a deliberately delayed
+    /// retransmission. The send can be cancelled through the returned
+    /// [`SendHandle`] until it fires; dropping the handle leaves it
+    /// scheduled.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the socket can't be cloned for the timer thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let pending = conn_client
+    ///      .send_after(b"ping".to_vec(), Duration::from_millis(10))
+    ///      .expect("couldn't schedule send");
+    ///  let sent = pending.wait().expect("send was cancelled");
+    ///  assert_eq!(sent.expect("couldn't send datagram"), 4);
+    /// # }
+    /// ```
+    pub fn send_after(&self, buf: Vec<u8>, delay: Duration) -> io::Result<SendHandle> {
+        let conn = self.try_clone()?;
+        let state = Arc::new(AtomicU8::new(PENDING));
+        let deadline = Instant::now() + delay;
+        let thread = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || fire_at(&conn, &buf, deadline, &state))
+        };
+
+        Ok(SendHandle {
+            state,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A handle to a send scheduled by [`ConnectedUdpSocket::send_after`].
+///
+/// Dropping the handle doesn't cancel the send.
+#[derive(Debug)]
+pub struct SendHandle {
+    state: Arc<AtomicU8>,
+    thread: Option<JoinHandle<Option<io::Result<usize>>>>,
+}
+
+impl SendHandle {
+    /// Cancels the send, returning whether it was stopped in time.
+    ///
+    /// Returns `false` if the send already fired (or is firing).
+    pub fn cancel(&self) -> bool {
+        self.state
+            .compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Returns whether the send is still waiting for its delay to pass.
+    pub fn is_pending(&self) -> bool {
+        self.state.load(Ordering::Acquire) == PENDING
+    }
+
+    /// Waits for the send to fire, returning its result, or `None` if it was
+    /// cancelled.
+    pub fn wait(mut self) -> Option<io::Result<usize>> {
+        let thread = self.thread.take()?;
+        thread
+            .join()
+            .unwrap_or_else(|_| Some(Err(io::Error::other("delayed send thread panicked"))))
+    }
+}
+
+/// Sends `buf` on `conn` at `deadline`, unless `state` is cancelled first.
+fn fire_at(
+    conn: &ConnectedUdpSocket,
+    buf: &[u8],
+    deadline: Instant,
+    state: &AtomicU8,
+) -> Option<io::Result<usize>> {
+    loop {
+        if state.load(Ordering::Acquire) == CANCELLED {
+            return None;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        thread::sleep(POLL_SLICE.min(left));
+    }
+    // Claiming the send settles any race with a concurrent cancel.
+    state
+        .compare_exchange(PENDING, FIRED, Ordering::AcqRel, Ordering::Acquire)
+        .ok()?;
+    Some(conn.send(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn send_after_fires() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let started = Instant::now();
+        let pending = sender_conn
+            .send_after(b"ping".to_vec(), Duration::from_millis(50))
+            .unwrap();
+        let mut buf = [0u8; 8];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        assert!(!pending.cancel());
+        assert_eq!(pending.wait().unwrap().unwrap(), 4);
+    }
+
+    #[test]
+    fn cancelled_send_never_fires() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let pending = sender_conn
+            .send_after(b"ping".to_vec(), Duration::from_millis(100))
+            .unwrap();
+        assert!(pending.is_pending());
+        assert!(pending.cancel());
+        assert!(pending.wait().is_none());
+
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut buf = [0u8; 8];
+        assert!(receiver.recv(&mut buf).is_err());
+    }
+}
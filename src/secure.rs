@@ -0,0 +1,170 @@
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::net::SocketAddr;
+
+/// A security layer, such as a DTLS session, that protects the datagrams a
+/// [`ConnectedUdpSocket`] exchanges with its peer.
+///
+/// This crate doesn't bundle a TLS stack; it only provides the seam. An
+/// implementation wraps its session state (e.g. an `openssl` DTLS stream) and
+/// performs raw IO through the socket it's handed, sending and receiving
+/// records with [`ConnectedUdpSocket::send`] and
+/// [`ConnectedUdpSocket::recv`]. [`SecureSocket`] pairs an implementation
+/// with a socket. Handshakes are up to the implementation, e.g. driven
+/// lazily from the first `send` or `recv`.
+pub trait SecureTransport {
+    /// Protects `plaintext` and sends the result to the peer through
+    /// `socket`, returning the number of plaintext bytes sent.
+    fn send(&mut self, socket: &ConnectedUdpSocket, plaintext: &[u8]) -> io::Result<usize>;
+
+    /// Receives from the peer through `socket` until a message is
+    /// available, and writes its plaintext into `buf`, returning the number
+    /// of bytes written.
+    fn recv(&mut self, socket: &ConnectedUdpSocket, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+impl<T: SecureTransport + ?Sized> SecureTransport for Box<T> {
+    fn send(&mut self, socket: &ConnectedUdpSocket, plaintext: &[u8]) -> io::Result<usize> {
+        (**self).send(socket, plaintext)
+    }
+
+    fn recv(&mut self, socket: &ConnectedUdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).recv(socket, buf)
+    }
+}
+
+/// A [`ConnectedUdpSocket`] whose traffic goes through a [`SecureTransport`].
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, SecureSocket, SecureTransport};
+/// use std::io;
+/// use std::net::UdpSocket;
+///
+/// /// A stand-in for a real DTLS session.
+/// struct Plaintext;
+///
+/// impl SecureTransport for Plaintext {
+///     fn send(&mut self, socket: &ConnectedUdpSocket, plaintext: &[u8]) -> io::Result<usize> {
+///         socket.send(plaintext)
+///     }
+///
+///     fn recv(&mut self, socket: &ConnectedUdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+///         socket.recv(buf)
+///     }
+/// }
+///
+/// fn main() -> io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///
+///     let mut secure = SecureSocket::new(conn_client, Plaintext);
+///     secure.send(b"ping")?;
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SecureSocket<S> {
+    socket: ConnectedUdpSocket,
+    session: S,
+}
+
+impl<S: SecureTransport> SecureSocket<S> {
+    /// Protects the traffic of `socket` with `session`.
+    pub fn new(socket: ConnectedUdpSocket, session: S) -> Self {
+        Self { socket, session }
+    }
+
+    /// Sends `plaintext` to the peer through the security layer, returning
+    /// the number of plaintext bytes sent.
+    pub fn send(&mut self, plaintext: &[u8]) -> io::Result<usize> {
+        self.session.send(&self.socket, plaintext)
+    }
+
+    /// Receives a message from the peer through the security layer,
+    /// returning the number of plaintext bytes written to `buf`.
+    pub fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.session.recv(&self.socket, buf)
+    }
+
+    /// Returns the socket address of the remote peer.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.socket.peer_addr()
+    }
+
+    /// Returns the underlying socket, e.g. for configuring timeouts.
+    ///
+    /// Sending or receiving on it directly bypasses the security layer.
+    pub fn socket(&self) -> &ConnectedUdpSocket {
+        &self.socket
+    }
+
+    /// Returns the security layer.
+    pub fn session(&self) -> &S {
+        &self.session
+    }
+
+    /// Returns the security layer mutably.
+    pub fn session_mut(&mut self) -> &mut S {
+        &mut self.session
+    }
+
+    /// Decomposes this into the underlying socket and the security layer.
+    pub fn into_parts(self) -> (ConnectedUdpSocket, S) {
+        (self.socket, self.session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    /// An identity "cipher" that counts the records it handles.
+    #[derive(Debug, Default)]
+    struct Identity {
+        sent: usize,
+        received: usize,
+    }
+
+    impl SecureTransport for Identity {
+        fn send(&mut self, socket: &ConnectedUdpSocket, plaintext: &[u8]) -> io::Result<usize> {
+            self.sent += 1;
+            socket.send(plaintext)
+        }
+
+        fn recv(&mut self, socket: &ConnectedUdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+            self.received += 1;
+            socket.recv(buf)
+        }
+    }
+
+    #[test]
+    fn traffic_goes_through_session() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        let mut a_secure = SecureSocket::new(
+            ConnectedUdpSocket::connect(a, b_addr).unwrap(),
+            Identity::default(),
+        );
+        let mut b_secure: SecureSocket<Box<dyn SecureTransport>> = SecureSocket::new(
+            ConnectedUdpSocket::connect(b, a_addr).unwrap(),
+            Box::new(Identity::default()),
+        );
+
+        assert_eq!(a_secure.send(b"ping").unwrap(), 4);
+        let mut buf = [0u8; 32];
+        let n = b_secure.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+
+        assert_eq!(a_secure.session().sent, 1);
+        assert_eq!(a_secure.session().received, 0);
+        assert_eq!(a_secure.peer_addr(), b_addr);
+    }
+}
@@ -0,0 +1,512 @@
+use crate::connected_udp::Options;
+use crate::ConnectedUdpSocket;
+use socket2::SockRef;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+
+const VERSION: u8 = 5;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+const CMD_UDP_ASSOCIATE: u8 = 3;
+const ATYP_IPV4: u8 = 1;
+const ATYP_IPV6: u8 = 4;
+
+/// The largest UDP request header: RSV, FRAG, ATYP, an IPv6 address and a
+/// port.
+const MAX_HEADER_LEN: usize = 3 + 1 + 16 + 2;
+
+/// A SOCKS5 UDP association that a [`ConnectedUdpSocket`] relays its
+/// datagrams through.
+#[derive(Debug)]
+pub(crate) struct Socks5Association {
+    /// The TCP connection the association lives as long as.
+    _control: TcpStream,
+    /// The UDP request header targeting the peer.
+    header: Vec<u8>,
+    peer: SocketAddr,
+}
+
+impl Socks5Association {
+    /// Sends `buf` to the peer through the relay, returning the number of
+    /// payload bytes sent.
+    pub(crate) fn send(&self, socket: &UdpSocket, buf: &[u8]) -> io::Result<usize> {
+        self.send_vectored(socket, &[IoSlice::new(buf)])
+    }
+
+    /// Sends `bufs` as one datagram to the peer through the relay, returning
+    /// the number of payload bytes sent.
+    pub(crate) fn send_vectored(
+        &self,
+        socket: &UdpSocket,
+        bufs: &[IoSlice<'_>],
+    ) -> io::Result<usize> {
+        let mut slices = Vec::with_capacity(bufs.len() + 1);
+        slices.push(IoSlice::new(&self.header));
+        slices.extend_from_slice(bufs);
+        let n = SockRef::from(socket).send_vectored(&slices)?;
+        Ok(n.saturating_sub(self.header.len()))
+    }
+
+    /// Receives a datagram through the relay into `buf`, returning the
+    /// number of payload bytes read, or `None` if it has to be discarded.
+    ///
+    /// Datagrams the relay forwards from other addresses, and fragments
+    /// (which this doesn't reassemble), are discarded, leaving the caller to
+    /// decide whether to wait for another.
+    pub(crate) fn recv(&self, socket: &UdpSocket, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let mut datagram = vec![0; MAX_HEADER_LEN + buf.len()];
+        let n = socket.recv(&mut datagram)?;
+        let Some((from, header_len)) = parse_udp_header(&datagram[..n]) else {
+            return Ok(None);
+        };
+        if from != self.peer {
+            return Ok(None);
+        }
+        let payload = &datagram[header_len..n];
+        let len = payload.len().min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+        Ok(Some(len))
+    }
+}
+
+impl ConnectedUdpSocket {
+    /// Connects to `peer` through the SOCKS5 proxy at `proxy`, using its UDP
+    /// ASSOCIATE command.
+    ///
+    /// This performs the handshake over a TCP connection to the proxy,
+    /// authenticating with a username and password if `auth` is given, then
+    /// connects a UDP socket to the relay the proxy assigns. The returned
+    /// socket's [`send`](Self::send), [`send_vectored`](Self::send_vectored)
+    /// and [`recv`](Self::recv) (and the helpers built on them) wrap and
+    /// unwrap payloads in the SOCKS5 UDP request header targeting `peer`, and
+    /// [`peer_addr`](Self::peer_addr) reports `peer`. Methods that would
+    /// bypass the header or move the connection off the relay, such as
+    /// [`recv_uninit`](Self::recv_uninit), the Linux-specific sends and
+    /// receives like `send_gso` and [`reconnect`](Self::reconnect), fail
+    /// with [`io::ErrorKind::Unsupported`]. The association, and the TCP
+    /// connection it depends on, last until the socket and all its clones are
+    /// dropped.
+    ///
+    /// Fragmented datagrams aren't supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::PermissionDenied`] if the
+    /// proxy rejects the credentials (or requires some that weren't given),
+    /// and of kind [`io::ErrorKind::ConnectionRefused`] if it refuses the
+    /// association.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # fn main() {
+    ///  let conn = ConnectedUdpSocket::connect_via_socks5(
+    ///      "192.0.2.1:1080".parse().unwrap(),
+    ///      "198.51.100.7:53".parse().unwrap(),
+    ///      Some(("user".to_string(), "secret".to_string())),
+    ///  )
+    ///  .expect("couldn't associate through the proxy");
+    ///  conn.send(b"query").expect("couldn't send datagram");
+    /// # }
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "socks5")))]
+    pub fn connect_via_socks5(
+        proxy: SocketAddr,
+        peer: SocketAddr,
+        auth: Option<(String, String)>,
+    ) -> io::Result<Self> {
+        let mut control = TcpStream::connect(proxy)?;
+        negotiate(&mut control, auth.as_ref())?;
+
+        // Send from the interface that reaches the proxy, so the address
+        // announced to it is the one it'll see.
+        let socket = UdpSocket::bind(SocketAddr::new(control.local_addr()?.ip(), 0))?;
+        let mut request = vec![VERSION, CMD_UDP_ASSOCIATE, 0];
+        write_addr(&mut request, socket.local_addr()?);
+        control.write_all(&request)?;
+
+        let reply = read_reply(&mut control)?;
+        let relay = match reply {
+            // A proxy may leave the relay address for the client to infer.
+            relay if relay.ip().is_unspecified() => SocketAddr::new(proxy.ip(), relay.port()),
+            relay => relay,
+        };
+        socket.connect(relay)?;
+
+        let mut header = vec![0, 0, 0];
+        write_addr(&mut header, peer);
+        let association = Socks5Association {
+            _control: control,
+            header,
+            peer,
+        };
+        Self::with_socks5(socket, peer, Options::default(), association)
+    }
+}
+
+/// Agrees on an authentication method with the proxy, and authenticates.
+fn negotiate(control: &mut TcpStream, auth: Option<&(String, String)>) -> io::Result<()> {
+    let greeting: &[u8] = match auth {
+        Some(_) => &[VERSION, 2, METHOD_NONE, METHOD_PASSWORD],
+        None => &[VERSION, 1, METHOD_NONE],
+    };
+    control.write_all(greeting)?;
+
+    let mut choice = [0; 2];
+    control.read_exact(&mut choice)?;
+    if choice[0] != VERSION {
+        return Err(malformed());
+    }
+    match (choice[1], auth) {
+        (METHOD_NONE, _) => Ok(()),
+        (METHOD_PASSWORD, Some((user, password))) => authenticate(control, user, password),
+        (METHOD_UNACCEPTABLE, _) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5 proxy accepts none of the offered authentication methods",
+        )),
+        _ => Err(malformed()),
+    }
+}
+
+/// Performs username/password authentication (RFC 1929).
+fn authenticate(control: &mut TcpStream, user: &str, password: &str) -> io::Result<()> {
+    let (Ok(user_len), Ok(password_len)) = (u8::try_from(user.len()), u8::try_from(password.len()))
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SOCKS5 username and password must be at most 255 bytes",
+        ));
+    };
+    let mut request = vec![1, user_len];
+    request.extend_from_slice(user.as_bytes());
+    request.push(password_len);
+    request.extend_from_slice(password.as_bytes());
+    control.write_all(&request)?;
+
+    let mut status = [0; 2];
+    control.read_exact(&mut status)?;
+    if status[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5 proxy rejected the credentials",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the proxy's reply to a command, returning the bound address.
+fn read_reply(control: &mut TcpStream) -> io::Result<SocketAddr> {
+    let mut head = [0; 4];
+    control.read_exact(&mut head)?;
+    let [version, code, _, atyp] = head;
+    if version != VERSION {
+        return Err(malformed());
+    }
+    if code != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("SOCKS5 proxy refused the UDP association (reply code {code})"),
+        ));
+    }
+    let ip = match atyp {
+        ATYP_IPV4 => {
+            let mut octets = [0; 4];
+            control.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0; 16];
+            control.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        _ => return Err(malformed()),
+    };
+    let mut port = [0; 2];
+    control.read_exact(&mut port)?;
+    Ok(SocketAddr::new(ip, u16::from_be_bytes(port)))
+}
+
+/// Appends the SOCKS5 form of `addr` (ATYP, address, port) to `out`.
+fn write_addr(out: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(ATYP_IPV4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(ATYP_IPV6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Parses a UDP request header, returning the source address and the
+/// header's length, or `None` if it's malformed, a fragment, or names its
+/// source by domain.
+fn parse_udp_header(datagram: &[u8]) -> Option<(SocketAddr, usize)> {
+    let (&[_, _, frag, atyp], rest) = datagram.split_first_chunk::<4>()?;
+    if frag != 0 {
+        return None;
+    }
+    let (ip, rest): (IpAddr, _) = match atyp {
+        ATYP_IPV4 => {
+            let (octets, rest) = rest.split_first_chunk::<4>()?;
+            (Ipv4Addr::from(*octets).into(), rest)
+        }
+        ATYP_IPV6 => {
+            let (octets, rest) = rest.split_first_chunk::<16>()?;
+            (Ipv6Addr::from(*octets).into(), rest)
+        }
+        // Relays reply with the address they received from, never a domain.
+        _ => return None,
+    };
+    let (port, rest) = rest.split_first_chunk::<2>()?;
+    let addr = SocketAddr::new(ip, u16::from_be_bytes(*port));
+    Some((addr, datagram.len() - rest.len()))
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed reply from SOCKS5 proxy",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn_echo_server;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicBool;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    /// Runs a single-association SOCKS5 proxy that requires the given
+    /// credentials and relays `datagrams` datagrams from the client.
+    fn spawn_proxy(
+        user: &'static str,
+        password: &'static str,
+        datagrams: usize,
+    ) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut control, _) = listener.accept().unwrap();
+
+            let mut greeting = [0; 2];
+            control.read_exact(&mut greeting).unwrap();
+            let mut methods = vec![0; usize::from(greeting[1])];
+            control.read_exact(&mut methods).unwrap();
+            assert!(methods.contains(&METHOD_PASSWORD));
+            control.write_all(&[VERSION, METHOD_PASSWORD]).unwrap();
+
+            let mut head = [0; 2];
+            control.read_exact(&mut head).unwrap();
+            let mut got_user = vec![0; usize::from(head[1])];
+            control.read_exact(&mut got_user).unwrap();
+            let mut len = [0; 1];
+            control.read_exact(&mut len).unwrap();
+            let mut got_password = vec![0; usize::from(len[0])];
+            control.read_exact(&mut got_password).unwrap();
+            let ok = got_user == user.as_bytes() && got_password == password.as_bytes();
+            control.write_all(&[1, if ok { 0 } else { 1 }]).unwrap();
+            if !ok {
+                return;
+            }
+
+            // VER CMD RSV, then an IPv4 address and port.
+            let mut request = [0; 3 + 1 + 4 + 2];
+            control.read_exact(&mut request).unwrap();
+            assert_eq!(request[1], CMD_UDP_ASSOCIATE);
+
+            let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let mut reply = vec![VERSION, 0, 0];
+            write_addr(&mut reply, relay.local_addr().unwrap());
+            control.write_all(&reply).unwrap();
+
+            let mut buf = [0; 1500];
+            for _ in 0..datagrams {
+                let (n, client) = relay.recv_from(&mut buf).unwrap();
+                let (target, header_len) = parse_udp_header(&buf[..n]).unwrap();
+                relay.send_to(&buf[header_len..n], target).unwrap();
+
+                let (n, from) = relay.recv_from(&mut buf).unwrap();
+                let mut wrapped = vec![0, 0, 0];
+                write_addr(&mut wrapped, from);
+                wrapped.extend_from_slice(&buf[..n]);
+                relay.send_to(&wrapped, client).unwrap();
+            }
+        });
+        (proxy_addr, handle)
+    }
+
+    #[test]
+    fn round_trip_through_proxy() {
+        let (echo_addr, _echo) = spawn_echo_server().unwrap();
+        let (proxy_addr, proxy) = spawn_proxy("user", "secret", 1);
+
+        let conn = ConnectedUdpSocket::connect_via_socks5(
+            proxy_addr,
+            echo_addr,
+            Some(("user".to_string(), "secret".to_string())),
+        )
+        .unwrap();
+        assert_eq!(conn.peer_addr(), echo_addr);
+
+        assert_eq!(conn.send(b"ping").unwrap(), 4);
+        let mut buf = [0u8; 32];
+        let n = conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn helpers_receive_through_proxy() {
+        let (echo_addr, _echo) = spawn_echo_server().unwrap();
+        let (proxy_addr, proxy) = spawn_proxy("user", "secret", 2);
+
+        let conn = ConnectedUdpSocket::connect_via_socks5(
+            proxy_addr,
+            echo_addr,
+            Some(("user".to_string(), "secret".to_string())),
+        )
+        .unwrap();
+
+        let mut buf = [0u8; 32];
+        conn.send(b"ping").unwrap();
+        let n = conn.recv_verified(&mut buf, 0).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+
+        conn.send(b"pong").unwrap();
+        let cancel = AtomicBool::new(false);
+        let n = conn.recv_cancellable(&mut buf, &cancel).unwrap();
+        assert_eq!(n, Some(4));
+        assert_eq!(&buf[..4], b"pong");
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn connection_changes_are_refused() {
+        let (echo_addr, _echo) = spawn_echo_server().unwrap();
+        let (proxy_addr, proxy) = spawn_proxy("user", "secret", 0);
+
+        let mut conn = ConnectedUdpSocket::connect_via_socks5(
+            proxy_addr,
+            echo_addr,
+            Some(("user".to_string(), "secret".to_string())),
+        )
+        .unwrap();
+        let relay = conn.as_ref().peer_addr().unwrap();
+
+        let mut buf = [0u8; 32];
+        let mut uninit = [std::mem::MaybeUninit::uninit(); 32];
+        let errs = [
+            conn.recv_from_any(&mut buf).unwrap_err(),
+            conn.recv_tracking_peer(&mut buf).unwrap_err(),
+            conn.reconnect(echo_addr).unwrap_err(),
+            conn.recv_uninit(&mut uninit).unwrap_err(),
+            conn.monitor_liveness(b"ping".to_vec(), Duration::from_millis(10), 1, || {})
+                .unwrap_err(),
+        ];
+        for err in errs {
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+        #[cfg(target_os = "linux")]
+        for err in [
+            conn.send_gso(&[0; 64], 16).unwrap_err(),
+            conn.recv_gro(&mut buf).unwrap_err(),
+            conn.recv_with_drops(&mut buf).unwrap_err(),
+            conn.recv_with_ttl(&mut buf).unwrap_err(),
+            conn.recv_orig_dst(&mut buf).unwrap_err(),
+            conn.recv_checked_trunc(&mut buf).unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+        assert_eq!(conn.as_ref().peer_addr().unwrap(), relay);
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn recv_cancellable_cancels_after_foreign_datagram() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let control = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(relay.local_addr().unwrap()).unwrap();
+
+        let peer: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let mut header = vec![0, 0, 0];
+        write_addr(&mut header, peer);
+        let association = Socks5Association {
+            _control: control,
+            header,
+            peer,
+        };
+        let conn =
+            ConnectedUdpSocket::with_socks5(socket, peer, Options::default(), association).unwrap();
+
+        // The relay forwards a datagram from some other host, then nothing.
+        let mut foreign = vec![0, 0, 0];
+        write_addr(&mut foreign, "127.0.0.1:10".parse().unwrap());
+        foreign.extend_from_slice(b"spoof");
+        relay.send_to(&foreign, conn.local_addr().unwrap()).unwrap();
+
+        let cancel = std::sync::Arc::new(AtomicBool::new(false));
+        let canceller = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                cancel.store(true, std::sync::atomic::Ordering::Release);
+            })
+        };
+        let mut buf = [0u8; 32];
+        assert_eq!(conn.recv_cancellable(&mut buf, &cancel).unwrap(), None);
+        canceller.join().unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn from_std_refuses_relayed_socket() {
+        let (proxy_addr, proxy) = spawn_proxy("user", "secret", 0);
+
+        let conn = ConnectedUdpSocket::connect_via_socks5(
+            proxy_addr,
+            "127.0.0.1:9".parse().unwrap(),
+            Some(("user".to_string(), "secret".to_string())),
+        )
+        .unwrap();
+        let err = crate::AsyncConnectedUdpSocket::from_std(conn).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn rejected_credentials() {
+        let (proxy_addr, proxy) = spawn_proxy("user", "secret", 0);
+
+        let err = ConnectedUdpSocket::connect_via_socks5(
+            proxy_addr,
+            "127.0.0.1:9".parse().unwrap(),
+            Some(("user".to_string(), "wrong".to_string())),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn udp_header_round_trip() {
+        let addr: SocketAddr = "[2001:db8::1]:4433".parse().unwrap();
+        let mut datagram = vec![0, 0, 0];
+        write_addr(&mut datagram, addr);
+        datagram.extend_from_slice(b"payload");
+
+        assert_eq!(parse_udp_header(&datagram), Some((addr, MAX_HEADER_LEN)));
+        // Fragments aren't supported.
+        datagram[2] = 1;
+        assert_eq!(parse_udp_header(&datagram), None);
+    }
+}
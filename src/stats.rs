@@ -0,0 +1,191 @@
+use crate::ConnectedUdpSocket;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Traffic counters for a [`ConnectedUdpSocket`], as returned by
+/// [`ConnectedUdpSocket::stats`].
+///
+/// Only traffic that goes through the socket's own send and receive methods
+/// is counted, not anything sent or received on the underlying
+/// [`UdpSocket`](std::net::UdpSocket) directly.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectedUdpStats {
+    /// The number of datagrams sent to the peer.
+    pub datagrams_sent: u64,
+    /// The number of payload bytes sent to the peer.
+    pub bytes_sent: u64,
+    /// The number of datagrams received from the peer.
+    pub datagrams_received: u64,
+    /// The number of payload bytes received from the peer.
+    pub bytes_received: u64,
+}
+
+/// The live counters behind [`ConnectedUdpStats`], shared between a socket
+/// and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    datagrams_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    bytes_received: AtomicU64,
+    /// Called with the final counts once the last clone is dropped. Behind a
+    /// mutex only so the counters stay `Sync`.
+    on_drop: Mutex<Option<OnDrop>>,
+}
+
+impl Stats {
+    /// Records `datagrams` datagrams totalling `bytes` bytes being sent.
+    pub(crate) fn record_send(&self, datagrams: usize, bytes: usize) {
+        self.datagrams_sent
+            .fetch_add(datagrams as u64, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records `datagrams` datagrams totalling `bytes` bytes being received.
+    pub(crate) fn record_recv(&self, datagrams: usize, bytes: usize) {
+        self.datagrams_received
+            .fetch_add(datagrams as u64, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the current counts.
+    pub(crate) fn snapshot(&self) -> ConnectedUdpStats {
+        ConnectedUdpStats {
+            datagrams_sent: self.datagrams_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            datagrams_received: self.datagrams_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Sets the hook to call when the counters are dropped.
+    pub(crate) fn set_on_drop(&self, hook: OnDrop) {
+        *self.on_drop.lock().unwrap_or_else(PoisonError::into_inner) = Some(hook);
+    }
+}
+
+impl Drop for Stats {
+    fn drop(&mut self) {
+        let hook = self
+            .on_drop
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(OnDrop(hook)) = hook.take() {
+            hook(&self.snapshot());
+        }
+    }
+}
+
+/// A hook set with [`Builder::on_drop`](crate::Builder::on_drop).
+pub(crate) struct OnDrop(pub(crate) Box<dyn FnOnce(&ConnectedUdpStats) + Send>);
+
+impl fmt::Debug for OnDrop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnDrop").finish_non_exhaustive()
+    }
+}
+
+impl ConnectedUdpSocket {
+    /// Returns how much traffic the socket has sent and received.
+    ///
+    /// The counts cover the socket and all of its
+    /// [`try_clone`](Self::try_clone)s together.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  conn_client.send(b"ping").expect("couldn't send datagram");
+    ///
+    ///  let stats = conn_client.stats();
+    ///  assert_eq!(stats.datagrams_sent, 1);
+    ///  assert_eq!(stats.bytes_sent, 4);
+    /// # }
+    /// ```
+    pub fn stats(&self) -> ConnectedUdpStats {
+        self.stats_counters().snapshot()
+    }
+
+    /// Returns how long ago the socket was connected.
+    ///
+    /// Together with [`stats`](Self::stats), this gives average throughput.
+    /// A [`try_clone`](Self::try_clone) reports the same uptime as the
+    /// original.
+    ///
+    /// # Examples
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  conn_client.send(b"ping").expect("couldn't send datagram");
+    ///
+    ///  let secs = conn_client.uptime().as_secs_f64();
+    ///  println!("{:.1} bytes/s", conn_client.stats().bytes_sent as f64 / secs);
+    /// # }
+    /// ```
+    pub fn uptime(&self) -> Duration {
+        self.recv_clock().created().elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::thread;
+
+    #[test]
+    fn counts_sends_and_receives() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let clone = sender_conn.try_clone().unwrap();
+
+        sender_conn.send(b"ping").unwrap();
+        clone.send(b"pong!").unwrap();
+        receiver
+            .send_to(b"reply", sender_conn.local_addr().unwrap())
+            .unwrap();
+        let mut buf = [0u8; 8];
+        sender_conn.recv(&mut buf).unwrap();
+
+        let stats = clone.stats();
+        assert_eq!(stats.datagrams_sent, 2);
+        assert_eq!(stats.bytes_sent, 9);
+        assert_eq!(stats.datagrams_received, 1);
+        assert_eq!(stats.bytes_received, 5);
+    }
+
+    #[test]
+    fn uptime_increases() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let first = sender_conn.uptime();
+        thread::sleep(Duration::from_millis(20));
+        let second = sender_conn.try_clone().unwrap().uptime();
+        assert!(second >= first + Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,349 @@
+//! Platform-specific helpers that the standard library doesn't expose.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::ops::RangeInclusive;
+#[cfg(unix)]
+use std::os::fd::RawFd;
+use std::time::Duration;
+#[cfg(unix)]
+use std::{mem, ptr};
+
+/// The readiness a caller is waiting for in [`poll`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interest {
+    Readable,
+    Writable,
+}
+
+/// Waits up to `timeout` for `socket` to become ready for `interest`,
+/// returning whether it did. A `timeout` of `None` waits indefinitely.
+///
+/// No data is consumed from the socket.
+#[cfg(unix)]
+pub(crate) fn poll(
+    socket: &UdpSocket,
+    interest: Interest,
+    timeout: Option<Duration>,
+) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let events = match interest {
+        Interest::Readable => libc::POLLIN,
+        Interest::Writable => libc::POLLOUT,
+    };
+    let mut fd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events,
+        revents: 0,
+    };
+
+    // SAFETY: `fd` is a single valid `pollfd` that outlives the call.
+    let ret = unsafe { libc::poll(&mut fd, 1, timeout_ms(timeout)) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret > 0)
+}
+
+/// Waits up to `timeout` for `socket` to become ready for `interest`,
+/// returning whether it did. A `timeout` of `None` waits indefinitely.
+///
+/// No data is consumed from the socket.
+#[cfg(windows)]
+pub(crate) fn poll(
+    socket: &UdpSocket,
+    interest: Interest,
+    timeout: Option<Duration>,
+) -> io::Result<bool> {
+    use std::os::windows::io::AsRawSocket;
+    use windows_sys::Win32::Networking::WinSock::{
+        WSAGetLastError, WSAPoll, POLLRDNORM, POLLWRNORM, WSAPOLLFD,
+    };
+
+    let events = match interest {
+        Interest::Readable => POLLRDNORM,
+        Interest::Writable => POLLWRNORM,
+    };
+    let mut fd = WSAPOLLFD {
+        fd: socket.as_raw_socket() as _,
+        events,
+        revents: 0,
+    };
+
+    // SAFETY: `fd` is a single valid `WSAPOLLFD` that outlives the call.
+    let ret = unsafe { WSAPoll(&mut fd, 1, timeout_ms(timeout)) };
+    if ret < 0 {
+        // SAFETY: always safe to call after a failed winsock call.
+        return Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }));
+    }
+    Ok(ret > 0)
+}
+
+/// Dissolves `socket`'s OS-level connection, so it receives from (and can
+/// only `send_to`) any address. The socket keeps its local port.
+///
+/// Linux releases a port the OS picked when disconnecting, so it's claimed
+/// again right away, but another socket may bind it first. If so, the socket
+/// is connected back to its peer on a fresh port and the error returned.
+#[cfg(unix)]
+pub(crate) fn disconnect(socket: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let local = socket.local_addr()?;
+    let peer = socket.peer_addr()?;
+
+    // SAFETY: an all-zero `sockaddr` is valid.
+    let mut addr: libc::sockaddr = unsafe { mem::zeroed() };
+    addr.sa_family = libc::AF_UNSPEC as _;
+    // SAFETY: `addr` is a valid `sockaddr` of the given length.
+    let ret = unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            &addr,
+            mem::size_of::<libc::sockaddr>() as libc::socklen_t,
+        )
+    };
+    // The BSDs disconnect but still report `EAFNOSUPPORT`.
+    if ret < 0 && io::Error::last_os_error().raw_os_error() != Some(libc::EAFNOSUPPORT) {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Linux also releases a port that was picked automatically (by binding
+    // to port 0), so claim it again. The address it reports now reflects
+    // whether the IP was bound explicitly.
+    let unbound = socket.local_addr()?;
+    if unbound.port() == 0 {
+        reclaim_port(socket, SocketAddr::new(unbound.ip(), local.port()), peer)?;
+    }
+    Ok(())
+}
+
+/// Binds a socket that lost its port on disconnecting back to `local`, or
+/// failing that, connects it back to `peer`, which binds a fresh port.
+#[cfg(unix)]
+fn reclaim_port(socket: &UdpSocket, local: SocketAddr, peer: SocketAddr) -> io::Result<()> {
+    let res = socket2::SockRef::from(socket).bind(&local.into());
+    if res.is_err() {
+        let _ = socket.connect(peer);
+    }
+    res
+}
+
+/// Dissolves `socket`'s OS-level connection, so it receives from (and can
+/// only `send_to`) any address.
+#[cfg(windows)]
+pub(crate) fn disconnect(socket: &UdpSocket) -> io::Result<()> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    // Winsock disconnects a datagram socket "connected" to the all-zero
+    // address.
+    let unspecified: SocketAddr = if socket.local_addr()?.is_ipv6() {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    socket.connect(unspecified)
+}
+
+/// Returns the error the OS reports when a receive's timeout expires.
+#[cfg(unix)]
+pub(crate) fn recv_timed_out() -> io::Error {
+    io::Error::from_raw_os_error(libc::EAGAIN)
+}
+
+/// Returns the error the OS reports when a receive's timeout expires.
+#[cfg(windows)]
+pub(crate) fn recv_timed_out() -> io::Error {
+    use windows_sys::Win32::Networking::WinSock::WSAETIMEDOUT;
+
+    io::Error::from_raw_os_error(WSAETIMEDOUT)
+}
+
+/// Returns whether `socket` is in nonblocking mode.
+#[cfg(unix)]
+pub(crate) fn is_nonblocking(socket: &UdpSocket) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: `F_GETFL` only reads the descriptor's flags.
+    let flags = unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags & libc::O_NONBLOCK != 0)
+}
+
+/// Returns the kernel's current estimate of the path MTU to the connected
+/// peer (`IP_MTU` or `IPV6_MTU`).
+#[cfg(target_os = "linux")]
+pub(crate) fn path_mtu(socket: &UdpSocket) -> io::Result<usize> {
+    use socket2::{Domain, SockRef};
+    use std::os::fd::AsRawFd;
+
+    let (level, name) = if SockRef::from(socket).domain()? == Domain::IPV6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_MTU)
+    };
+    let mtu: libc::c_int = getsockopt(socket.as_raw_fd(), level, name)?;
+    Ok(mtu as usize)
+}
+
+/// Returns the kernel's current estimate of the path MTU to the connected
+/// peer.
+///
+/// Only Linux exposes this.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn path_mtu(_socket: &UdpSocket) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "querying the path MTU isn't supported on this platform",
+    ))
+}
+
+/// Sets the traffic class of outgoing IPv6 datagrams (`IPV6_TCLASS`).
+#[cfg(unix)]
+pub(crate) fn set_tclass_v6(socket: &UdpSocket, tc: u8) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    setsockopt(
+        socket.as_raw_fd(),
+        libc::IPPROTO_IPV6,
+        libc::IPV6_TCLASS,
+        libc::c_int::from(tc),
+    )
+}
+
+/// Sets the traffic class of outgoing IPv6 datagrams.
+///
+/// Winsock doesn't let applications set `IPV6_TCLASS`.
+#[cfg(windows)]
+pub(crate) fn set_tclass_v6(_socket: &UdpSocket, _tc: u8) -> io::Result<()> {
+    Err(tclass_unsupported())
+}
+
+/// Returns the traffic class of outgoing IPv6 datagrams (`IPV6_TCLASS`).
+#[cfg(unix)]
+pub(crate) fn tclass_v6(socket: &UdpSocket) -> io::Result<u8> {
+    use std::os::fd::AsRawFd;
+
+    let tc: libc::c_int = getsockopt(socket.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_TCLASS)?;
+    Ok(tc as u8)
+}
+
+/// Returns the traffic class of outgoing IPv6 datagrams.
+///
+/// Winsock doesn't let applications set `IPV6_TCLASS`.
+#[cfg(windows)]
+pub(crate) fn tclass_v6(_socket: &UdpSocket) -> io::Result<u8> {
+    Err(tclass_unsupported())
+}
+
+#[cfg(windows)]
+fn tclass_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "IPv6 traffic classes aren't supported on this platform",
+    )
+}
+
+/// Returns the range the OS assigns ephemeral ports from.
+#[cfg(target_os = "linux")]
+pub(crate) fn ephemeral_port_range() -> io::Result<RangeInclusive<u16>> {
+    let range = std::fs::read_to_string("/proc/sys/net/ipv4/ip_local_port_range")?;
+    let mut ports = range.split_whitespace().map(str::parse::<u16>);
+    match (ports.next(), ports.next()) {
+        (Some(Ok(low)), Some(Ok(high))) => Ok(low..=high),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed ephemeral port range",
+        )),
+    }
+}
+
+/// Returns the range the OS assigns ephemeral ports from.
+///
+/// Assumes the IANA dynamic range, which is the default outside Linux.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn ephemeral_port_range() -> io::Result<RangeInclusive<u16>> {
+    Ok(49152..=65535)
+}
+
+/// Sets a socket option of type `T`.
+#[cfg(unix)]
+pub(crate) fn setsockopt<T: Copy>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: T,
+) -> io::Result<()> {
+    // SAFETY: `value` is a valid buffer of `size_of::<T>()` bytes.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            ptr::addr_of!(value).cast(),
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads a socket option of type `T`.
+#[cfg(unix)]
+pub(crate) fn getsockopt<T: Copy>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+) -> io::Result<T> {
+    // SAFETY: socket options are plain integers or structs, for which all-zero
+    // is a valid bit pattern.
+    let mut value: T = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<T>() as libc::socklen_t;
+
+    // SAFETY: `value` and `len` describe a valid, writable buffer.
+    let ret =
+        unsafe { libc::getsockopt(fd, level, name, ptr::addr_of_mut!(value).cast(), &mut len) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Converts an optional timeout to the millisecond form `poll` expects,
+/// rounding up so short non-zero timeouts don't turn into busy loops.
+fn timeout_ms(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        None => -1,
+        Some(timeout) => {
+            let ms = timeout.as_nanos().div_ceil(1_000_000);
+            i32::try_from(ms).unwrap_or(i32::MAX)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reclaim_port_falls_back_to_reconnecting() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        // Stands in for another socket binding the released port first.
+        let thief = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let taken = thief.local_addr().unwrap();
+
+        let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None)
+            .map(UdpSocket::from)
+            .unwrap();
+        let err = reclaim_port(&socket, taken, peer_addr).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(socket.peer_addr().unwrap(), peer_addr);
+        assert_ne!(socket.local_addr().unwrap().port(), 0);
+    }
+}
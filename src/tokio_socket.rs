@@ -0,0 +1,237 @@
+use crate::connected_udp::{resolve_peer, xor_with_key, Options};
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+/// A connected UDP socket for the `tokio` runtime.
+///
+/// This is the async counterpart of [`ConnectedUdpSocket`], wrapping a
+/// [`tokio::net::UdpSocket`]. It's connected the same way, so peers are
+/// validated and canonicalized just like for the blocking socket.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::AsyncConnectedUdpSocket;
+/// use tokio::net::UdpSocket;
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0").await?;
+///     let host_addr = host.local_addr()?;
+///
+///     let client = UdpSocket::bind("127.0.0.1:0").await?;
+///     let conn_client = AsyncConnectedUdpSocket::connect(client, host_addr)?;
+///     conn_client.send(b"ping").await?;
+///
+///     let mut buf = [0; 32];
+///     let n = host.recv(&mut buf).await?;
+///     assert_eq!(&buf[..n], b"ping");
+///
+///     Ok(())
+/// }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+#[derive(Debug)]
+pub struct AsyncConnectedUdpSocket {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    options: Options,
+}
+
+impl AsyncConnectedUdpSocket {
+    /// Connects `socket` to `peer`.
+    ///
+    /// Must be called from within a `tokio` runtime.
+    ///
+    /// # Errors
+    ///
+    /// Fails in the same cases as [`ConnectedUdpSocket::connect`].
+    pub fn connect(socket: UdpSocket, peer: SocketAddr) -> io::Result<Self> {
+        Self::from_std(ConnectedUdpSocket::connect(socket.into_std()?, peer)?)
+    }
+
+    /// Moves a blocking [`ConnectedUdpSocket`] onto the `tokio` runtime.
+    ///
+    /// The socket is switched to nonblocking mode. Settings that stay with
+    /// the socket, such as [`Builder::obfuscate`](crate::Builder::obfuscate),
+    /// carry over. Must be called from within a `tokio` runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] for a socket
+    /// that relays through SOCKS5, since the async socket can't keep the
+    /// association alive.
+    pub fn from_std(conn: ConnectedUdpSocket) -> io::Result<Self> {
+        conn.ensure_direct()?;
+        let (socket, peer, options) = conn.into_parts();
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: UdpSocket::from_std(socket)?,
+            peer,
+            options,
+        })
+    }
+
+    /// Connects the socket to a new `peer`, replacing the current one.
+    ///
+    /// The cached peer is only updated if connecting succeeds.
+    ///
+    /// # Errors
+    ///
+    /// Fails for the same reasons as [`connect`](Self::connect).
+    pub async fn reconnect(&mut self, peer: SocketAddr) -> io::Result<()> {
+        let (os_peer, peer) = resolve_peer(self.socket.local_addr()?, peer, &self.options)?;
+        self.socket.connect(os_peer).await?;
+        self.peer = peer;
+        Ok(())
+    }
+
+    /// Returns the socket address of the remote peer this socket was
+    /// connected to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Returns the socket address that this socket was created from.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sends `buf` to the peer as a single datagram, returning the number of
+    /// bytes sent.
+    ///
+    /// The payload is obfuscated if the socket was built with a key.
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(key) = &self.options.obfuscation_key {
+            let mut scrambled = buf.to_vec();
+            xor_with_key(&mut scrambled, key);
+            return self.socket.send(&scrambled).await;
+        }
+        self.socket.send(buf).await
+    }
+
+    /// Receives a single datagram from the peer into `buf`, returning the
+    /// number of bytes read.
+    ///
+    /// Any obfuscation is reversed.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.socket.recv(buf).await?;
+        if let Some(key) = &self.options.obfuscation_key {
+            xor_with_key(&mut buf[..n], key);
+        }
+        Ok(n)
+    }
+
+    /// Waits until a datagram can be received, without consuming it.
+    ///
+    /// This lets a caller decide whether (and where) to receive only once
+    /// there is something to receive, e.g. as one branch of
+    /// `tokio::select!`. The readiness can be spurious, so a following
+    /// receive may still need to wait.
+    pub async fn readable(&self) -> io::Result<()> {
+        self.socket.readable().await
+    }
+}
+
+impl AsRef<UdpSocket> for AsyncConnectedUdpSocket {
+    /// Returns the underlying [`tokio::net::UdpSocket`].
+    fn as_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn readable_after_datagram_queued() {
+        let host = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let host_addr = host.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let conn_client = AsyncConnectedUdpSocket::connect(client, host_addr).unwrap();
+
+        host.send_to(b"ping", conn_client.local_addr().unwrap())
+            .await
+            .unwrap();
+        conn_client.readable().await.unwrap();
+
+        // Readiness doesn't consume the datagram.
+        let mut buf = [0u8; 8];
+        let n = conn_client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+    }
+
+    #[tokio::test]
+    async fn reconnect_switches_peer() {
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let second_addr = second.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut conn_client =
+            AsyncConnectedUdpSocket::connect(client, first.local_addr().unwrap()).unwrap();
+
+        conn_client.reconnect(second_addr).await.unwrap();
+        assert_eq!(conn_client.peer_addr(), second_addr);
+
+        conn_client.send(b"moved").await.unwrap();
+        let mut buf = [0u8; 8];
+        let n = second.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"moved");
+    }
+
+    #[tokio::test]
+    async fn failed_reconnect_keeps_peer() {
+        let host = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let host_addr = host.local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut conn_client = AsyncConnectedUdpSocket::connect(client, host_addr).unwrap();
+
+        assert!(conn_client
+            .reconnect("0.0.0.0:0".parse().unwrap())
+            .await
+            .is_err());
+        assert_eq!(conn_client.peer_addr(), host_addr);
+    }
+
+    #[tokio::test]
+    async fn from_std_keeps_obfuscation() {
+        let a = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b_addr = b.local_addr().unwrap();
+        let key = b"lab key".to_vec();
+
+        let a_conn = crate::Builder::new()
+            .obfuscate(key.clone())
+            .connect(a, b_addr)
+            .unwrap();
+        let a_addr = a_conn.local_addr().unwrap();
+        let b_conn = crate::Builder::new()
+            .obfuscate(key)
+            .connect(b, a_addr)
+            .unwrap();
+        let a_async = AsyncConnectedUdpSocket::from_std(a_conn).unwrap();
+
+        a_async.send(b"ping").await.unwrap();
+        let mut buf = [0u8; 8];
+        let n = b_conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+
+        b_conn.send(b"pong").unwrap();
+        let n = a_async.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong");
+    }
+
+    #[tokio::test]
+    async fn connect_rejects_unspecified_peer() {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let err =
+            AsyncConnectedUdpSocket::connect(client, "0.0.0.0:0".parse().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::net::SocketAddr;
+
+/// A connection-oriented transport that exchanges messages with a single
+/// remote peer.
+///
+/// This lets code be generic over the kind of transport it talks through,
+/// whether that's a [`ConnectedUdpSocket`] or something else entirely. The
+/// trait is object safe, so it can be used as `&dyn Transport` or
+/// `Box<dyn Transport>`.
+///
+/// # Examples
+///
+/// ```
+/// use connected_udp::{ConnectedUdpSocket, Transport};
+/// use std::net::UdpSocket;
+///
+/// fn ping(transport: &dyn Transport) -> std::io::Result<usize> {
+///     transport.send(b"ping")
+/// }
+///
+/// fn main() -> std::io::Result<()> {
+///     let host = UdpSocket::bind("127.0.0.1:0")?;
+///     let client = UdpSocket::bind("127.0.0.1:0")?;
+///     let conn_client = ConnectedUdpSocket::connect(client, host.local_addr()?)?;
+///
+///     let transport: Box<dyn Transport> = Box::new(conn_client);
+///     ping(&*transport)?;
+///
+///     Ok(())
+/// }
+/// ```
+pub trait Transport {
+    /// Sends a message to the peer, returning the number of bytes sent.
+    fn send(&self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Receives a message from the peer into `buf`, returning the number of
+    /// bytes read.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Returns the address of the remote peer.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for ConnectedUdpSocket {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        ConnectedUdpSocket::send(self, buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        ConnectedUdpSocket::recv(self, buf)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(ConnectedUdpSocket::peer_addr(self))
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        (**self).send(buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).recv(buf)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        (**self).peer_addr()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        (**self).send(buf)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).recv(buf)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        (**self).peer_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn send_recv_through_dyn_transport() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        let a_conn = ConnectedUdpSocket::connect(a, b_addr).unwrap();
+        let b_conn: Box<dyn Transport> = Box::new(ConnectedUdpSocket::connect(b, a_addr).unwrap());
+
+        let a_transport: &dyn Transport = &a_conn;
+        assert_eq!(a_transport.peer_addr().unwrap(), b_addr);
+        assert_eq!(a_transport.send(b"ping").unwrap(), 4);
+
+        let mut buf = [0u8; 32];
+        let n = b_conn.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert_eq!(b_conn.peer_addr().unwrap(), a_addr);
+    }
+}
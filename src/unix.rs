@@ -0,0 +1,218 @@
+//! Unix-specific extensions to [`ConnectedUdpSocket`].
+
+use crate::connected_udp::{canonicalize, Options};
+use crate::sys::{getsockopt, setsockopt};
+use crate::{ConnectedUdpSocket, Error};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+
+impl ConnectedUdpSocket {
+    /// Sets the receive low-water mark (`SO_RCVLOWAT`), the minimum number of
+    /// queued bytes before a blocking receive or readiness poll wakes up.
+    ///
+    /// This option has limited effect on datagram sockets: most kernels
+    /// (including Linux) deliver a datagram as soon as one is queued
+    /// regardless of the low-water mark, and some reject values other than
+    /// `1` outright. It's mainly useful for portable code that batches on
+    /// platforms that honor it.
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn set_recv_lowat(&self, bytes: usize) -> io::Result<()> {
+        let bytes = libc::c_int::try_from(bytes).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "low-water mark is too large")
+        })?;
+        setsockopt(
+            self.as_ref().as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVLOWAT,
+            bytes,
+        )
+    }
+
+    /// Returns the receive low-water mark (`SO_RCVLOWAT`).
+    ///
+    /// See [`set_recv_lowat`](Self::set_recv_lowat) for details.
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn recv_lowat(&self) -> io::Result<usize> {
+        let bytes: libc::c_int = getsockopt(
+            self.as_ref().as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVLOWAT,
+        )?;
+        Ok(bytes as usize)
+    }
+
+    /// Duplicates the underlying file descriptor without `FD_CLOEXEC`, so the
+    /// duplicate survives `exec` and can be handed to a child process.
+    ///
+    /// The original descriptor is unaffected. The duplicate shares the same
+    /// socket, including its connected peer and options.
+    ///
+    /// # Security
+    ///
+    /// Every program this process `exec`s while the returned descriptor is
+    /// open inherits it, not just the intended child. A program that
+    /// inherits it can send to and receive from the peer as this process
+    /// would. Keep the duplicate's lifetime as short as possible: create it
+    /// right before spawning the child and drop it right after, and avoid
+    /// calling this while other threads may be spawning unrelated processes.
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn dup_for_exec(&self) -> io::Result<OwnedFd> {
+        // SAFETY: `dup` only reads the descriptor. The duplicate it returns
+        // never has `FD_CLOEXEC` set.
+        let fd = unsafe { libc::dup(self.as_ref().as_raw_fd()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a freshly created descriptor that nothing else owns.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Borrows the underlying file descriptor, e.g. to pass to C code.
+    ///
+    /// The socket keeps ownership: the borrow can't outlive it, and the
+    /// descriptor stays open until the socket is dropped. This is safer than
+    /// handing out a bare [`RawFd`](std::os::fd::RawFd), which nothing ties
+    /// to the socket's lifetime.
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn borrow_fd(&self) -> BorrowedFd<'_> {
+        self.as_ref().as_fd()
+    }
+
+    /// Decomposes the socket into its file descriptor and cached peer, for
+    /// handing the connection to another process.
+    ///
+    /// The descriptor can be passed over a Unix socket with `SCM_RIGHTS`,
+    /// e.g. during a zero-downtime upgrade, along with the peer, and the
+    /// receiving process rebuilds the socket with
+    /// [`from_handoff`](Self::from_handoff). Settings chosen through the
+    /// [`Builder`](crate::Builder) aren't carried over.
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn into_handoff(self) -> (OwnedFd, SocketAddr) {
+        let (socket, peer) = self.into();
+        (socket.into(), peer)
+    }
+
+    /// Rebuilds a socket handed off by [`into_handoff`](Self::into_handoff).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotConnected`] if `fd`
+    /// isn't a connected socket, and [`Error::PeerMismatch`] (reported as
+    /// [`io::ErrorKind::Other`]) if it's connected to an address other than
+    /// `peer`.
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn from_handoff(fd: OwnedFd, peer: SocketAddr) -> io::Result<Self> {
+        let conn = Self::try_from(UdpSocket::from(fd))?;
+        let expected = canonicalize(peer, &Options::default());
+        if conn.peer_addr() != expected {
+            return Err(Error::PeerMismatch {
+                expected,
+                actual: conn.peer_addr(),
+            }
+            .into());
+        }
+        Ok(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recv_lowat_round_trip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        // Not every kernel lets the mark be changed on a datagram socket.
+        if sender_conn.set_recv_lowat(128).is_err() {
+            return;
+        }
+        assert_eq!(sender_conn.recv_lowat().unwrap(), 128);
+    }
+
+    #[test]
+    fn borrow_fd_matches_raw_fd() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let fd = sender_conn.borrow_fd();
+        assert_eq!(fd.as_raw_fd(), sender_conn.as_ref().as_raw_fd());
+    }
+
+    #[test]
+    fn handoff_round_trip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        let local = sender_conn.local_addr().unwrap();
+
+        let (fd, peer) = sender_conn.into_handoff();
+        assert_eq!(peer, recv_addr);
+        let sender_conn = ConnectedUdpSocket::from_handoff(fd, peer).unwrap();
+        assert_eq!(sender_conn.peer_addr(), recv_addr);
+        assert_eq!(sender_conn.local_addr().unwrap(), local);
+
+        sender_conn.send(b"hi").unwrap();
+        let mut buf = [0; 2];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 2);
+    }
+
+    #[test]
+    fn from_handoff_peer_mismatch_error() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other_addr = other.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (fd, _) = ConnectedUdpSocket::connect(sender, recv_addr)
+            .unwrap()
+            .into_handoff();
+        let err = ConnectedUdpSocket::from_handoff(fd, other_addr).unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+            Some(&Error::PeerMismatch {
+                expected: other_addr,
+                actual: recv_addr,
+            })
+        );
+    }
+
+    #[test]
+    fn dup_for_exec_clears_cloexec() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let fd = sender_conn.dup_for_exec().unwrap();
+        assert_ne!(fd.as_raw_fd(), sender_conn.as_ref().as_raw_fd());
+
+        // SAFETY: `F_GETFD` only reads the descriptor's flags.
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+        assert!(flags >= 0);
+        assert_eq!(flags & libc::FD_CLOEXEC, 0);
+
+        // The original descriptor keeps its close-on-exec flag.
+        // SAFETY: as above.
+        let flags = unsafe { libc::fcntl(sender_conn.as_ref().as_raw_fd(), libc::F_GETFD) };
+        assert_ne!(flags & libc::FD_CLOEXEC, 0);
+
+        // The duplicate refers to the same connected socket.
+        let dup = UdpSocket::from(fd);
+        dup.send(b"hi").unwrap();
+        let mut buf = [0; 2];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 2);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::ConnectedUdpSocket;
+use socket2::SockRef;
+use std::io;
+use std::ops::Deref;
+
+impl ConnectedUdpSocket {
+    /// Returns a view exposing IPv6-only socket options, or `None` if this
+    /// isn't an IPv6 socket.
+    ///
+    /// Options that only exist for IPv6 sockets live on the view rather than
+    /// on [`ConnectedUdpSocket`] itself, so calling one on an IPv4 socket is
+    /// a compile error rather than a runtime [`io::ErrorKind::InvalidInput`].
+    /// A dual-stack IPv6 socket connected to an IPv4 peer still has a view.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  assert!(conn_client.as_v6().is_none());
+    /// # }
+    /// ```
+    pub fn as_v6(&self) -> Option<ConnectedUdpSocketV6View<'_>> {
+        match self.as_ref().local_addr() {
+            Ok(local) if local.is_ipv6() => Some(ConnectedUdpSocketV6View { conn: self }),
+            _ => None,
+        }
+    }
+}
+
+/// A view of an IPv6 [`ConnectedUdpSocket`] with access to IPv6-only socket
+/// options, as returned by [`ConnectedUdpSocket::as_v6`].
+///
+/// The view dereferences to the socket, so the rest of its methods are
+/// available too. The IPv6-only options aren't callable on the socket
+/// directly:
+///
+/// ```compile_fail
+/// # use connected_udp::ConnectedUdpSocket;
+/// # use std::net::UdpSocket;
+/// # fn main() {
+///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+///  let conn_client = ConnectedUdpSocket::connect(client, host.local_addr().unwrap())
+///      .expect("couldn't client to host");
+///
+///  conn_client.set_unicast_hops(16);
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectedUdpSocketV6View<'a> {
+    conn: &'a ConnectedUdpSocket,
+}
+
+impl ConnectedUdpSocketV6View<'_> {
+    /// Sets the hop limit for outgoing unicast datagrams
+    /// (`IPV6_UNICAST_HOPS`), the IPv6 counterpart of the TTL.
+    pub fn set_unicast_hops(&self, hops: u32) -> io::Result<()> {
+        SockRef::from(self.conn.as_ref()).set_unicast_hops_v6(hops)
+    }
+
+    /// Returns the hop limit for outgoing unicast datagrams
+    /// (`IPV6_UNICAST_HOPS`).
+    pub fn unicast_hops(&self) -> io::Result<u32> {
+        SockRef::from(self.conn.as_ref()).unicast_hops_v6()
+    }
+
+    /// Returns whether the socket is restricted to IPv6 traffic
+    /// (`IPV6_V6ONLY`), i.e. whether it can't talk to IPv4 peers through
+    /// IPv4-mapped addresses.
+    pub fn only_v6(&self) -> io::Result<bool> {
+        SockRef::from(self.conn.as_ref()).only_v6()
+    }
+}
+
+impl Deref for ConnectedUdpSocketV6View<'_> {
+    type Target = ConnectedUdpSocket;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn as_v6_none_for_ipv4_socket() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+        assert!(sender_conn.as_v6().is_none());
+    }
+
+    #[test]
+    fn as_v6_unicast_hops_round_trip() {
+        // Not every environment has IPv6 loopback.
+        let Ok(receiver) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("[::1]:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let v6 = sender_conn.as_v6().unwrap();
+        v6.set_unicast_hops(16).unwrap();
+        assert_eq!(v6.unicast_hops().unwrap(), 16);
+        assert_eq!(v6.peer_addr(), recv_addr);
+    }
+}
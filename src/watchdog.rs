@@ -0,0 +1,198 @@
+use crate::ConnectedUdpSocket;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the watchdog thread sleeps between checks.
+const POLL_SLICE: Duration = Duration::from_millis(10);
+
+/// When a socket last received a datagram, shared between the socket, its
+/// clones, and any watchdogs watching it.
+#[derive(Debug)]
+pub(crate) struct RecvClock {
+    origin: Instant,
+    /// Nanoseconds from `origin` to the last receive.
+    last: AtomicU64,
+}
+
+impl RecvClock {
+    /// Creates a clock that reports `now` as the last receive, so idleness
+    /// is measured from the socket's creation.
+    pub(crate) fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a receive happening now.
+    pub(crate) fn touch(&self) {
+        let nanos = u64::try_from(self.origin.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.last.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns when the socket was created.
+    pub(crate) fn created(&self) -> Instant {
+        self.origin
+    }
+
+    /// Returns when the last receive happened.
+    fn last(&self) -> Instant {
+        self.origin + Duration::from_nanos(self.last.load(Ordering::Relaxed))
+    }
+}
+
+impl ConnectedUdpSocket {
+    /// Spawns a thread that calls `on_idle` whenever no datagram has been
+    /// received for `idle`.
+    ///
+    /// Datagrams count once they've been received through this socket (or a
+    /// [`try_clone`](Self::try_clone) of it) by any of its receive methods;
+    /// the watchdog doesn't read from the socket itself. Idleness is measured
+    /// from when the socket was connected.
+    ///
+    /// `on_idle` is given a clone of the socket, so it can send a probe or
+    /// notify whoever owns the socket to reconnect. After it's called, the
+    /// watchdog waits another full `idle` window before calling it again.
+    /// The watchdog runs until the returned [`WatchdogHandle`] is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use connected_udp::ConnectedUdpSocket;
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() {
+    ///  let host = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to host address");
+    ///  let host_addr = host.local_addr().expect("couldn't retrieve host address");
+    ///
+    ///  let client = UdpSocket::bind("127.0.0.1:0").expect("couldn't bind to client address");
+    ///  let conn_client = ConnectedUdpSocket::connect(client, host_addr).expect("couldn't client to host");
+    ///
+    ///  let watchdog = conn_client
+    ///      .spawn_watchdog(Duration::from_secs(30), |conn| {
+    ///          let _ = conn.send(b"are you there?");
+    ///      })
+    ///      .expect("couldn't start watchdog");
+    ///  drop(watchdog);
+    /// # }
+    /// ```
+    pub fn spawn_watchdog(
+        &self,
+        idle: Duration,
+        on_idle: impl Fn(&ConnectedUdpSocket) + Send + 'static,
+    ) -> io::Result<WatchdogHandle> {
+        let conn = self.try_clone()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || watch(&conn, idle, &stop, on_idle))
+        };
+
+        Ok(WatchdogHandle {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A handle to a watchdog started by
+/// [`ConnectedUdpSocket::spawn_watchdog`].
+///
+/// Dropping the handle stops the watchdog.
+#[derive(Debug)]
+pub struct WatchdogHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatchdogHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Calls `on_idle` each time `conn` goes `idle` without receiving, until
+/// `stop` is set.
+fn watch(
+    conn: &ConnectedUdpSocket,
+    idle: Duration,
+    stop: &AtomicBool,
+    on_idle: impl Fn(&ConnectedUdpSocket),
+) {
+    let mut last_fired = None;
+    while !stop.load(Ordering::Acquire) {
+        let quiet_since = match last_fired {
+            Some(fired) => conn.recv_clock().last().max(fired),
+            None => conn.recv_clock().last(),
+        };
+        let now = Instant::now();
+        if now.duration_since(quiet_since) >= idle {
+            on_idle(conn);
+            last_fired = Some(now);
+            continue;
+        }
+        thread::sleep(POLL_SLICE.min(idle - now.duration_since(quiet_since)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn watchdog_fires_when_idle() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_conn = ConnectedUdpSocket::connect(sender, recv_addr).unwrap();
+
+        let fired = Arc::new(AtomicUsize::new(0));
+        let watchdog = {
+            let fired = Arc::clone(&fired);
+            sender_conn
+                .spawn_watchdog(Duration::from_millis(30), move |_| {
+                    fired.fetch_add(1, Ordering::Relaxed);
+                })
+                .unwrap()
+        };
+        thread::sleep(Duration::from_millis(200));
+        drop(watchdog);
+        assert!(fired.load(Ordering::Relaxed) >= 1);
+    }
+
+    #[test]
+    fn watchdog_quiet_while_traffic_flows() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let a_addr = a.local_addr().unwrap();
+        let b_conn = ConnectedUdpSocket::connect(b, a_addr).unwrap();
+        a.connect(b_conn.local_addr().unwrap()).unwrap();
+
+        let fired = Arc::new(AtomicUsize::new(0));
+        let watchdog = {
+            let fired = Arc::clone(&fired);
+            b_conn
+                .spawn_watchdog(Duration::from_millis(300), move |_| {
+                    fired.fetch_add(1, Ordering::Relaxed);
+                })
+                .unwrap()
+        };
+        let mut buf = [0u8; 4];
+        for _ in 0..30 {
+            a.send(b"tick").unwrap();
+            b_conn.recv(&mut buf).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        drop(watchdog);
+        assert_eq!(fired.load(Ordering::Relaxed), 0);
+    }
+}